@@ -0,0 +1,24 @@
+use std::path::Path;
+use std::process::Command;
+
+// Record the commit the binary was built from for the run manifest; builds outside a
+// git checkout (e.g. from a crates.io tarball) report "unknown"
+fn main() {
+    let git_hash = Command::new("git")
+        .args(["describe", "--always", "--dirty", "--abbrev=40"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=ROUTE_RS_GIT_HASH={}", git_hash);
+
+    // The index changes on commit and checkout, and src on edits that make the tree dirty
+    for path in [".git/HEAD", ".git/index", "src"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
@@ -1,24 +1,477 @@
+use route_rs::config::{
+    ChannelParams, Confluence, EXTERNAL_TIMESTEP_SECONDS, FeatureOrder, ForcingLayout,
+    GeometryCheck, NonconvergenceFallback, OutputSplit, OutputVariable, ProgressMode,
+    SecantBracket, TimeFormat,
+};
 use anyhow::{Context, Result};
-use clap::{Parser, command};
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 /// Network routing simulation tool
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Route directory path
-    route_dir: PathBuf,
+    #[arg(required = true)]
+    pub route_dir: Option<PathBuf>,
 
     /// Internal timestep in seconds
     #[arg(short, long, default_value_t = 3600)]
-    internal_timestep_seconds: usize,
+    pub internal_timestep_seconds: usize,
+
+    /// Output timestep in seconds, a multiple of the internal timestep [default: 3600]
+    #[arg(long)]
+    pub output_timestep: Option<usize>,
+
+    /// Split the NetCDF output into one file per time chunk
+    #[arg(long, value_enum, default_value_t = OutputSplit::None)]
+    pub split_output_by: OutputSplit,
+
+    /// Regex used to extract numeric ids; the first capture group is the id. The default,
+    /// ^\D*?0*(\d+)$, must match the whole id, so wb-12a is rejected rather than read as 12
+    #[arg(long)]
+    pub id_pattern: Option<String>,
+
+    /// CSV mapping feature_id to group_id; writes summed group outlet flows to a separate file
+    #[arg(long)]
+    pub aggregate_map: Option<PathBuf>,
+
+    /// Also write flow divided by cumulative upstream drainage area
+    #[arg(long)]
+    pub emit_specific_discharge: bool,
+
+    /// Also write each reach's cumulative upstream drainage area
+    #[arg(long)]
+    pub emit_drainage_area: bool,
+
+    /// How lateral inflow forcing is laid out; long and wide read a single --forcing-file
+    #[arg(long, value_enum, default_value_t = ForcingLayout::PerFile)]
+    pub forcing_layout: ForcingLayout,
+
+    /// Forcing CSV for the long and wide layouts
+    #[arg(long)]
+    pub forcing_file: Option<PathBuf>,
+
+    /// CSV of feature_id,weight giving the share of its catchment's runoff each listed
+    /// reach receives, for catchments split across several flowpaths. Unlisted reaches get 1
+    #[arg(long)]
+    pub lateral_weights: Option<PathBuf>,
+
+    /// CSV of feature_id,type setting how listed reaches are routed: channel, waterbody,
+    /// junction or passthrough. Overrides --reach-type-column; unlisted reaches are channels.
+    /// Waterbodies have no reservoir model, so like junctions their inflow passes through
+    #[arg(long)]
+    pub reach_types: Option<PathBuf>,
+
+    /// Column of the gpkg `flowpath-attributes` table holding each reach's type
+    #[arg(long)]
+    pub reach_type_column: Option<String>,
+
+    /// Route the network once per forcing directory (ensemble members), reusing the
+    /// topology and parameters, and write the series with a leading `member` dimension.
+    /// Replaces the route directory's outputs/ngen; members must share a start and length
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "DIRS",
+        conflicts_with_all = [
+            "forcing_file", "emit_iterations", "emit_travel_time", "aggregate_map",
+            "stats_file", "timings_file", "verify_output", "resume_output", "results_cache",
+        ]
+    )]
+    pub forcing_members: Vec<PathBuf>,
+
+    /// Read --forcing-file for at most this many reaches at a time, as they become ready to
+    /// route, instead of loading it all up front. Each window is one pass over the file.
+    #[arg(long, value_name = "REACHES", requires = "forcing_file")]
+    pub forcing_window: Option<usize>,
+
+    /// Read every cat-<id>.csv forcing file up front, one thread per core, instead of as each
+    /// reach is routed. Holds all forcing in memory; faster on spinning disks
+    #[arg(long, conflicts_with_all = ["forcing_file", "forcing_members"])]
+    pub preload_forcing: bool,
+
+    /// Also write each reach's mean and maximum solver iterations per timestep
+    #[arg(long)]
+    pub emit_iterations: bool,
+
+    /// Also write each reach's mean travel time (dx/ck, seconds) over the timesteps it
+    /// carries flow, as a `travel_time` variable
+    #[arg(long)]
+    pub emit_travel_time: bool,
+
+    /// Also write the kernel's Muskingum weighting X and kinematic celerity at every
+    /// timestep, as `musk_x` and `celerity` variables. With flow, X is clamped to
+    /// [0.25, 0.5], so values at either bound show where the weighting left that range
+    #[arg(long)]
+    pub emit_muskingum: bool,
+
+    /// Also write each reach's routed outflow volume (m3) since the first output time, as
+    /// a `cumulative_volume` series. Flow is integrated with the trapezoidal rule over every
+    /// internal timestep, so the volume between output times isn't lost to --output-timestep
+    #[arg(long)]
+    pub emit_cumulative_volume: bool,
+
+    /// Drop the first forcing row, for products whose first row is the t=0 state rather
+    /// than the flux over the first hour. Without it, row k is the flux over hour k (from 0)
+    #[arg(long)]
+    pub skip_first_forcing: bool,
+
+    /// Route the whole forcing record this many times first, carrying channel state into
+    /// the next cycle, and only output the final cycle
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub spinup_cycles: usize,
+
+    /// Keep routing this many forcing steps past the end of the forcing with zero lateral
+    /// inflow, so the network drains; the output time axis grows to match
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub recession_steps: usize,
+
+    /// Route each independent basin whole on one worker thread rather than scheduling
+    /// reach by reach. Faster for domains of many small basins; one large basin routes serially
+    #[arg(long)]
+    pub by_component: bool,
+
+    /// Read channel parameters from this CSV instead of the gpkg `flowpath-attributes` table
+    #[arg(long)]
+    pub attributes_csv: Option<PathBuf>,
+
+    /// Route even if some headwater reaches have no forcing data (they produce zero flow)
+    #[arg(long)]
+    pub allow_dry_reaches: bool,
+
+    /// Fail on anything that would otherwise be a warning (missing parameters, zero slopes,
+    /// dry or failed reaches, ragged forcing, ...). Routing stops at the first reach that fails.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Channel used for reaches with no parameters, e.g. "dx=1000,n=0.06,s0=0.001,bw=5,tw=10,twcc=20,ncc=0.1,cs=2".
+    /// Also fills NULL columns in the gpkg's flowpath-attributes; without it, such reaches have no parameters
+    #[arg(long)]
+    pub default_channel: Option<ChannelParams>,
+
+    /// Format of forcing timestamps and the NetCDF time units: auto (detects ISO 8601),
+    /// iso, ngen ("%Y-%m-%d %H:%M:%S"), compact ("%Y%m%d%H%M") or a strftime pattern
+    #[arg(long, default_value = "auto")]
+    pub time_format: TimeFormat,
+
+    /// Snap the forcing start time onto the nearest whole forcing timestep when it's within
+    /// this many seconds of it, so float-clock times like 00:59:59.999 line up with the grid.
+    /// By default the start time is used as written
+    #[arg(long, default_value_t = 0.0)]
+    pub time_epsilon: f64,
+
+    /// Treat all flow as in-channel, ignoring the compound (overbank) channel.
+    /// Diagnostic only: overbank depth comes from the trapezoid extended upward, not the floodplain
+    #[arg(long)]
+    pub no_compound: bool,
+
+    /// Route reaches with a bed slope above this threshold with the kinematic-wave kernel
+    #[arg(long)]
+    pub kinematic_above_slope: Option<f32>,
+
+    /// Pass inflow straight through, unattenuated, on reaches shorter than this many meters
+    #[arg(long)]
+    pub passthrough_below_length: Option<f32>,
+
+    /// Pass inflow straight through, unattenuated, on these reaches, e.g. `12,4057`
+    #[arg(long, value_delimiter = ',')]
+    pub passthrough_ids: Vec<u64>,
+
+    /// Order of the features in the output: ascending id, upstream before downstream
+    /// (ties by id), or the row order of the gpkg flowpaths table
+    #[arg(long, value_enum, default_value_t = FeatureOrder::Id)]
+    pub feature_order: FeatureOrder,
+
+    /// Warn about every reach whose routed outflow goes negative
+    #[arg(long)]
+    pub check_nonnegative: bool,
+
+    /// Raise routed outflow to at least this many m3/s after the kernel, on every reach,
+    /// and carry the raised flow downstream. Each reach's count of raised timesteps is
+    /// written as `floored_steps`, so the floor isn't mistaken for baseflow
+    #[arg(long, value_name = "M3_PER_S")]
+    pub min_flow: Option<f32>,
+
+    /// Stop the run, naming the reach and timestep, as soon as any reach's routed outflow
+    /// exceeds this many m3/s or isn't finite, a sign of bad data or a numerical blowup
+    #[arg(long, value_name = "M3_PER_S", conflicts_with = "replay")]
+    pub abort_above_flow: Option<f32>,
+
+    /// How upstream reaches' outflow is combined at a downstream reach: summed as is, or
+    /// each series first delayed by its reach's mean Muskingum travel time
+    #[arg(long, value_enum, default_value_t = Confluence::Sum)]
+    pub confluence: Confluence,
+
+    /// List every reach timestep whose velocity exceeds this many m/s, usually a sign of
+    /// bad slope or geometry data, in --velocity-report with the reach's slope and length
+    #[arg(long, value_name = "M_PER_S", conflicts_with = "replay")]
+    pub flag_velocity_above: Option<f32>,
+
+    /// CSV that --flag-velocity-above writes its report to
+    #[arg(long, default_value = "velocity_qa.csv")]
+    pub velocity_report: PathBuf,
+
+    /// Significant digits kept for values in CSV reports, with trailing zeros dropped. Only
+    /// the text is rounded; routing and the NetCDF output keep full precision
+    #[arg(long, value_name = "N", default_value_t = 6)]
+    pub float_precision: usize,
+
+    /// Write each reach's flowpath line with its peak routed flow as a `peak_flow`
+    /// property to this GeoJSON file (requires building with `--features geometry`)
+    #[arg(long, value_name = "PATH", conflicts_with = "replay")]
+    pub peak_flow_geojson: Option<PathBuf>,
+
+    /// Reaches whose bottom width is at least their top width: keep them as given (the
+    /// kernel's fixups effectively disable their overbank flow), clamp the top width to
+    /// --tw-clamp-factor times the bottom width, or error
+    #[arg(long, value_enum, default_value_t = GeometryCheck::Keep)]
+    pub geometry_check: GeometryCheck,
+
+    /// Top width as a multiple of bottom width for --geometry-check clamp
+    #[arg(long, default_value_t = 1.5)]
+    pub tw_clamp_factor: f32,
+
+    /// What to use when the Muskingum-Cunge depth solve doesn't converge
+    #[arg(long, value_enum, default_value_t = NonconvergenceFallback::Last)]
+    pub nonconvergence_fallback: NonconvergenceFallback,
+
+    /// Depth the Muskingum-Cunge secant solve is bracketed around: the previous timestep's,
+    /// or the normal depth of the current inflow (fewer iterations on flashy sub-hourly
+    /// hydrographs). The solve stops on step size, so flows shift with the bracket; compare
+    /// runs made with the same one
+    #[arg(long, value_enum, default_value_t = SecantBracket::PreviousDepth)]
+    pub secant_bracket: SecantBracket,
+
+    /// Multiplier on the bracket depth for the upper secant point, and for widening it on retry
+    #[arg(long, default_value_t = 1.33)]
+    pub bracket_upper: f32,
+
+    /// Multiplier on the bracket depth for the lower secant point, and for narrowing it on retry
+    #[arg(long, default_value_t = 0.67)]
+    pub bracket_lower: f32,
+
+    /// Cap on a reach's secant iterations over the whole run. Once spent, its remaining
+    /// timesteps skip the solve, take normal flow, and count as nonconverged, bounding the
+    /// worst-case runtime of pathological reaches
+    #[arg(long)]
+    pub max_reach_iterations: Option<u64>,
+
+    /// CSV of time-varying Manning's n with columns feature_id,step,n and optionally ncc;
+    /// each row applies from that forcing step until the reach's next row. Ids are read
+    /// with --id-pattern
+    #[arg(long)]
+    pub roughness_file: Option<PathBuf>,
+
+    /// NetCDF file of observed flows used to nudge gauged reaches
+    #[arg(long)]
+    pub nudge: Option<PathBuf>,
+
+    /// e-folding time in seconds over which a nudge fades once observations stop
+    #[arg(long, default_value_t = 3600.0, requires = "nudge")]
+    pub nudge_decay: f32,
+
+    /// Write min/max/mean/percentile summaries of the routed variables to this JSON file
+    #[arg(long)]
+    pub stats_file: Option<PathBuf>,
+
+    /// Write each reach's routing wall time and kernel iterations to this CSV, slowest first
+    #[arg(long)]
+    pub timings_file: Option<PathBuf>,
+
+    /// Retry a failed output write this many times, backing off from 100 ms and doubling,
+    /// before the reach is reported as unwritten; for flaky networked storage
+    #[arg(long, default_value_t = 3)]
+    pub write_retries: u32,
+
+    /// Where to write the JSON record of the run's inputs, effective options, build, and
+    /// policy decisions (floored slopes, skipped reaches)
+    #[arg(long, default_value = "run_manifest.json")]
+    pub run_manifest: PathBuf,
+
+    /// Fill value for missing output, either a number or "nan"
+    #[arg(long, default_value = "-9999.0", allow_hyphen_values = true, value_parser = parse_fill_value)]
+    pub fill_value: f32,
+
+    /// Routed variables to write, e.g. `flow,depth`
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = OutputVariable::ALL)]
+    pub variables: Vec<OutputVariable>,
+
+    /// Write the NWM CHRTOUT layout (`streamflow`, epoch-based times, CF station
+    /// attributes, `type` and `nudge`) so output diffs directly against NWM files
+    #[arg(long)]
+    pub nwm_compat: bool,
+
+    /// Progress reporting: an interactive bar or periodic JSON lines
+    #[arg(long, value_enum, default_value_t = ProgressMode::Bar)]
+    pub progress: ProgressMode,
+
+    /// Don't report progress at all
+    #[arg(short, long, conflicts_with = "progress")]
+    pub quiet: bool,
+
+    /// Periodically print resident memory during routing, and the peak at the end
+    /// (requires building with `--features memory-stats`)
+    #[arg(long)]
+    pub report_memory: bool,
+
+    /// Reopen the output once routing finishes and check it is complete and readable
+    #[arg(long)]
+    pub verify_output: bool,
+
+    /// Fill in the features missing from this existing output file instead of starting a new one.
+    /// The file must have been written with the same inputs and routing options.
+    #[arg(long)]
+    pub resume_output: Option<PathBuf>,
+
+    /// Cache each node's routed results in this directory for later --replay
+    #[arg(long)]
+    pub results_cache: Option<PathBuf>,
+
+    /// Skip routing and regenerate output from --results-cache
+    #[arg(long, requires = "results_cache")]
+    pub replay: bool,
 }
 
-pub fn get_args() -> Result<(PathBuf, PathBuf, PathBuf, usize)> {
-    let args = Args::parse();
+// Utilities that run instead of a simulation
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the gpkg tables and columns route_rs reads
+    Schema {
+        /// Check this gpkg for the expected tables and columns
+        #[arg(long)]
+        gpkg: Option<PathBuf>,
+
+        /// Regex used to extract numeric ids; the first capture group is the id
+        #[arg(long)]
+        id_pattern: Option<String>,
+    },
+    /// Build the network from a gpkg and summarise it without routing
+    Topology {
+        /// Hydrofabric gpkg to read
+        #[arg(long)]
+        gpkg: PathBuf,
+
+        /// Print every outlet with the number of reaches and drainage area above it
+        #[arg(long)]
+        list_outlets: bool,
+
+        /// Regex used to extract numeric ids; the first capture group is the id
+        #[arg(long)]
+        id_pattern: Option<String>,
+    },
+    /// Cross-check the reaches in flowpaths against the parameters in flowpath-attributes
+    Validate {
+        /// Hydrofabric gpkg to check
+        #[arg(long)]
+        gpkg: PathBuf,
+
+        /// Directory for flowpaths_without_attributes.csv and attributes_without_flowpaths.csv
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+
+        /// Also report how many reaches have their cat-<id>.csv in this forcing directory,
+        /// writing reaches_without_forcing.csv and forcing_without_reaches.csv
+        #[arg(long)]
+        forcing_dir: Option<PathBuf>,
+    },
+    /// Compare two output NetCDF files feature by feature, failing when any value differs
+    /// beyond the tolerance
+    Compare {
+        /// Output file to check
+        a: PathBuf,
+
+        /// Reference output file
+        b: PathBuf,
+
+        /// Absolute difference allowed, in each variable's units
+        #[arg(long, default_value_t = 0.0)]
+        abs_tolerance: f64,
+
+        /// Relative difference allowed; a value passes within either tolerance
+        #[arg(long, default_value_t = 1e-6)]
+        rel_tolerance: f64,
+
+        /// Number of most divergent features to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+}
+
+pub fn get_args(args: Args) -> Result<(PathBuf, PathBuf, PathBuf, Args)> {
+    validate_internal_timestep(args.internal_timestep_seconds)?;
+    if let Some(output_timestep) = args.output_timestep {
+        validate_output_timestep(output_timestep, args.internal_timestep_seconds)?;
+    }
+    match (args.forcing_layout, &args.forcing_file) {
+        (ForcingLayout::PerFile, Some(_)) => {
+            return Err(anyhow::anyhow!("--forcing-file needs --forcing-layout long or wide"));
+        }
+        (ForcingLayout::Long | ForcingLayout::Wide, None) => {
+            return Err(anyhow::anyhow!("--forcing-layout long and wide need a --forcing-file"));
+        }
+        _ => {}
+    }
+    if args.tw_clamp_factor <= 1.0 {
+        return Err(anyhow::anyhow!("--tw-clamp-factor must be above 1"));
+    }
+    if args.recession_steps > 0 && args.spinup_cycles > 0 {
+        return Err(anyhow::anyhow!(
+            "--recession-steps can't be combined with --spinup-cycles, which would drain the network between cycles"
+        ));
+    }
+    if args
+        .flag_velocity_above
+        .is_some_and(|threshold| threshold.is_nan() || threshold <= 0.0)
+    {
+        return Err(anyhow::anyhow!("--flag-velocity-above must be positive"));
+    }
+    if args.peak_flow_geojson.is_some() && !cfg!(feature = "geometry") {
+        return Err(anyhow::anyhow!(
+            "--peak-flow-geojson needs route_rs built with --features geometry"
+        ));
+    }
+    if args
+        .min_flow
+        .is_some_and(|floor| !floor.is_finite() || floor <= 0.0)
+    {
+        return Err(anyhow::anyhow!("--min-flow must be positive"));
+    }
+    if args
+        .abort_above_flow
+        .is_some_and(|ceiling| !ceiling.is_finite() || ceiling <= 0.0)
+    {
+        return Err(anyhow::anyhow!("--abort-above-flow must be positive"));
+    }
+    if !args.time_epsilon.is_finite() || args.time_epsilon < 0.0 {
+        return Err(anyhow::anyhow!("--time-epsilon must be zero or positive"));
+    }
+    if args.forcing_window == Some(0) {
+        return Err(anyhow::anyhow!("--forcing-window must be at least 1 reach"));
+    }
+    if !(args.bracket_upper > 1.0 && (0.0..1.0).contains(&args.bracket_lower)) {
+        return Err(anyhow::anyhow!(
+            "--bracket-upper must be above 1 and --bracket-lower in [0, 1)"
+        ));
+    }
     
-    let root_dir = args.route_dir;
-    let csv_dir = root_dir.join("outputs").join("ngen");
+    let root_dir = args
+        .route_dir
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No route directory given"))?;
+    let csv_dir = match args.forcing_members.first() {
+        Some(first_member) => first_member.clone(),
+        None => root_dir.join("outputs").join("ngen"),
+    };
     let config_dir = root_dir.join("config");
     
     // Find the .gpkg file in the config directory
@@ -28,7 +481,7 @@ pub fn get_args() -> Result<(PathBuf, PathBuf, PathBuf, usize)> {
         .filter_map(Result::ok)
         .find(|entry| {
             entry.path().extension()
-                .map_or(false, |ext| ext == "gpkg")
+                .is_some_and(|ext| ext == "gpkg")
         })
         .ok_or_else(|| anyhow::anyhow!("No .gpkg file found in config directory"))?
         .path();
@@ -37,6 +490,74 @@ pub fn get_args() -> Result<(PathBuf, PathBuf, PathBuf, usize)> {
         config_dir,
         csv_dir,
         gpkg_file,
-        args.internal_timestep_seconds,
+        args,
     ))
-}
\ No newline at end of file
+}
+
+// Accepts any finite number or "nan" (case-insensitive)
+fn parse_fill_value(value: &str) -> Result<f32, String> {
+    let fill_value: f32 = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{}' is not a number or 'nan'", value))?;
+    if fill_value.is_infinite() {
+        return Err("Fill value must be finite or 'nan'".to_string());
+    }
+    Ok(fill_value)
+}
+
+// The internal timestep must evenly subdivide the external forcing timestep
+fn validate_internal_timestep(internal_timestep_seconds: usize) -> Result<()> {
+    if internal_timestep_seconds == 0 {
+        return Err(anyhow::anyhow!("Internal timestep must be greater than zero"));
+    }
+    if !EXTERNAL_TIMESTEP_SECONDS.is_multiple_of(internal_timestep_seconds) {
+        return Err(anyhow::anyhow!(
+            "Internal timestep of {} seconds does not evenly divide the external timestep of {} seconds",
+            internal_timestep_seconds,
+            EXTERNAL_TIMESTEP_SECONDS
+        ));
+    }
+    Ok(())
+}
+
+// Output is sampled from the internal steps, so its timestep must be a whole number of them
+fn validate_output_timestep(
+    output_timestep_seconds: usize,
+    internal_timestep_seconds: usize,
+) -> Result<()> {
+    if output_timestep_seconds == 0
+        || !output_timestep_seconds.is_multiple_of(internal_timestep_seconds)
+    {
+        return Err(anyhow::anyhow!(
+            "Output timestep of {} seconds is not a multiple of the internal timestep of {} seconds",
+            output_timestep_seconds,
+            internal_timestep_seconds
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_timestep_must_divide_external_timestep() {
+        assert!(validate_internal_timestep(300).is_ok());
+        assert!(validate_internal_timestep(3600).is_ok());
+        assert!(validate_internal_timestep(0).is_err());
+
+        let error = validate_internal_timestep(700).unwrap_err().to_string();
+        assert!(error.contains("700"));
+        assert!(error.contains("3600"));
+    }
+
+    #[test]
+    fn output_timestep_must_be_a_multiple_of_internal_timestep() {
+        assert!(validate_output_timestep(900, 300).is_ok());
+        assert!(validate_output_timestep(5400, 300).is_ok());
+        assert!(validate_output_timestep(1000, 300).is_err());
+        assert!(validate_output_timestep(0, 300).is_err());
+    }
+}
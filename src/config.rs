@@ -1,3 +1,10 @@
+use chrono::NaiveDateTime;
+use chrono::format::{Item, StrftimeItems};
+use std::collections::{HashMap, HashSet};
+
+// Timestep of the external (lateral inflow) forcing in seconds
+pub const EXTERNAL_TIMESTEP_SECONDS: usize = 3600;
+
 // Configuration structure for column name mapping
 #[derive(Debug, Clone)]
 pub struct ColumnConfig {
@@ -11,6 +18,8 @@ pub struct ColumnConfig {
     pub tw: String,
     pub twcc: String,
     pub cs: String,
+    // Regex applied to raw ids; the first capture group is the numeric id
+    pub id_pattern: String,
 }
 
 impl Default for ColumnConfig {
@@ -32,19 +41,380 @@ impl ColumnConfig {
             tw: "TopWdth".to_string(),
             twcc: "TopWdthCC".to_string(),
             cs: "ChSlp".to_string(),
+            id_pattern: r"^\D*?0*(\d+)$".to_string(),
         }
     }
+
+    // Columns read from each gpkg table
+    pub fn expected_columns(&self) -> [(&'static str, Vec<&str>); 2] {
+        [
+            ("flowpaths", vec![&self.key, &self.downstream, "areasqkm"]),
+            (
+                "flowpath-attributes",
+                vec![
+                    &self.key, &self.dx, &self.n, &self.ncc, &self.s0, &self.bw, &self.tw,
+                    &self.twcc, &self.cs,
+                ],
+            ),
+        ]
+    }
 }
 
-// Output format configuration
+// Output format configuration. main always writes NetCDF for now.
 #[derive(Debug, Clone)]
 pub enum OutputFormat {
+    #[allow(dead_code)]
     Csv,
     NetCdf,
+    #[allow(dead_code)]
     Both,
 }
 
+// How lateral inflow forcing is laid out on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum ForcingLayout {
+    // One `cat-{id}.csv` per catchment in outputs/ngen
+    PerFile,
+    // A single CSV with a `Time,ID,Q_OUT` row per catchment and timestep
+    Long,
+    // A single CSV with a time column and one Q_OUT column per catchment
+    Wide,
+}
+
+// How the NetCDF output is split into files along the time axis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputSplit {
+    None,
+    Day,
+    Month,
+}
+
+// How routing progress is reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressMode {
+    // Interactive terminal progress bar
+    Bar,
+    // Periodic `{"done":N,"total":M,"eta_s":S}` lines for log files and job schedulers
+    Json,
+}
+
+// Routed variables that can be selected for output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputVariable {
+    Flow,
+    Velocity,
+    Depth,
+}
+
+impl OutputVariable {
+    pub const ALL: [OutputVariable; 3] = [
+        OutputVariable::Flow,
+        OutputVariable::Velocity,
+        OutputVariable::Depth,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputVariable::Flow => "flow",
+            OutputVariable::Velocity => "velocity",
+            OutputVariable::Depth => "depth",
+        }
+    }
+}
+
+// Timestamp format for forcing times and the NetCDF time units
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    // Recognise ISO 8601 and the other built-in formats; write `%Y-%m-%d %H:%M:%S`
+    #[default]
+    Auto,
+    // A strftime pattern, used for both reading and writing
+    Pattern(String),
+}
+
+impl TimeFormat {
+    // Named formats accepted by --time-format, tried in this order when auto-detecting
+    pub const BUILTIN: [(&'static str, &'static str); 3] = [
+        ("iso", "%Y-%m-%dT%H:%M:%S"),
+        ("ngen", "%Y-%m-%d %H:%M:%S"),
+        ("compact", "%Y%m%d%H%M"),
+    ];
+
+    pub fn parse(&self, value: &str) -> Result<NaiveDateTime, String> {
+        let value = value.trim();
+        match self {
+            TimeFormat::Pattern(pattern) => NaiveDateTime::parse_from_str(value, pattern)
+                .map_err(|e| format!("'{}' doesn't match '{}': {}", value, pattern, e)),
+            TimeFormat::Auto => {
+                // ISO 8601 with an offset, e.g. 2020-01-01T00:00:00Z, is taken as UTC
+                if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+                    return Ok(time.naive_utc());
+                }
+                [
+                    "%Y-%m-%dT%H:%M:%S%.f",
+                    "%Y-%m-%d %H:%M:%S%.f",
+                    "%Y-%m-%dT%H:%M",
+                    "%Y-%m-%d %H:%M",
+                    "%Y%m%d%H%M",
+                ]
+                .iter()
+                .find_map(|pattern| NaiveDateTime::parse_from_str(value, pattern).ok())
+                .ok_or_else(|| format!("'{}' isn't a recognised timestamp", value))
+            }
+        }
+    }
+
+    pub fn format(&self, time: &NaiveDateTime) -> String {
+        match self {
+            TimeFormat::Pattern(pattern) => time.format(pattern).to_string(),
+            TimeFormat::Auto => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        }
+    }
+}
+
+// `auto`, a built-in name, or a strftime pattern
+impl std::str::FromStr for TimeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(TimeFormat::Auto);
+        }
+        if let Some((_, pattern)) = TimeFormat::BUILTIN.iter().find(|(name, _)| *name == s) {
+            return Ok(TimeFormat::Pattern(pattern.to_string()));
+        }
+        if !s.contains('%') || StrftimeItems::new(s).any(|item| item == Item::Error) {
+            return Err(format!(
+                "Invalid time format '{}', expected auto, {} or a strftime pattern",
+                s,
+                TimeFormat::BUILTIN.map(|(name, _)| name).join(", ")
+            ));
+        }
+        Ok(TimeFormat::Pattern(s.to_string()))
+    }
+}
+
+// Optional output variables written alongside flow/velocity/depth
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    // Which of flow/velocity/depth are written
+    pub variables: Vec<OutputVariable>,
+    pub specific_discharge: bool,
+    pub drainage_area: bool,
+    // Per-reach mean and maximum solver iterations per timestep
+    pub iterations: bool,
+    // Per-reach mean travel time (dx/ck) over the timesteps with flow
+    pub travel_time: bool,
+    // The kernel's Muskingum weighting X and kinematic celerity at every timestep
+    pub muskingum: bool,
+    // Routed outflow volume accumulated since the start of the output cycle
+    pub cumulative_volume: bool,
+    // Per-reach count of timesteps whose outflow was raised to the minimum flow
+    pub floored_steps: bool,
+    // _FillValue/missing_value for every output variable, may be NaN
+    pub fill_value: f32,
+    // Write the NWM CHRTOUT schema: `streamflow`, epoch-based times, CF station
+    // attributes, and the `type`/`nudge` variables
+    pub nwm_compat: bool,
+    // Write the flow correction applied by nudging
+    pub nudge: bool,
+    // Write fill values at timesteps where the kernel didn't converge
+    pub fill_nonconverged: bool,
+    // Reaches routed as passthrough; flagged in a `passthrough` variable when non-empty
+    pub passthrough: HashSet<u64>,
+    // Reaches written as waterbodies (1) rather than channels (0) in the NWM `type` variable
+    pub waterbodies: HashSet<u64>,
+    // Forcing directories routed into a leading `member` dimension; empty for a single run
+    pub members: Vec<String>,
+    // Internal timesteps per output timestep: output step j is internal step j * output_stride
+    pub output_stride: usize,
+    // Format of the reference time in the `time` units attribute
+    pub time_format: TimeFormat,
+    // Kernel settings and internal timestep, recorded in global attributes
+    pub solver: SolverConfig,
+    pub internal_timestep_seconds: usize,
+    // Hash of the inputs and routing options, checked before a later run resumes the file
+    pub input_hash: Option<String>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            variables: OutputVariable::ALL.to_vec(),
+            specific_discharge: false,
+            drainage_area: false,
+            iterations: false,
+            muskingum: false,
+            travel_time: false,
+            cumulative_volume: false,
+            floored_steps: false,
+            fill_value: -9999.0,
+            nwm_compat: false,
+            nudge: false,
+            fill_nonconverged: false,
+            passthrough: HashSet::new(),
+            waterbodies: HashSet::new(),
+            members: Vec::new(),
+            output_stride: 1,
+            time_format: TimeFormat::Auto,
+            solver: SolverConfig::default(),
+            internal_timestep_seconds: 3600,
+            input_hash: None,
+        }
+    }
+}
+
+impl OutputOptions {
+    // NWM CHRTOUT lays series out (time, feature_id); otherwise they're (feature_id, time)
+    pub fn time_major(&self) -> bool {
+        self.nwm_compat
+    }
+
+    // Names of the series variables written for every feature
+    pub fn series_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = self
+            .variables
+            .iter()
+            .map(|&variable| self.variable_name(variable))
+            .collect();
+        if self.specific_discharge {
+            names.push("specific_discharge");
+        }
+        if self.writes_nudge() {
+            names.push("nudge");
+        }
+        if self.muskingum {
+            names.extend(["musk_x", "celerity"]);
+        }
+        if self.cumulative_volume {
+            names.push("cumulative_volume");
+        }
+        names
+    }
+
+    // NetCDF name of a routed variable; flow is `streamflow` in the NWM layout
+    pub fn variable_name(&self, variable: OutputVariable) -> &'static str {
+        match variable {
+            OutputVariable::Flow if self.nwm_compat => "streamflow",
+            _ => variable.name(),
+        }
+    }
+
+    // NWM files always carry `nudge`, which is zero where nothing was nudged
+    pub fn writes_nudge(&self) -> bool {
+        self.nudge || self.nwm_compat
+    }
+}
+
+// What the kernel returns when the depth solve doesn't converge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum NonconvergenceFallback {
+    // Keep the last secant iterate
+    Last,
+    // Steady Manning normal flow: outflow equals inflow at normal depth
+    NormalFlow,
+    // Route on with normal flow but write fill values for those timesteps
+    Fill,
+}
+
+// What to do with reaches whose bottom width is at least their top width, which the
+// kernel otherwise handles with fixups (bw > tw effectively disables overbank flow)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum GeometryCheck {
+    // Report how many there are and route them as they are
+    Keep,
+    // Widen the top width to a multiple of the bottom width
+    Clamp,
+    // Refuse to route
+    Error,
+}
+
+// How upstream reaches' outflow is combined into a downstream reach's inflow
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Confluence {
+    // Add the series timestep by timestep, as if every tributary arrives at once
+    #[default]
+    Sum,
+    // Delay each upstream series by its reach's mean travel time (dx/ck) first
+    Lagged,
+}
+
+// How a reach is routed. Read per reach from a gpkg column or an override file;
+// reaches that aren't listed are channels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum ReachType {
+    // Muskingum-Cunge, or kinematic wave above --kinematic-above-slope
+    #[default]
+    Channel,
+    // Lake or reservoir. There is no reservoir model, so inflow passes through
+    // unattenuated; the reach is flagged as a waterbody in the NWM `type` variable
+    Waterbody,
+    // Zero-length connector where flowpaths meet; inflow passes through
+    Junction,
+    // Inflow passes straight through, as with --passthrough-ids
+    Passthrough,
+}
+
+impl ReachType {
+    // Whether the reach's inflow is routed through a channel kernel
+    pub fn is_routed(self) -> bool {
+        self == ReachType::Channel
+    }
+}
+
+// Order of the features along the output's feature_id dimension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum FeatureOrder {
+    // Upstream reaches before the reaches they drain to, ties broken by ascending id
+    Topo,
+    // Ascending id
+    Id,
+    // The row order of the gpkg `flowpaths` table
+    Database,
+}
+
+// Depth the secant solve's initial bracket is scaled from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum SecantBracket {
+    // The previous timestep's depth, as in t-route
+    PreviousDepth,
+    // In-channel normal depth of the current upstream plus lateral inflow, which tracks
+    // rapidly rising hydrographs that the previous depth lags
+    Inflow,
+}
+
+// Numerical configuration for the Muskingum-Cunge kernel
+#[derive(Debug, Clone)]
+pub struct SolverConfig {
+    // When false all flow is treated as in-channel, ignoring twcc/ncc. This is a
+    // diagnostic mode: overbank depth comes from the trapezoid extended upward.
+    pub compound_channel: bool,
+    // Reaches steeper than this slope use the kinematic-wave kernel instead
+    pub kinematic_above_slope: Option<f32>,
+    pub nonconvergence_fallback: NonconvergenceFallback,
+    // The secant solve starts from `bracket_upper` and `bracket_lower` times the `bracket`
+    // depth, and widens by the same factors on each retry
+    pub bracket: SecantBracket,
+    pub bracket_upper: f32,
+    pub bracket_lower: f32,
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        SolverConfig {
+            compound_channel: true,
+            kinematic_above_slope: None,
+            nonconvergence_fallback: NonconvergenceFallback::Last,
+            bracket: SecantBracket::PreviousDepth,
+            bracket_upper: 1.33,
+            bracket_lower: 0.67,
+        }
+    }
+}
+
 // Channel parameters from SQLite
+// Stored as f32, the precision the kernel computes in, so they're passed to
+// submuskingcunge as-is with no widening or narrowing
 #[derive(Debug, Clone)]
 pub struct ChannelParams {
     pub dx: f32,
@@ -55,4 +425,133 @@ pub struct ChannelParams {
     pub tw: f32,
     pub twcc: f32,
     pub cs: f32,
-}
\ No newline at end of file
+}
+
+// Parse `dx=1000,n=0.06,...`; every field must be given exactly once
+impl std::str::FromStr for ChannelParams {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut values: HashMap<&str, f32> = HashMap::new();
+        for pair in s.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value, got '{}'", pair))?;
+            let key = key.trim();
+            if !CHANNEL_PARAM_KEYS.contains(&key) {
+                return Err(format!(
+                    "Unknown channel parameter '{}', expected one of {}",
+                    key,
+                    CHANNEL_PARAM_KEYS.join(", ")
+                ));
+            }
+            let value: f32 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid value '{}' for {}", value.trim(), key))?;
+            if values.insert(key, value).is_some() {
+                return Err(format!("Channel parameter '{}' given more than once", key));
+            }
+        }
+
+        ChannelParams::from_values(&values)
+    }
+}
+
+impl ChannelParams {
+    // Build from a value for each of dx, n, ncc, s0, bw, tw, twcc and cs
+    pub fn from_values(values: &HashMap<&str, f32>) -> Result<Self, String> {
+        if let Some(key) = values.keys().find(|key| !CHANNEL_PARAM_KEYS.contains(key)) {
+            return Err(format!(
+                "Unknown channel parameter '{}', expected one of {}",
+                key,
+                CHANNEL_PARAM_KEYS.join(", ")
+            ));
+        }
+        let missing: Vec<&str> = CHANNEL_PARAM_KEYS
+            .iter()
+            .copied()
+            .filter(|key| !values.contains_key(key))
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Missing channel parameters: {}",
+                missing.join(", ")
+            ));
+        }
+        // Muskingum-Cunge divides by the reach length
+        if values["dx"].is_nan() || values["dx"] <= 0.0 {
+            return Err(format!("dx must be positive, got {}", values["dx"]));
+        }
+
+        Ok(ChannelParams {
+            dx: values["dx"],
+            n: values["n"],
+            ncc: values["ncc"],
+            s0: values["s0"],
+            bw: values["bw"],
+            tw: values["tw"],
+            twcc: values["twcc"],
+            cs: values["cs"],
+        })
+    }
+}
+
+const CHANNEL_PARAM_KEYS: [&str; 8] = ["dx", "n", "ncc", "s0", "bw", "tw", "twcc", "cs"];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_default_channel() {
+        let params: ChannelParams = "dx=1000,n=0.06,s0=0.001,bw=5,tw=10,twcc=20,ncc=0.1,cs=2"
+            .parse()
+            .unwrap();
+        assert_eq!(params.dx, 1000.0);
+        assert_eq!(params.ncc, 0.1);
+        assert_eq!(params.cs, 2.0);
+
+        assert!("dx=1000,n=0.06".parse::<ChannelParams>().unwrap_err().contains("s0"));
+        assert!("dx=1000,dx=5".parse::<ChannelParams>().is_err());
+        assert!("width=5".parse::<ChannelParams>().is_err());
+        assert!("dx=abc".parse::<ChannelParams>().is_err());
+        let zero_length = "dx=0,n=0.06,s0=0.001,bw=5,tw=10,twcc=20,ncc=0.1,cs=2";
+        assert!(zero_length.parse::<ChannelParams>().unwrap_err().contains("dx"));
+    }
+
+    #[test]
+    fn time_format_reads_iso_and_named_formats() {
+        let expected =
+            NaiveDateTime::parse_from_str("2020-06-01 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        for value in [
+            "2020-06-01T12:00:00",
+            "2020-06-01 12:00:00",
+            "2020-06-01T12:00:00Z",
+        ] {
+            assert_eq!(TimeFormat::Auto.parse(value).unwrap(), expected);
+        }
+        assert!(TimeFormat::Auto.parse("0").is_err());
+
+        let compact: TimeFormat = "compact".parse().unwrap();
+        assert_eq!(compact.parse("202006011200").unwrap(), expected);
+        assert_eq!(compact.format(&expected), "202006011200");
+
+        let custom: TimeFormat = "%d/%m/%Y %H:%M".parse().unwrap();
+        assert_eq!(custom.parse("01/06/2020 12:00").unwrap(), expected);
+        assert!("%Q".parse::<TimeFormat>().is_err());
+        assert!("daily".parse::<TimeFormat>().is_err());
+    }
+
+    #[test]
+    fn nwm_layout_renames_flow_and_always_writes_nudge() {
+        let mut options = OutputOptions::default();
+        assert_eq!(options.series_names(), vec!["flow", "velocity", "depth"]);
+
+        options.nwm_compat = true;
+        assert_eq!(
+            options.series_names(),
+            vec!["streamflow", "velocity", "depth", "nudge"]
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use indicatif::ProgressBar;
+use std::sync::RwLock;
+
+// Progress bar currently drawn on the terminal, if routing is running
+static ACTIVE_BAR: RwLock<Option<ProgressBar>> = RwLock::new(None);
+
+// Print a warning, or in strict mode (--strict) return it as an error for the caller
+// to propagate
+pub fn warn(strict: bool, message: String) -> anyhow::Result<()> {
+    if strict {
+        return Err(anyhow::anyhow!("{} (--strict)", message));
+    }
+    suspend(|| println!("Warning: {}", message));
+    Ok(())
+}
+
+// Clears the active progress bar when dropped
+pub struct AttachedBar;
+
+impl Drop for AttachedBar {
+    fn drop(&mut self) {
+        if let Ok(mut active) = ACTIVE_BAR.write() {
+            *active = None;
+        }
+    }
+}
+
+// Route console output through `bar` until the returned guard is dropped
+pub fn attach(bar: &ProgressBar) -> AttachedBar {
+    if let Ok(mut active) = ACTIVE_BAR.write() {
+        *active = Some(bar.clone());
+    }
+    AttachedBar
+}
+
+// Run `f` with the progress bar cleared from the terminal so its output doesn't
+// interleave with the bar, which is redrawn below it afterwards
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    match ACTIVE_BAR.read().ok().and_then(|active| active.clone()) {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
+
+// `println!` that keeps any active progress bar intact
+#[macro_export]
+macro_rules! console_println {
+    ($($arg:tt)*) => {
+        $crate::console::suspend(|| println!($($arg)*))
+    };
+}
+
+// Warn with `format!` arguments, raising it as an error when `strict`; evaluates to the
+// `Result` of `console::warn`
+#[macro_export]
+macro_rules! console_warn {
+    ($strict:expr, $($arg:tt)*) => {
+        $crate::console::warn($strict, format!($($arg)*))
+    };
+}
+
+// `eprintln!` that keeps any active progress bar intact
+#[macro_export]
+macro_rules! console_eprintln {
+    ($($arg:tt)*) => {
+        $crate::console::suspend(|| eprintln!($($arg)*))
+    };
+}
@@ -0,0 +1,55 @@
+use thiserror::Error;
+
+// Failures library users may want to handle differently, e.g. a cycle in the network
+// versus forcing that doesn't fit it. The library entry points (`RoutingGraph`,
+// `PreparedNetwork`, `process_routing_parallel` and `route_reach`) return them directly;
+// deeper functions return `anyhow::Error`, which carries them and can be matched with
+// `error.downcast_ref::<RouteError>()` through added context.
+#[derive(Debug, Error)]
+pub enum RouteError {
+    // The network can't be put in routing order: a cycle, repeated or dangling reaches,
+    // braided channels, or no reaches at all
+    #[error("{message}")]
+    Topology { reaches: Vec<u64>, message: String },
+    // Channel parameters that can't be routed as given
+    #[error("{message}")]
+    Params { reaches: Vec<u64>, message: String },
+    // Lateral inflow that is missing or doesn't fit the network
+    #[error("{message}")]
+    Forcing { reaches: Vec<u64>, message: String },
+    // Routing started but stopped before every reach was routed
+    #[error("{message}")]
+    Routing { reaches: Vec<u64>, message: String },
+    // A kernel was given coefficients it can't route, such as a zero length or slope
+    #[error("{message}")]
+    Kernel { reaches: Vec<u64>, message: String },
+    // Reading or writing the gpkg, forcing, caches or output failed. The underlying
+    // I/O, SQLite or NetCDF error can be recovered with `downcast_ref`.
+    #[error("{0:#}")]
+    Io(anyhow::Error),
+}
+
+impl RouteError {
+    // The reaches the failure concerns, where it is about particular reaches
+    pub fn reaches(&self) -> &[u64] {
+        match self {
+            RouteError::Topology { reaches, .. }
+            | RouteError::Params { reaches, .. }
+            | RouteError::Forcing { reaches, .. }
+            | RouteError::Routing { reaches, .. }
+            | RouteError::Kernel { reaches, .. } => reaches,
+            RouteError::Io(_) => &[],
+        }
+    }
+}
+
+// A RouteError raised below an entry point comes back out as itself; anything else
+// failed reading or writing
+impl From<anyhow::Error> for RouteError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<RouteError>() {
+            Ok(error) => error,
+            Err(error) => RouteError::Io(error),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+// Synthetic in-memory hydrofabric for tests that exercise the database layer
+use crate::config::{ChannelParams, ColumnConfig, OutputOptions};
+use crate::io::cache::ResultsCache;
+use crate::io::netcdf::{
+    NetCdfOutput, OutputChunk, init_netcdf_output, resume_output, sorted_feature_index,
+};
+use crate::network::NetworkTopology;
+use crate::routing::{RoutingOptions, process_routing_parallel, resume_skip_set};
+use anyhow::Result;
+use chrono::NaiveDate;
+use indicatif::ProgressBar;
+use rusqlite::{Connection, params};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Channel parameters that route cleanly through the kernel
+pub fn default_params() -> ChannelParams {
+    ChannelParams {
+        dx: 1000.0,
+        n: 0.06,
+        ncc: 0.12,
+        s0: 0.001,
+        bw: 5.0,
+        tw: 10.0,
+        twcc: 30.0,
+        cs: 0.5,
+    }
+}
+
+// Create empty `flowpaths` and `flowpath-attributes` tables using the configured column names
+pub fn empty_network(config: &ColumnConfig) -> Connection {
+    let conn = Connection::open_in_memory().expect("Failed to open in-memory database");
+    conn.execute_batch(&format!(
+        "CREATE TABLE 'flowpaths' ({key} TEXT, {downstream} TEXT, areasqkm REAL);
+         CREATE TABLE 'flowpath-attributes' (
+             {key} TEXT, {dx} REAL, {n} REAL, {ncc} REAL, {s0} REAL,
+             {bw} REAL, {tw} REAL, {twcc} REAL, {cs} REAL
+         );",
+        key = config.key,
+        downstream = config.downstream,
+        dx = config.dx,
+        n = config.n,
+        ncc = config.ncc,
+        s0 = config.s0,
+        bw = config.bw,
+        tw = config.tw,
+        twcc = config.twcc,
+        cs = config.cs,
+    ))
+    .expect("Failed to create fixture tables");
+    conn
+}
+
+pub fn insert_flowpath(conn: &Connection, config: &ColumnConfig, id: &str, toid: &str, area: f32) {
+    conn.execute(
+        &format!(
+            "INSERT INTO 'flowpaths' ({}, {}, areasqkm) VALUES (?1, ?2, ?3)",
+            config.key, config.downstream
+        ),
+        params![id, toid, area],
+    )
+    .expect("Failed to insert flowpath");
+}
+
+pub fn insert_attributes(
+    conn: &Connection,
+    config: &ColumnConfig,
+    id: &str,
+    channel: &ChannelParams,
+) {
+    conn.execute(
+        &format!(
+            "INSERT INTO 'flowpath-attributes' ({}, {}, {}, {}, {}, {}, {}, {}, {}) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            config.key,
+            config.dx,
+            config.n,
+            config.ncc,
+            config.s0,
+            config.bw,
+            config.tw,
+            config.twcc,
+            config.cs
+        ),
+        params![
+            id,
+            channel.dx,
+            channel.n,
+            channel.ncc,
+            channel.s0,
+            channel.bw,
+            channel.tw,
+            channel.twcc,
+            channel.cs
+        ],
+    )
+    .expect("Failed to insert flowpath attributes");
+}
+
+// Two headwaters joining at a confluence, which drains to an outlet leaving the domain:
+//
+//   wb-1 \
+//         wb-3 -> wb-4 -> wb-5 (outside the domain)
+//   wb-2 /
+pub fn synthetic_network(config: &ColumnConfig) -> Connection {
+    let conn = empty_network(config);
+    for (id, toid, area) in [
+        ("wb-1", "wb-3", 2.5),
+        ("wb-2", "wb-3", 1.5),
+        ("wb-3", "wb-4", 4.0),
+        ("wb-4", "wb-5", 3.0),
+    ] {
+        insert_flowpath(&conn, config, id, toid, area);
+        insert_attributes(&conn, config, id, &default_params());
+    }
+    conn
+}
+
+// Write a lateral inflow CSV (m3/s per km2 per hour) for one catchment into a per-test temp dir
+pub fn write_forcing(test_name: &str, id: u64, q_out: &[f32]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("route_rs_{}", test_name));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("cat-{}.csv", id));
+    let mut contents = String::from("Time,ID,Q_OUT\n");
+    for (step, value) in q_out.iter().enumerate() {
+        contents.push_str(&format!("{},cat-{},{}\n", step, id, value));
+    }
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+// An output with no files, for routing runs whose results are read from a results cache
+pub fn empty_output(feature_ids: impl IntoIterator<Item = u64>) -> Arc<Mutex<NetCdfOutput>> {
+    Arc::new(Mutex::new(NetCdfOutput {
+        chunks: Vec::new(),
+        feature_index: sorted_feature_index(feature_ids),
+        options: OutputOptions::default(),
+        drainage_area: HashMap::new(),
+        member: 0,
+    }))
+}
+
+// A fresh, empty temp dir for one results cache
+pub fn cache_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "route_rs_cache_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+// Route hourly timesteps through the parallel scheduler without writing any output
+// files, returning every routed reach's results from a results cache
+pub fn route_in_memory(
+    topology: &NetworkTopology,
+    params: &HashMap<u64, ChannelParams>,
+    max_timesteps: usize,
+    options: RoutingOptions,
+) -> Result<ResultsCache> {
+    let dir = cache_dir();
+    let options = RoutingOptions {
+        results_cache: Some(ResultsCache::create(&dir, 0)?),
+        ..options
+    };
+    process_routing_parallel(
+        topology,
+        params,
+        max_timesteps,
+        3600.0,
+        options,
+        empty_output(topology.nodes.keys().copied()),
+        None,
+        None,
+        None,
+        None,
+        Arc::new(ProgressBar::hidden()),
+    )?;
+    ResultsCache::open(&dir, 0)
+}
+
+// Input hash recorded in the files written by `route_to_netcdf`
+pub const FIXTURE_INPUT_HASH: &str = "0123456789abcdef";
+
+// Route hourly timesteps into a single NetCDF output file at `path`
+pub fn route_to_netcdf(
+    path: &Path,
+    topology: &NetworkTopology,
+    params: &HashMap<u64, ChannelParams>,
+    max_timesteps: usize,
+    options: RoutingOptions,
+) -> Result<()> {
+    let timesteps: Vec<i64> = (0..max_timesteps as i64).map(|step| step * 3600).collect();
+    let reference_time = NaiveDate::from_ymd_opt(2000, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let filename = path.to_string_lossy().into_owned();
+    let output_options = OutputOptions {
+        input_hash: Some(FIXTURE_INPUT_HASH.to_string()),
+        ..OutputOptions::default()
+    };
+    let file = init_netcdf_output(&filename, timesteps, &reference_time, &output_options)?;
+    let output = Arc::new(Mutex::new(NetCdfOutput {
+        chunks: vec![OutputChunk {
+            filename,
+            first_step: 0,
+            num_steps: max_timesteps,
+            start_time: reference_time,
+            end_time: reference_time,
+            file,
+        }],
+        feature_index: sorted_feature_index(topology.nodes.keys().copied()),
+        options: output_options,
+        drainage_area: HashMap::new(),
+        member: 0,
+    }));
+    process_routing_parallel(
+        topology,
+        params,
+        max_timesteps,
+        3600.0,
+        options,
+        output,
+        None,
+        None,
+        None,
+        None,
+        Arc::new(ProgressBar::hidden()),
+    )?;
+    Ok(())
+}
+
+// Fill in the reaches missing from a `route_to_netcdf` output, as `--resume-output` does,
+// for a run whose inputs hash to `input_hash`
+pub fn resume_netcdf(
+    path: &Path,
+    topology: &NetworkTopology,
+    params: &HashMap<u64, ChannelParams>,
+    max_timesteps: usize,
+    input_hash: &str,
+) -> Result<()> {
+    let timesteps: Vec<i64> = (0..max_timesteps as i64).map(|step| step * 3600).collect();
+    let reference_time = NaiveDate::from_ymd_opt(2000, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    let output_options = OutputOptions {
+        input_hash: Some(input_hash.to_string()),
+        ..OutputOptions::default()
+    };
+    let (output, written) = resume_output(
+        path,
+        &timesteps,
+        &reference_time,
+        sorted_feature_index(topology.nodes.keys().copied()),
+        output_options,
+        HashMap::new(),
+    )?;
+    let options = RoutingOptions {
+        skip_routing: resume_skip_set(topology, &written),
+        already_written: written,
+        ..RoutingOptions::default()
+    };
+    process_routing_parallel(
+        topology,
+        params,
+        max_timesteps,
+        3600.0,
+        options,
+        output,
+        None,
+        None,
+        None,
+        None,
+        Arc::new(ProgressBar::hidden()),
+    )?;
+    Ok(())
+}
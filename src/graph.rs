@@ -0,0 +1,354 @@
+use crate::config::{ChannelParams, ColumnConfig};
+use crate::error::RouteError;
+use crate::io::netcdf::{NetCdfOutput, sorted_feature_index};
+use crate::network::{self, NetworkTopology, build_network_topology, load_channel_parameters};
+use crate::routing::{RoutingOptions, process_routing_parallel};
+use anyhow::Result;
+use indicatif::ProgressBar;
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// A reach network assembled in code rather than read from a hydrofabric
+pub struct RoutingGraph {
+    pub topology: NetworkTopology,
+    pub channel_params: HashMap<u64, ChannelParams>,
+}
+
+impl RoutingGraph {
+    pub fn builder() -> RoutingGraphBuilder {
+        RoutingGraphBuilder::default()
+    }
+
+    // Route every reach, writing results to `output_file`
+    pub fn route(
+        &self,
+        max_timesteps: usize,
+        dt: f32,
+        options: RoutingOptions,
+        output_file: Arc<Mutex<NetCdfOutput>>,
+        progress_bar: Arc<ProgressBar>,
+    ) -> Result<(), RouteError> {
+        process_routing_parallel(
+            &self.topology,
+            &self.channel_params,
+            max_timesteps,
+            dt,
+            options,
+            output_file,
+            None,
+            None,
+            None,
+            None,
+            progress_bar,
+        )
+    }
+}
+
+// Lateral inflow for one run of a `PreparedNetwork`
+pub enum ForcingSource {
+    // Per-catchment `cat-<id>.csv` files in a directory, as with --csv-dir
+    Directory(PathBuf),
+    // ngen Q_OUT runoff per forcing step by catchment id, converted with each reach's area
+    Runoff(HashMap<u64, Vec<f32>>),
+}
+
+// A gpkg network built once and routed any number of times, e.g. over forcing scenarios
+// in a calibration loop, without re-reading the database between runs
+pub struct PreparedNetwork {
+    pub topology: NetworkTopology,
+    pub channel_params: HashMap<u64, ChannelParams>,
+    // Output slot of each reach, the same for every run
+    pub feature_index: HashMap<u64, usize>,
+}
+
+impl PreparedNetwork {
+    pub fn load(conn: &Connection, config: &ColumnConfig) -> Result<Self, RouteError> {
+        let topology = build_network_topology(conn, config, Path::new(""), false)?;
+        let channel_params = load_channel_parameters(conn, &topology, config)?;
+        network::check_reach_lengths(&channel_params, &HashSet::new())?;
+        let feature_index = sorted_feature_index(topology.routing_order.iter().copied());
+        Ok(PreparedNetwork {
+            topology,
+            channel_params,
+            feature_index,
+        })
+    }
+
+    // Route every reach with `forcing`, writing results to `output_file`. State left by
+    // the previous run is cleared first, so each run matches one on a freshly built network
+    pub fn route(
+        &mut self,
+        forcing: ForcingSource,
+        max_timesteps: usize,
+        dt: f32,
+        options: RoutingOptions,
+        output_file: Arc<Mutex<NetCdfOutput>>,
+        progress_bar: Arc<ProgressBar>,
+    ) -> Result<(), RouteError> {
+        match forcing {
+            ForcingSource::Directory(dir) => {
+                for node in self.topology.nodes.values_mut() {
+                    node.lateral_inflow = None;
+                }
+                network::use_forcing_dir(&mut self.topology, &dir)?;
+            }
+            ForcingSource::Runoff(runoff) => {
+                network::attach_forcing(&mut self.topology, runoff, options.strict)?;
+                network::reset_routing_state(&self.topology)?;
+            }
+        }
+        process_routing_parallel(
+            &self.topology,
+            &self.channel_params,
+            max_timesteps,
+            dt,
+            options,
+            output_file,
+            None,
+            None,
+            None,
+            None,
+            progress_bar,
+        )
+    }
+}
+
+struct Reach {
+    id: u64,
+    downstream_id: Option<u64>,
+    params: ChannelParams,
+    lateral_inflow: Vec<f32>,
+}
+
+#[derive(Default)]
+pub struct RoutingGraphBuilder {
+    reaches: Vec<Reach>,
+}
+
+impl RoutingGraphBuilder {
+    // Add a reach draining to `downstream_id` (None for an outlet), with lateral
+    // inflow in m3/s for each forcing timestep
+    pub fn add_reach(
+        &mut self,
+        id: u64,
+        downstream_id: Option<u64>,
+        params: ChannelParams,
+        lateral_inflow: Vec<f32>,
+    ) -> &mut Self {
+        self.reaches.push(Reach {
+            id,
+            downstream_id,
+            params,
+            lateral_inflow,
+        });
+        self
+    }
+
+    // Check that ids are unique, every downstream reach exists, every reach has a positive
+    // length, and the network is acyclic
+    pub fn build(self) -> Result<RoutingGraph, RouteError> {
+        if self.reaches.is_empty() {
+            return Err(RouteError::Topology {
+                reaches: Vec::new(),
+                message: "Routing graph has no reaches".to_string(),
+            });
+        }
+        let mut topology = NetworkTopology::new();
+        let mut channel_params = HashMap::new();
+
+        for reach in &self.reaches {
+            // Reaches are written to the NetCDF's int64 `feature_id`
+            if i64::try_from(reach.id).is_err() {
+                return Err(RouteError::Topology {
+                    reaches: vec![reach.id],
+                    message: format!("Reach id {} is beyond the int64 range", reach.id),
+                });
+            }
+            if channel_params
+                .insert(reach.id, reach.params.clone())
+                .is_some()
+            {
+                return Err(RouteError::Topology {
+                    reaches: vec![reach.id],
+                    message: format!("Reach {} was added more than once", reach.id),
+                });
+            }
+        }
+        network::check_reach_lengths(&channel_params, &HashSet::new())?;
+
+        for reach in self.reaches {
+            if let Some(downstream) = reach.downstream_id
+                && !channel_params.contains_key(&downstream)
+            {
+                return Err(RouteError::Topology {
+                    reaches: vec![reach.id],
+                    message: format!("Reach {} drains to unknown reach {}", reach.id, downstream),
+                });
+            }
+            topology.add_node(reach.id, reach.downstream_id, None, PathBuf::new());
+            if let Some(node) = topology.nodes.get_mut(&reach.id) {
+                node.lateral_inflow = Some(reach.lateral_inflow);
+            }
+        }
+
+        topology.build_upstream_connections();
+        topology.topological_sort()?;
+
+        Ok(RoutingGraph {
+            topology,
+            channel_params,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::routing::process_node_all_timesteps;
+
+    #[test]
+    fn builds_routing_order_from_edges() {
+        let mut builder = RoutingGraph::builder();
+        builder
+            .add_reach(3, None, fixtures::default_params(), vec![0.0; 4])
+            .add_reach(1, Some(3), fixtures::default_params(), vec![1.0; 4])
+            .add_reach(2, Some(3), fixtures::default_params(), vec![2.0; 4]);
+        let graph = builder.build().unwrap();
+
+        assert_eq!(graph.topology.routing_order.len(), 3);
+        assert_eq!(graph.topology.routing_order[2], 3);
+        assert_eq!(graph.channel_params.len(), 3);
+    }
+
+    #[test]
+    fn rejects_cycles_and_dangling_edges() {
+        let mut cycle = RoutingGraph::builder();
+        cycle
+            .add_reach(1, Some(2), fixtures::default_params(), vec![])
+            .add_reach(2, Some(1), fixtures::default_params(), vec![]);
+        assert!(cycle.build().is_err());
+
+        let mut dangling = RoutingGraph::builder();
+        dangling.add_reach(1, Some(9), fixtures::default_params(), vec![]);
+        assert!(dangling.build().is_err());
+
+        let mut duplicate = RoutingGraph::builder();
+        duplicate
+            .add_reach(1, None, fixtures::default_params(), vec![])
+            .add_reach(1, None, fixtures::default_params(), vec![]);
+        assert!(duplicate.build().is_err());
+    }
+
+    #[test]
+    fn errors_can_be_matched_by_kind_and_reach() {
+        use anyhow::Context;
+
+        // 3 drains into a cycle between 1 and 2
+        let mut cycle = RoutingGraph::builder();
+        cycle
+            .add_reach(3, Some(1), fixtures::default_params(), vec![])
+            .add_reach(1, Some(2), fixtures::default_params(), vec![])
+            .add_reach(2, Some(1), fixtures::default_params(), vec![]);
+        let error = cycle.build().err().unwrap();
+        assert!(matches!(
+            &error,
+            RouteError::Topology { reaches, .. } if reaches == &[1, 2]
+        ));
+
+        let mut params = fixtures::default_params();
+        params.dx = 0.0;
+        let mut zero_length = RoutingGraph::builder();
+        zero_length
+            .add_reach(6, Some(7), fixtures::default_params(), vec![])
+            .add_reach(7, None, params, vec![]);
+        let error = zero_length
+            .build()
+            .context("Building the graph")
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<RouteError>(),
+            Some(RouteError::Params { reaches, .. }) if reaches == &[7]
+        ));
+
+        // A gpkg without the expected tables fails reading it
+        let empty = Connection::open_in_memory().unwrap();
+        let error = PreparedNetwork::load(&empty, &ColumnConfig::new())
+            .err()
+            .unwrap();
+        assert!(matches!(error, RouteError::Io(_)));
+        assert!(error.reaches().is_empty());
+    }
+
+    #[test]
+    fn routes_in_memory_forcing() {
+        let mut builder = RoutingGraph::builder();
+        builder.add_reach(1, None, fixtures::default_params(), vec![5.0; 4]);
+        let graph = builder.build().unwrap();
+
+        let results = process_node_all_timesteps(
+            &1,
+            &graph.topology,
+            &graph.channel_params[&1],
+            4,
+            3600.0,
+            &RoutingOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.flow_data.len(), 4);
+        assert!(results.flow_data[3] > 4.0);
+    }
+
+    #[test]
+    fn prepared_network_routes_each_scenario_like_a_fresh_build() {
+        use crate::io::cache::ResultsCache;
+
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let q_out = [0.5, 2.0, 1.0];
+        let mut dir = PathBuf::new();
+        for id in 1..=4 {
+            dir = fixtures::write_forcing("prepared", id, &q_out)
+                .parent()
+                .unwrap()
+                .to_path_buf();
+        }
+        let scaled: HashMap<u64, Vec<f32>> = (1..=4)
+            .map(|id| (id, q_out.iter().map(|q| q * 3.0).collect()))
+            .collect();
+
+        let mut prepared = PreparedNetwork::load(&conn, &config).unwrap();
+        let mut route = |forcing: ForcingSource| {
+            let dir = fixtures::cache_dir();
+            let options = RoutingOptions {
+                results_cache: Some(ResultsCache::create(&dir, 0).unwrap()),
+                ..RoutingOptions::default()
+            };
+            let output = fixtures::empty_output(prepared.feature_index.keys().copied());
+            prepared
+                .route(
+                    forcing,
+                    3,
+                    3600.0,
+                    options,
+                    output,
+                    Arc::new(ProgressBar::hidden()),
+                )
+                .unwrap();
+            ResultsCache::open(&dir, 0).unwrap()
+        };
+
+        let first = route(ForcingSource::Directory(dir.clone()));
+        let runoff = route(ForcingSource::Runoff(scaled));
+        let again = route(ForcingSource::Directory(dir));
+        for id in 1..=4 {
+            let flow = first.load(id).unwrap().flow_data;
+            assert_eq!(flow, again.load(id).unwrap().flow_data);
+            assert_ne!(flow, runoff.load(id).unwrap().flow_data);
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use crate::console_eprintln;
+use anyhow::{Context, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set on Ctrl-C or a termination signal. Routing stops taking new reaches, and the writer
+// thread flushes the CSV and syncs the NetCDF for everything already routed.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+// Install the handler for the route_rs binary; library callers keep their own. A second
+// interrupt exits at once, in case finishing the output hangs.
+pub fn install() -> Result<()> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        console_eprintln!(
+            "Interrupted: finishing the output written so far (interrupt again to quit now)"
+        );
+    })
+    .context("Failed to install the interrupt handler")
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// An error once the run has been interrupted, for workers to stop on
+pub fn check() -> Result<()> {
+    if interrupted() {
+        return Err(anyhow::anyhow!("interrupted"));
+    }
+    Ok(())
+}
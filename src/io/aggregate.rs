@@ -0,0 +1,159 @@
+use crate::config::TimeFormat;
+use crate::console_println;
+use crate::io::netcdf::downsample;
+use crate::io::results::SimulationResults;
+use crate::network::NetworkTopology;
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// Load a feature_id -> group_id mapping from a two column CSV
+pub fn load_aggregate_map(path: &Path) -> Result<HashMap<u64, u64>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .with_context(|| format!("Failed to open aggregate map: {}", path.display()))?;
+
+    let mut map = HashMap::new();
+    for (i, result) in rdr.records().enumerate() {
+        let record =
+            result.with_context(|| format!("Failed to read aggregate map record {}", i))?;
+        let parse = |column: usize| -> Result<u64> {
+            let value = record
+                .get(column)
+                .ok_or_else(|| anyhow::anyhow!("Missing column {} in record {}", column, i))?;
+            value
+                .parse::<u64>()
+                .with_context(|| format!("Invalid id '{}' in aggregate map record {}", value, i))
+        };
+        map.insert(parse(0)?, parse(1)?);
+    }
+
+    Ok(map)
+}
+
+// Sums the outflow of each group's outlet reaches into a per-group hydrograph
+pub struct GroupAggregator {
+    outlet_groups: HashMap<u64, u64>,
+    group_flows: BTreeMap<u64, Vec<f32>>,
+    filename: String,
+    timesteps: Vec<i64>,
+    reference_time: NaiveDateTime,
+    time_format: TimeFormat,
+    // Internal timesteps per output timestep
+    output_stride: usize,
+}
+
+impl GroupAggregator {
+    pub fn new(
+        group_map: &HashMap<u64, u64>,
+        topology: &NetworkTopology,
+        filename: String,
+        timesteps: Vec<i64>,
+        reference_time: NaiveDateTime,
+        time_format: TimeFormat,
+        output_stride: usize,
+    ) -> Self {
+        // A reach is a group outlet when its downstream reach belongs to another group (or none)
+        let outlet_groups: HashMap<u64, u64> = group_map
+            .iter()
+            .filter(|(id, group)| {
+                topology.nodes.get(id).is_some_and(|node| {
+                    node.downstream_id
+                        .and_then(|downstream| group_map.get(&downstream))
+                        != Some(group)
+                })
+            })
+            .map(|(&id, &group)| (id, group))
+            .collect();
+
+        let group_flows = group_map
+            .values()
+            .map(|&group| (group, vec![0.0; timesteps.len()]))
+            .collect();
+
+        GroupAggregator {
+            outlet_groups,
+            group_flows,
+            filename,
+            timesteps,
+            reference_time,
+            time_format,
+            output_stride,
+        }
+    }
+
+    pub fn add(&mut self, results: &SimulationResults) {
+        let Some(group) = self.outlet_groups.get(&(results.feature_id as u64)) else {
+            return;
+        };
+        if let Some(flows) = self.group_flows.get_mut(group) {
+            let downsampled = downsample(&results.flow_data, self.output_stride);
+            for (total, flow) in flows.iter_mut().zip(downsampled) {
+                *total += flow;
+            }
+        }
+    }
+
+    // Write the aggregated flows to their own NetCDF file with a group_id dimension
+    pub fn finish(self) -> Result<()> {
+        let mut file = netcdf::create(&self.filename)
+            .with_context(|| format!("Failed to create NetCDF file: {}", self.filename))?;
+
+        file.add_dimension("group_id", self.group_flows.len())
+            .context("Failed to add group_id dimension")?;
+        file.add_dimension("time", self.timesteps.len())
+            .context("Failed to add time dimension")?;
+
+        let mut time_var = file
+            .add_variable::<f64>("time", &["time"])
+            .context("Failed to add time variable")?;
+        time_var.put_attribute("long_name", "valid output time")?;
+        time_var.put_attribute("standard_name", "time")?;
+        time_var.put_attribute(
+            "units",
+            format!(
+                "seconds since {}",
+                self.time_format.format(&self.reference_time)
+            ),
+        )?;
+        let times: Vec<f64> = self
+            .timesteps
+            .iter()
+            .map(|&seconds| seconds as f64)
+            .collect();
+        time_var
+            .put_values(&times, ..)
+            .context("Failed to write time values")?;
+
+        let group_ids: Vec<i64> = self.group_flows.keys().map(|&id| id as i64).collect();
+        let mut group_var = file
+            .add_variable::<i64>("group_id", &["group_id"])
+            .context("Failed to add group_id variable")?;
+        group_var.put_attribute("long_name", "Aggregation group ID")?;
+        group_var
+            .put_values(&group_ids, ..)
+            .context("Failed to write group ids")?;
+
+        let mut flow_var = file
+            .add_variable::<f32>("flow", &["group_id", "time"])
+            .context("Failed to add flow variable")?;
+        flow_var.put_attribute("long_name", "Summed outflow of group outlet reaches")?;
+        flow_var.put_attribute("units", "m3 s-1")?;
+        for (gidx, flows) in self.group_flows.values().enumerate() {
+            flow_var
+                .put_values(flows, (gidx, ..))
+                .context("Failed to write aggregated flow")?;
+        }
+
+        file.add_attribute("TITLE", "AGGREGATED OUTPUT FROM ROUTE_RS")?;
+        console_println!(
+            "Aggregated output for {} groups saved to {}",
+            group_ids.len(),
+            self.filename
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::io::results::SimulationResults;
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const HASH_FILE: &str = "inputs.hash";
+
+// A reach's feature_id, then its flow, velocity, depth and nudge series (nudge is empty
+// for reaches that weren't nudged), bincode-encoded
+type CachedSeries<'a> = (i64, &'a Vec<f32>, &'a Vec<f32>, &'a Vec<f32>, &'a Vec<f32>);
+
+// On-disk cache of per-node routing results, keyed to the inputs that produced them
+#[derive(Debug, Clone)]
+pub struct ResultsCache {
+    dir: PathBuf,
+}
+
+impl ResultsCache {
+    // Open the cache for writing, discarding any results produced from different inputs
+    pub fn create(dir: &Path, input_hash: u64) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create results cache: {}", dir.display()))?;
+        let cache = ResultsCache {
+            dir: dir.to_path_buf(),
+        };
+
+        if cache.stored_hash() != Some(input_hash) {
+            let mut removed = 0;
+            for entry in fs::read_dir(dir)?.filter_map(Result::ok) {
+                if entry.path().extension().is_some_and(|ext| ext == "bin") {
+                    fs::remove_file(entry.path())?;
+                    removed += 1;
+                }
+            }
+            if removed > 0 {
+                println!(
+                    "Inputs changed, invalidated {} cached results in {}",
+                    removed,
+                    dir.display()
+                );
+            }
+            fs::write(dir.join(HASH_FILE), input_hash.to_string())
+                .context("Failed to write results cache hash")?;
+        }
+
+        Ok(cache)
+    }
+
+    // Open an existing cache for replay; it must have been produced from the same inputs
+    pub fn open(dir: &Path, input_hash: u64) -> Result<Self> {
+        let cache = ResultsCache {
+            dir: dir.to_path_buf(),
+        };
+        match cache.stored_hash() {
+            Some(hash) if hash == input_hash => Ok(cache),
+            Some(_) => Err(anyhow::anyhow!(
+                "Results cache {} was built from different inputs; rerun without --replay",
+                dir.display()
+            )),
+            None => Err(anyhow::anyhow!(
+                "No results cache found in {}",
+                dir.display()
+            )),
+        }
+    }
+
+    fn stored_hash(&self) -> Option<u64> {
+        fs::read_to_string(self.dir.join(HASH_FILE))
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+    }
+
+    fn path(&self, feature_id: i64) -> PathBuf {
+        self.dir.join(format!("{}.bin", feature_id))
+    }
+
+    pub fn store(&self, results: &SimulationResults) -> Result<()> {
+        let path = self.path(results.feature_id);
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to write cached results: {}", path.display()))?;
+        let series: CachedSeries = (
+            results.feature_id,
+            &results.flow_data,
+            &results.velocity_data,
+            &results.depth_data,
+            &results.nudge_data,
+        );
+        let write = || -> Result<()> {
+            let mut writer = BufWriter::new(file);
+            bincode::serialize_into(&mut writer, &series)?;
+            writer.flush()?;
+            Ok(())
+        };
+        write().with_context(|| format!("Failed to write cached results: {}", path.display()))
+    }
+
+    pub fn load(&self, feature_id: i64) -> Result<SimulationResults> {
+        let path = self.path(feature_id);
+        let file = File::open(&path)
+            .with_context(|| format!("Failed to read cached results: {}", path.display()))?;
+        let (id, flow, velocity, depth, nudge): (i64, Vec<f32>, Vec<f32>, Vec<f32>, Vec<f32>) =
+            bincode::deserialize_from(BufReader::new(file))
+                .with_context(|| format!("Corrupt cache file: {}", path.display()))?;
+
+        let mut results = SimulationResults::new(id);
+        results.flow_data = flow;
+        results.velocity_data = velocity;
+        results.depth_data = depth;
+        results.nudge_data = nudge;
+        Ok(results)
+    }
+}
@@ -0,0 +1,493 @@
+use anyhow::{Context, Result};
+use netcdf::{AttributeValue, Variable};
+use std::collections::HashMap;
+use std::path::Path;
+
+// A feature both files hold: its id, its slot in a and its slot in b
+type SharedFeature = (u64, usize, usize);
+
+// Allowed difference between two values: within either bound passes
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    pub absolute: f64,
+    pub relative: f64,
+}
+
+impl Tolerance {
+    fn allows(&self, abs: f64, rel: f64) -> bool {
+        abs <= self.absolute || rel <= self.relative
+    }
+}
+
+// Differences in one variable over the features and timesteps both files hold
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VariableDiff {
+    pub name: String,
+    pub compared: usize,
+    pub max_abs: f64,
+    pub mean_abs: f64,
+    pub max_rel: f64,
+    pub mean_rel: f64,
+    // Values beyond the tolerance
+    pub exceeding: usize,
+    // Values filled (or non-finite) in one file but not the other
+    pub fill_mismatches: usize,
+    sum_abs: f64,
+    sum_rel: f64,
+}
+
+impl VariableDiff {
+    pub fn passed(&self) -> bool {
+        self.exceeding == 0 && self.fill_mismatches == 0
+    }
+
+    // Add one feature's values from each file, skipping values both files leave as fill.
+    // Returns the feature's largest absolute and relative differences.
+    fn add_row(
+        &mut self,
+        a: &[f64],
+        b: &[f64],
+        fills: (Option<f64>, Option<f64>),
+        tolerance: Tolerance,
+    ) -> (f64, f64) {
+        let is_fill = |value: f64, fill: Option<f64>| !value.is_finite() || Some(value) == fill;
+        let (mut row_abs, mut row_rel) = (0.0_f64, 0.0_f64);
+        for (&x, &y) in a.iter().zip(b) {
+            match (is_fill(x, fills.0), is_fill(y, fills.1)) {
+                (true, true) => continue,
+                (false, false) => {}
+                _ => {
+                    self.fill_mismatches += 1;
+                    continue;
+                }
+            }
+            let (abs, rel) = ((x - y).abs(), relative_difference(x, y));
+            self.compared += 1;
+            self.sum_abs += abs;
+            self.sum_rel += rel;
+            if !tolerance.allows(abs, rel) {
+                self.exceeding += 1;
+            }
+            row_abs = row_abs.max(abs);
+            row_rel = row_rel.max(rel);
+        }
+        self.max_abs = self.max_abs.max(row_abs);
+        self.max_rel = self.max_rel.max(row_rel);
+        (row_abs, row_rel)
+    }
+
+    fn finish(&mut self) {
+        if self.compared > 0 {
+            self.mean_abs = self.sum_abs / self.compared as f64;
+            self.mean_rel = self.sum_rel / self.compared as f64;
+        }
+    }
+}
+
+// A feature's largest relative difference and the variable it's in
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureDiff {
+    pub feature_id: u64,
+    pub variable: String,
+    pub max_abs: f64,
+    pub max_rel: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Comparison {
+    pub variables: Vec<VariableDiff>,
+    // Most divergent first
+    pub worst_features: Vec<FeatureDiff>,
+    pub only_in_a: Vec<u64>,
+    pub only_in_b: Vec<u64>,
+    // Variables only one file has as (feature_id[, time]), such as member-led series
+    pub skipped: Vec<String>,
+}
+
+impl Comparison {
+    pub fn passed(&self) -> bool {
+        self.only_in_a.is_empty()
+            && self.only_in_b.is_empty()
+            && self.variables.iter().all(VariableDiff::passed)
+    }
+}
+
+// Symmetric relative difference, so neither file is treated as the truth; 0 when both are 0
+fn relative_difference(a: f64, b: f64) -> f64 {
+    let scale = a.abs().max(b.abs());
+    if scale == 0.0 {
+        0.0
+    } else {
+        (a - b).abs() / scale
+    }
+}
+
+fn fill_value(var: &Variable) -> Option<f64> {
+    match var.attribute_value("_FillValue")?.ok()? {
+        AttributeValue::Float(value) => Some(value as f64),
+        AttributeValue::Double(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Feature ids by slot, leaving out slots that were never written
+fn feature_slots(file: &netcdf::File, path: &Path) -> Result<HashMap<u64, usize>> {
+    let ids: Vec<i64> = file
+        .variable("feature_id")
+        .ok_or_else(|| anyhow::anyhow!("feature_id variable not found in {}", path.display()))?
+        .get_values(..)
+        .with_context(|| format!("Failed to read feature ids from {}", path.display()))?;
+    Ok(ids
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, &id)| u64::try_from(id).ok().map(|id| (id, slot)))
+        .collect())
+}
+
+// How a compared variable lays out its features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    // (feature_id)
+    Scalar,
+    // (feature_id, time)
+    FeatureMajor,
+    // (time, feature_id), as written under --nwm-compat
+    TimeMajor,
+}
+
+// Rows `slots` of a variable, one feature after another, read as one block when they're
+// consecutive
+fn read_rows(var: &Variable, slots: &[usize], layout: Layout) -> Result<Vec<f64>> {
+    let consecutive = slots.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    if consecutive && let (Some(&first), Some(&last)) = (slots.first(), slots.last()) {
+        return Ok(match layout {
+            Layout::Scalar => var.get_values(first..last + 1)?,
+            Layout::FeatureMajor => var.get_values((first..last + 1, ..))?,
+            Layout::TimeMajor => {
+                let values: Vec<f64> = var.get_values((.., first..last + 1))?;
+                transpose(&values, slots.len())
+            }
+        });
+    }
+    let mut values = Vec::new();
+    for &slot in slots {
+        match layout {
+            Layout::Scalar => values.push(var.get_value::<f64, _>(slot)?),
+            Layout::FeatureMajor => values.extend(var.get_values::<f64, _>((slot, ..))?),
+            Layout::TimeMajor => values.extend(var.get_values::<f64, _>((.., slot))?),
+        }
+    }
+    Ok(values)
+}
+
+// Reorder a (time, feature) block of `features` columns into (feature, time)
+fn transpose(values: &[f64], features: usize) -> Vec<f64> {
+    let steps = values.len() / features.max(1);
+    (0..features)
+        .flat_map(|feature| (0..steps).map(move |step| values[step * features + feature]))
+        .collect()
+}
+
+// Compare every (feature_id[, time]) variable two output files share, matching features
+// by id rather than slot, and keep the `top` most divergent features
+pub fn compare_outputs(a: &Path, b: &Path, tolerance: Tolerance, top: usize) -> Result<Comparison> {
+    let open = |path: &Path| {
+        netcdf::open(path).with_context(|| format!("Failed to open {}", path.display()))
+    };
+    let (file_a, file_b) = (open(a)?, open(b)?);
+
+    let steps = |file: &netcdf::File| file.dimension("time").map(|dim| dim.len());
+    if steps(&file_a) != steps(&file_b) {
+        return Err(anyhow::anyhow!(
+            "{} has {:?} timesteps but {} has {:?}",
+            a.display(),
+            steps(&file_a),
+            b.display(),
+            steps(&file_b)
+        ));
+    }
+
+    check_time_axes((&file_a, a), (&file_b, b))?;
+
+    let slots_a = feature_slots(&file_a, a)?;
+    let slots_b = feature_slots(&file_b, b)?;
+    let (shared, only_in_a, only_in_b) = match_features(&slots_a, &slots_b);
+    let mut comparison = Comparison {
+        only_in_a,
+        only_in_b,
+        ..Comparison::default()
+    };
+
+    let mut worst: HashMap<u64, FeatureDiff> = HashMap::new();
+    let mut names: Vec<String> = file_a.variables().map(|var| var.name()).collect();
+    names.sort_unstable();
+    for name in names {
+        let Some(var_a) = file_a.variable(&name) else {
+            continue;
+        };
+        let dims: Vec<String> = var_a.dimensions().iter().map(|dim| dim.name()).collect();
+        let layout = match dims.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["feature_id"] if name != "feature_id" => Layout::Scalar,
+            ["feature_id", "time"] => Layout::FeatureMajor,
+            ["time", "feature_id"] => Layout::TimeMajor,
+            _ => {
+                if name != "feature_id" && dims.iter().any(|dim| dim == "feature_id") {
+                    comparison.skipped.push(name);
+                }
+                continue;
+            }
+        };
+        let Some(var_b) = file_b.variable(&name).filter(|var| {
+            var.dimensions()
+                .iter()
+                .map(|dim| dim.name())
+                .eq(dims.clone())
+        }) else {
+            comparison.skipped.push(name);
+            continue;
+        };
+        let fills = (fill_value(&var_a), fill_value(&var_b));
+
+        let mut diff = VariableDiff {
+            name: name.clone(),
+            ..VariableDiff::default()
+        };
+        // Read in blocks of features to bound memory on large domains
+        const BLOCK: usize = 4096;
+        for block in shared.chunks(BLOCK) {
+            let rows_a: Vec<usize> = block.iter().map(|&(_, slot, _)| slot).collect();
+            let rows_b: Vec<usize> = block.iter().map(|&(_, _, slot)| slot).collect();
+            let values_a = read_rows(&var_a, &rows_a, layout)
+                .with_context(|| format!("Failed to read {} from {}", name, a.display()))?;
+            let values_b = read_rows(&var_b, &rows_b, layout)
+                .with_context(|| format!("Failed to read {} from {}", name, b.display()))?;
+            let row_len = values_a.len() / block.len();
+
+            for (row, &(id, _, _)) in block.iter().enumerate() {
+                let range = row * row_len..(row + 1) * row_len;
+                let (max_abs, max_rel) =
+                    diff.add_row(&values_a[range.clone()], &values_b[range], fills, tolerance);
+                if max_rel > 0.0 && worst.get(&id).is_none_or(|seen| max_rel > seen.max_rel) {
+                    worst.insert(
+                        id,
+                        FeatureDiff {
+                            feature_id: id,
+                            variable: name.clone(),
+                            max_abs,
+                            max_rel,
+                        },
+                    );
+                }
+            }
+        }
+        diff.finish();
+        comparison.variables.push(diff);
+    }
+
+    comparison.worst_features = most_divergent(worst, top);
+    Ok(comparison)
+}
+
+// Fail unless both files have the same time units and values: series on shifted axes
+// would otherwise be compared step by step as if they lined up
+fn check_time_axes(a: (&netcdf::File, &Path), b: (&netcdf::File, &Path)) -> Result<()> {
+    let axis = |(file, path): (&netcdf::File, &Path)| -> Result<(Option<String>, Vec<f64>)> {
+        let Some(var) = file.variable("time") else {
+            return Ok((None, Vec::new()));
+        };
+        let units = match var.attribute_value("units") {
+            Some(Ok(AttributeValue::Str(units))) => Some(units),
+            _ => None,
+        };
+        let values = var
+            .get_values(..)
+            .with_context(|| format!("Failed to read times from {}", path.display()))?;
+        Ok((units, values))
+    };
+    let ((units_a, times_a), (units_b, times_b)) = (axis(a)?, axis(b)?);
+    if units_a != units_b {
+        return Err(anyhow::anyhow!(
+            "{} has time units {:?} but {} has {:?}",
+            a.1.display(),
+            units_a,
+            b.1.display(),
+            units_b
+        ));
+    }
+    if let Some((step, (x, y))) = times_a
+        .iter()
+        .zip(&times_b)
+        .enumerate()
+        .find(|(_, (x, y))| x != y)
+    {
+        return Err(anyhow::anyhow!(
+            "{} and {} have different times at step {} ({} and {} {})",
+            a.1.display(),
+            b.1.display(),
+            step,
+            x,
+            y,
+            units_a.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+// Features in both files in a's slot order, so matching layouts read in blocks, then the
+// ids only in a and only in b
+fn match_features(
+    slots_a: &HashMap<u64, usize>,
+    slots_b: &HashMap<u64, usize>,
+) -> (Vec<SharedFeature>, Vec<u64>, Vec<u64>) {
+    let mut shared = Vec::new();
+    let mut only_in_a = Vec::new();
+    for (&id, &slot) in slots_a {
+        match slots_b.get(&id) {
+            Some(&slot_b) => shared.push((id, slot, slot_b)),
+            None => only_in_a.push(id),
+        }
+    }
+    let mut only_in_b: Vec<u64> = slots_b
+        .keys()
+        .filter(|id| !slots_a.contains_key(id))
+        .copied()
+        .collect();
+    shared.sort_unstable_by_key(|&(_, slot, _)| slot);
+    only_in_a.sort_unstable();
+    only_in_b.sort_unstable();
+    (shared, only_in_a, only_in_b)
+}
+
+// The `top` features with the largest relative differences, ties by id
+fn most_divergent(worst: HashMap<u64, FeatureDiff>, top: usize) -> Vec<FeatureDiff> {
+    let mut worst: Vec<FeatureDiff> = worst.into_values().collect();
+    worst.sort_unstable_by(|x, y| {
+        y.max_rel
+            .total_cmp(&x.max_rel)
+            .then(x.feature_id.cmp(&y.feature_id))
+    });
+    worst.truncate(top);
+    worst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: Tolerance = Tolerance {
+        absolute: 0.0,
+        relative: 1e-6,
+    };
+
+    #[test]
+    fn features_are_matched_by_id_not_slot() {
+        let slots_a = HashMap::from([(1, 0), (2, 1), (3, 2)]);
+        let slots_b = HashMap::from([(2, 0), (1, 1), (4, 2)]);
+        let (shared, only_in_a, only_in_b) = match_features(&slots_a, &slots_b);
+        assert_eq!(shared, vec![(1, 0, 1), (2, 1, 0)]);
+        assert_eq!((only_in_a, only_in_b), (vec![3], vec![4]));
+    }
+
+    #[test]
+    fn time_major_blocks_are_read_back_one_feature_at_a_time() {
+        // Two timesteps of three features, (time, feature_id)
+        let values = [1.0, 2.0, 3.0, 10.0, 20.0, 30.0];
+        assert_eq!(transpose(&values, 3), vec![1.0, 10.0, 2.0, 20.0, 3.0, 30.0]);
+    }
+
+    #[test]
+    fn differences_skip_shared_fill_and_count_one_sided_fill() {
+        let mut diff = VariableDiff::default();
+        let fills = (Some(-9999.0), Some(-9999.0));
+        assert_eq!(
+            diff.add_row(&[1.0, 2.0], &[1.0, 2.0], fills, TOLERANCE),
+            (0.0, 0.0)
+        );
+        assert_eq!(
+            diff.add_row(&[4.0, 4.0, -9999.0], &[4.0, 5.0, -9999.0], fills, TOLERANCE),
+            (1.0, 0.2)
+        );
+        diff.add_row(&[3.0, f64::NAN], &[-9999.0, f64::NAN], fills, TOLERANCE);
+        diff.finish();
+
+        assert_eq!(diff.compared, 4);
+        assert_eq!((diff.max_abs, diff.max_rel), (1.0, 0.2));
+        assert_eq!((diff.mean_abs, diff.mean_rel), (0.25, 0.05));
+        assert_eq!((diff.exceeding, diff.fill_mismatches), (1, 1));
+        assert!(!diff.passed());
+
+        // Within the absolute tolerance even though the relative difference is large
+        let mut near_zero = VariableDiff::default();
+        let loose = Tolerance {
+            absolute: 0.01,
+            ..TOLERANCE
+        };
+        near_zero.add_row(&[0.001], &[0.002], fills, loose);
+        assert!(near_zero.passed());
+    }
+
+    // One feature over two steps, with the given time units and values
+    fn write_output(path: &Path, units: &str, times: [f64; 2]) {
+        let mut file = netcdf::create(path).unwrap();
+        file.add_dimension("feature_id", 1).unwrap();
+        file.add_dimension("time", 2).unwrap();
+        let mut time = file.add_variable::<f64>("time", &["time"]).unwrap();
+        time.put_attribute("units", units).unwrap();
+        time.put_values(&times, ..).unwrap();
+        file.add_variable::<i64>("feature_id", &["feature_id"])
+            .unwrap()
+            .put_values(&[7], ..)
+            .unwrap();
+        file.add_variable::<f32>("flow", &["feature_id", "time"])
+            .unwrap()
+            .put_values(&[1.0, 2.0], ..)
+            .unwrap();
+    }
+
+    #[test]
+    fn outputs_on_different_time_axes_do_not_compare() {
+        let dir = std::env::temp_dir().join("route_rs_compare_time");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name);
+        let hourly = "seconds since 2020-01-01 00:00:00";
+        write_output(&path("a.nc"), hourly, [0.0, 3600.0]);
+        write_output(&path("same.nc"), hourly, [0.0, 3600.0]);
+        write_output(&path("shifted.nc"), hourly, [3600.0, 7200.0]);
+        write_output(
+            &path("later.nc"),
+            "seconds since 2020-01-02 00:00:00",
+            [0.0, 3600.0],
+        );
+
+        let same = compare_outputs(&path("a.nc"), &path("same.nc"), TOLERANCE, 5).unwrap();
+        assert!(same.passed());
+        let error = compare_outputs(&path("a.nc"), &path("shifted.nc"), TOLERANCE, 5)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("different times at step 0"), "{}", error);
+        let error = compare_outputs(&path("a.nc"), &path("later.nc"), TOLERANCE, 5)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("time units"), "{}", error);
+    }
+
+    #[test]
+    fn most_divergent_features_rank_by_relative_difference() {
+        let feature = |feature_id: u64, max_rel: f64| FeatureDiff {
+            feature_id,
+            variable: "flow".to_string(),
+            max_abs: 1.0,
+            max_rel,
+        };
+        let worst = HashMap::from([
+            (5, feature(5, 0.1)),
+            (2, feature(2, 0.5)),
+            (9, feature(9, 0.5)),
+        ]);
+        let ranked: Vec<u64> = most_divergent(worst, 2)
+            .iter()
+            .map(|feature| feature.feature_id)
+            .collect();
+        assert_eq!(ranked, vec![2, 9]);
+    }
+}
@@ -1,65 +1,517 @@
+use crate::config::{ForcingLayout, ReachType};
+use crate::console_println;
+use crate::network::{IdParser, parse_reach_type};
 use anyhow::{Context, Result};
-use csv::{ReaderBuilder, Writer, WriterBuilder};
-use std::collections::VecDeque;
+use csv::{Position, ReaderBuilder, Writer, WriterBuilder};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
 use std::io::BufReader;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-// Function to load external flows for a specific nexus/catchment
+// Function to load external flows for a specific nexus/catchment. `weight` is the
+// reach's share of the catchment runoff, applied before the area conversion. A missing
+// file gives no flows, as for reaches routed on upstream inflow alone.
 pub fn load_external_flows(
     csv_file: PathBuf,
-    id: &u32,
-    var_name: Option<&str>,
+    id: &u64,
     area: f32,
+    weight: f32,
 ) -> Result<VecDeque<f32>> {
-    let mut external_flows = Vec::new();
-
-    // Check if file exists, if not return empty flows
     if !csv_file.exists() {
-        println!(
+        console_println!(
             "No external flow file found for {}: {}",
             id,
             csv_file.display()
         );
-        return Ok(VecDeque::from(external_flows));
+        return Ok(VecDeque::new());
+    }
+    // ngen writes Q_OUT third, so files with other headers are read from there
+    read_timeseries_column(&csv_file, "Q_OUT", Some(2), |ql| {
+        runoff_to_flow(ql * weight, area)
+    })
+}
+
+// A CSV with a header row whose columns are found by name. `name` describes the file in
+// errors, e.g. "Invalid weight in lateral weights record 3".
+pub struct Table {
+    rdr: csv::Reader<BufReader<File>>,
+    headers: csv::StringRecord,
+    path: PathBuf,
+    name: &'static str,
+}
+
+impl Table {
+    pub fn open(path: &Path, name: &'static str) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open {} file: {}", name, path.display()))?;
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_reader(BufReader::new(file));
+        let headers = rdr
+            .headers()
+            .with_context(|| format!("Failed to read {} headers in {}", name, path.display()))?
+            .clone();
+        Ok(Table {
+            rdr,
+            headers,
+            path: path.to_path_buf(),
+            name,
+        })
+    }
+
+    pub fn column(&self, column: &str) -> Option<usize> {
+        self.headers.iter().position(|header| header == column)
+    }
+
+    pub fn required(&self, column: &str) -> Result<usize> {
+        self.column(column).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Column {} not found in {} (columns: {})",
+                column,
+                self.path.display(),
+                self.headers.iter().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+
+    // Call `row` on each record in turn, stopping at the first error
+    pub fn for_each(mut self, mut row: impl FnMut(&Row) -> Result<()>) -> Result<()> {
+        for (index, result) in self.rdr.records().enumerate() {
+            let record = result.with_context(|| {
+                format!(
+                    "Failed to read {} record {} in {}",
+                    self.name,
+                    index,
+                    self.path.display()
+                )
+            })?;
+            row(&Row {
+                record,
+                index,
+                name: self.name,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+// One record of a Table. Missing trailing fields read as blank.
+pub struct Row {
+    record: csv::StringRecord,
+    pub index: usize,
+    name: &'static str,
+}
+
+impl Row {
+    pub fn get(&self, column: usize) -> &str {
+        self.record.get(column).unwrap_or_default()
+    }
+
+    // Read a feature id with the network's id pattern, so it may keep its gpkg prefix
+    pub fn feature_id(&self, column: usize, id_parser: &IdParser) -> Result<u64> {
+        let value = self.get(column);
+        id_parser.parse(value).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Invalid feature_id '{}' in {} record {}",
+                value,
+                self.name,
+                self.index
+            )
+        })
+    }
+
+    // Parse a field, naming the column and record when it doesn't parse
+    pub fn parse<T>(&self, column: usize, what: &str) -> Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let value = self.get(column);
+        value.parse().with_context(|| {
+            format!(
+                "Invalid {} '{}' in {} record {}",
+                what, value, self.name, self.index
+            )
+        })
     }
+}
+
+// Read one column of a CSV time series with a header row, one value per record, passing
+// each value through `transform`. The column is found by name; without that header it is
+// read from `fallback_index` if given, and is otherwise an error.
+pub fn read_timeseries_column(
+    csv_file: &Path,
+    column: &str,
+    fallback_index: Option<usize>,
+    transform: impl Fn(f32) -> f32,
+) -> Result<VecDeque<f32>> {
+    let table = Table::open(csv_file, "time series")?;
+    let index = match table.column(column).or(fallback_index) {
+        Some(index) => index,
+        None => table.required(column)?,
+    };
+
+    let mut values = VecDeque::new();
+    table.for_each(|row| {
+        values.push_back(transform(row.parse(index, "value")?));
+        Ok(())
+    })?;
+    Ok(values)
+}
+
+// Convert an ngen Q_OUT value over a catchment of `area` km2 to m3/s
+// https://github.com/CIROH-UA/ngen/blob/ed2a903730467fa631716c033b757c3dff5fa2bb/include/core/Layer.hpp#L142
+pub fn runoff_to_flow(ql: f32, area: f32) -> f32 {
+    (ql * (area * 1_000_000.0)) / 3600.0
+}
 
-    let file = File::open(&csv_file)
-        .with_context(|| format!("Failed to open CSV file: {}", csv_file.display()))?;
-    let buffered_reader = BufReader::new(file);
+// Load a CSV of `feature_id,weight`: the share of its catchment's runoff each listed
+// reach receives. Reaches not listed get all of it. Ids are read with the network's id
+// pattern, so they may keep their gpkg prefix.
+pub fn load_lateral_weights(path: &Path, id_parser: &IdParser) -> Result<HashMap<u64, f32>> {
+    let table = Table::open(path, "lateral weights")?;
+    let (id_index, weight_index) = (table.required("feature_id")?, table.required("weight")?);
 
-    let mut rdr = ReaderBuilder::new()
+    let mut weights = HashMap::new();
+    table.for_each(|row| {
+        let feature_id = row.feature_id(id_index, id_parser)?;
+        let weight: f32 = row.parse(weight_index, "weight")?;
+        if !(weight >= 0.0 && weight.is_finite()) {
+            return Err(anyhow::anyhow!(
+                "Lateral weight must be non-negative in record {} (feature {})",
+                row.index,
+                feature_id
+            ));
+        }
+        if weights.insert(feature_id, weight).is_some() {
+            return Err(anyhow::anyhow!(
+                "Feature {} has more than one lateral weight",
+                feature_id
+            ));
+        }
+        Ok(())
+    })?;
+    Ok(weights)
+}
+
+// Per-reach type overrides from a CSV of feature_id,type, with ids read by the network's
+// id pattern
+pub fn load_reach_types(path: &Path, id_parser: &IdParser) -> Result<HashMap<u64, ReachType>> {
+    let table = Table::open(path, "reach types")?;
+    let (id_index, type_index) = (table.required("feature_id")?, table.required("type")?);
+
+    let mut types = HashMap::new();
+    table.for_each(|row| {
+        let feature_id = row.feature_id(id_index, id_parser)?;
+        let reach_type = parse_reach_type(row.get(type_index))
+            .with_context(|| format!("Invalid type in reach types record {}", row.index))?;
+        if types.insert(feature_id, reach_type).is_some() {
+            return Err(anyhow::anyhow!(
+                "Feature {} has more than one reach type",
+                feature_id
+            ));
+        }
+        Ok(())
+    })?;
+    Ok(types)
+}
+
+fn open_forcing(csv_file: &Path) -> Result<csv::Reader<BufReader<File>>> {
+    let file = File::open(csv_file)
+        .with_context(|| format!("Failed to open forcing file: {}", csv_file.display()))?;
+    Ok(ReaderBuilder::new()
         .has_headers(true)
-        .delimiter(b',')
-        .flexible(true)
         .trim(csv::Trim::All)
-        .from_reader(buffered_reader);
+        .from_reader(BufReader::new(file)))
+}
+
+fn parse_q_out(value: &str, row: usize, csv_file: &Path) -> Result<f32> {
+    value.parse::<f32>().with_context(|| {
+        format!(
+            "Failed to parse flow value '{}' in record {} of {}",
+            value,
+            row,
+            csv_file.display()
+        )
+    })
+}
+
+// Read raw Q_OUT series from a single CSV with a `Time,ID,Q_OUT` row per catchment and
+// timestep. Each catchment's rows must be in time order.
+pub fn load_long_forcing(csv_file: &Path, id_parser: &IdParser) -> Result<HashMap<u64, Vec<f32>>> {
+    read_long_forcing(csv_file, id_parser, |_| true, None)
+}
+
+// `load_long_forcing` for only the catchments `keep` accepts. With a `span` from the
+// forcing index, only the rows from its start up to its end byte are read.
+pub fn read_long_forcing(
+    csv_file: &Path,
+    id_parser: &IdParser,
+    keep: impl Fn(u64) -> bool,
+    span: Option<(Position, u64)>,
+) -> Result<HashMap<u64, Vec<f32>>> {
+    let mut rdr = open_forcing(csv_file)?;
+    let headers = rdr
+        .headers()
+        .context("Failed to read forcing headers")?
+        .clone();
+    let column = |name: &str| -> Result<usize> {
+        headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("Column {} not found in {}", name, csv_file.display()))
+    };
+    let (id_index, qlat_index) = (column("ID")?, column("Q_OUT")?);
+    let end = match span {
+        Some((start, end)) => {
+            rdr.seek(start)
+                .with_context(|| format!("Failed to seek in {}", csv_file.display()))?;
+            end
+        }
+        None => u64::MAX,
+    };
+
+    let mut forcing: HashMap<u64, Vec<f32>> = HashMap::new();
+    let mut record = csv::StringRecord::new();
+    loop {
+        // Records are numbered from the first data row, wherever the read starts
+        let i = rdr.position().record().saturating_sub(1) as usize;
+        if rdr.position().byte() >= end {
+            break;
+        }
+        let more = rdr.read_record(&mut record).with_context(|| {
+            format!("Failed to read record {} in file {}", i, csv_file.display())
+        })?;
+        if !more {
+            break;
+        }
+        let raw_id = record.get(id_index).unwrap_or_default();
+        let id = id_parser
+            .parse(raw_id)
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse id '{}' in record {}", raw_id, i))?;
+        if !keep(id) {
+            continue;
+        }
+        let ql = parse_q_out(record.get(qlat_index).unwrap_or_default(), i, csv_file)?;
+        forcing.entry(id).or_default().push(ql);
+    }
+
+    Ok(forcing)
+}
+
+// Read raw Q_OUT series from a single CSV with a time column and one column per catchment,
+// matching each column header to a catchment id
+pub fn load_wide_forcing(csv_file: &Path, id_parser: &IdParser) -> Result<HashMap<u64, Vec<f32>>> {
+    read_wide_forcing(csv_file, id_parser, |_| true)
+}
+
+// `load_wide_forcing` for only the catchments `keep` accepts; other columns aren't parsed
+pub fn read_wide_forcing(
+    csv_file: &Path,
+    id_parser: &IdParser,
+    keep: impl Fn(u64) -> bool,
+) -> Result<HashMap<u64, Vec<f32>>> {
+    let mut rdr = open_forcing(csv_file)?;
+    let headers = rdr
+        .headers()
+        .context("Failed to read forcing headers")?
+        .clone();
+    let time_index = headers
+        .iter()
+        .position(|h| h.eq_ignore_ascii_case("time"))
+        .ok_or_else(|| anyhow::anyhow!("Column time not found in {}", csv_file.display()))?;
+
+    let mut columns = Vec::new();
+    for (index, header) in headers.iter().enumerate() {
+        if index == time_index {
+            continue;
+        }
+        let id = id_parser.parse(header.trim()).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Column '{}' in {} is not a catchment id",
+                header,
+                csv_file.display()
+            )
+        })?;
+        if keep(id) {
+            columns.push((index, id));
+        }
+    }
 
-    let qlat_index = match var_name {
-        Some(var_name) => {
-            let headers = rdr.headers()
-                .context("Failed to read CSV headers")?;
-            headers.iter().position(|h| h == var_name).unwrap_or(2)
+    let mut forcing: HashMap<u64, Vec<f32>> = HashMap::new();
+    for (i, result) in rdr.records().enumerate() {
+        let record = result.with_context(|| {
+            format!("Failed to read record {} in file {}", i, csv_file.display())
+        })?;
+        for &(index, id) in &columns {
+            let ql = parse_q_out(record.get(index).unwrap_or_default(), i, csv_file)?;
+            forcing.entry(id).or_default().push(ql);
         }
-        None => 2,
+    }
+
+    Ok(forcing)
+}
+
+// What a single-file forcing holds, indexed without keeping any Q_OUT values
+#[derive(Debug, Default)]
+pub struct ForcingIndex {
+    pub covered: HashSet<u64>,
+    pub num_steps: usize,
+    // Long layout only: where each catchment's first row starts and the byte just past its
+    // last row, so a window of catchments is read from the file's span that holds them
+    pub spans: HashMap<u64, (Position, u64)>,
+}
+
+// The catchments a single-file forcing covers, its number of timesteps and, for the long
+// layout, where each catchment's rows are
+pub fn forcing_index(
+    csv_file: &Path,
+    layout: ForcingLayout,
+    id_parser: &IdParser,
+) -> Result<ForcingIndex> {
+    let mut rdr = open_forcing(csv_file)?;
+    let headers = rdr
+        .headers()
+        .context("Failed to read forcing headers")?
+        .clone();
+    let column = |name: &str| -> Result<usize> {
+        headers
+            .iter()
+            .position(|h| h.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow::anyhow!("Column {} not found in {}", name, csv_file.display()))
     };
 
+    if layout == ForcingLayout::Wide {
+        let time_index = column("time")?;
+        let mut ids = HashSet::new();
+        for (index, header) in headers.iter().enumerate() {
+            if index == time_index {
+                continue;
+            }
+            ids.insert(id_parser.parse(header.trim()).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Column '{}' in {} is not a catchment id",
+                    header,
+                    csv_file.display()
+                )
+            })?);
+        }
+        let mut steps = 0;
+        for (i, result) in rdr.records().enumerate() {
+            result.with_context(|| {
+                format!("Failed to read record {} in file {}", i, csv_file.display())
+            })?;
+            steps += 1;
+        }
+        return Ok(ForcingIndex {
+            covered: ids,
+            num_steps: steps,
+            spans: HashMap::new(),
+        });
+    }
+
+    // Long forcing has one row per catchment and timestep
+    let id_index = column("ID")?;
+    let mut steps: HashMap<u64, usize> = HashMap::new();
+    let mut spans: HashMap<u64, (Position, u64)> = HashMap::new();
+    let mut record = csv::StringRecord::new();
+    for i in 0.. {
+        let start = rdr.position().clone();
+        if !rdr.read_record(&mut record).with_context(|| {
+            format!("Failed to read record {} in file {}", i, csv_file.display())
+        })? {
+            break;
+        }
+        let raw_id = record.get(id_index).unwrap_or_default();
+        let id = id_parser
+            .parse(raw_id)
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse id '{}' in record {}", raw_id, i))?;
+        *steps.entry(id).or_default() += 1;
+        spans.entry(id).or_insert((start, 0)).1 = rdr.position().byte();
+    }
+    let num_steps = steps.values().copied().max().unwrap_or(0);
+    Ok(ForcingIndex {
+        covered: steps.into_keys().collect(),
+        num_steps,
+        spans,
+    })
+}
+
+// Number of records in a per-catchment forcing file, counted the way they are read when
+// routing, or None if the file doesn't exist
+pub fn count_forcing_rows(csv_file: &Path) -> Result<Option<usize>> {
+    if !csv_file.exists() {
+        return Ok(None);
+    }
+    let mut rdr = open_forcing(csv_file)?;
+    let mut rows = 0;
     for (i, result) in rdr.records().enumerate() {
-        let record = result
-            .with_context(|| format!("Failed to read record {} in file {}", i, csv_file.display()))?;
-        
-        let ql_str = record.get(qlat_index)
-            .ok_or_else(|| anyhow::anyhow!("Missing column {} in record {}", qlat_index, i))?;
-        
-        let ql = ql_str.trim().parse::<f32>()
-            .with_context(|| format!("Failed to parse flow value '{}' in record {}", ql_str, i))?;
+        result.with_context(|| {
+            format!("Failed to read record {} in file {}", i, csv_file.display())
+        })?;
+        rows += 1;
+    }
+    Ok(Some(rows))
+}
+
+// The `row`-th distinct value in a forcing file's time column (`time` in any case, else the
+// second column as in ngen's per-catchment output), or None if there are fewer distinct times
+pub fn forcing_time(csv_file: &Path, row: usize) -> Result<Option<String>> {
+    let mut rdr = open_forcing(csv_file)?;
+    let headers = rdr
+        .headers()
+        .context("Failed to read forcing headers")?
+        .clone();
+    let time_index = headers
+        .iter()
+        .position(|h| h.eq_ignore_ascii_case("time"))
+        .unwrap_or(1);
 
-        // https://github.com/CIROH-UA/ngen/blob/ed2a903730467fa631716c033b757c3dff5fa2bb/include/core/Layer.hpp#L142
-        let adjusted_flow = (ql * (area * 1_000_000.0)) / 3600.0;
-        external_flows.push(adjusted_flow);
+    // Long forcing repeats each time once per catchment
+    let mut seen: Vec<String> = Vec::new();
+    for (i, result) in rdr.records().enumerate() {
+        let record = result.with_context(|| {
+            format!("Failed to read record {} in file {}", i, csv_file.display())
+        })?;
+        let time = record.get(time_index).unwrap_or_default();
+        if seen.iter().any(|seen_time| seen_time == time) {
+            continue;
+        }
+        if seen.len() == row {
+            return Ok(Some(time.to_string()));
+        }
+        seen.push(time.to_string());
+    }
+    Ok(None)
+}
+
+// A value for a CSV report, rounded to `digits` significant digits (at least one) with
+// trailing zeros dropped, so 0.5 stays `0.5` rather than `0.500000` and 1e-7 isn't `0`
+pub fn format_float(value: f32, digits: usize) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    // Round in scientific notation first, so the exponent accounts for rounding up,
+    // e.g. 9.999999 to 10.0000
+    let scientific = format!("{:.*e}", digits.max(1) - 1, value as f64);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let rounded: f64 = scientific.parse().unwrap_or(value as f64);
+    let decimals = (mantissa.trim_start_matches('-').len() as i32 - 2 - exponent).max(0);
+    let formatted = format!("{:.*}", decimals as usize, rounded);
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
     }
-    
-    Ok(VecDeque::from(external_flows))
 }
 
 // Create CSV writer with headers
@@ -70,8 +522,152 @@ pub fn create_csv_writer(path: &str) -> Result<Writer<File>> {
         .with_context(|| format!("Failed to create CSV writer at {}", path))?;
 
     // Write header
-    wtr.write_record(&["step", "feature_id", "flow", "velocity", "depth"])
+    wtr.write_record(["step", "feature_id", "flow", "velocity", "depth"])
         .context("Failed to write CSV header")?;
 
     Ok(wtr)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeseries_columns_are_read_by_name_or_fallback_position() {
+        let dir = std::env::temp_dir().join("route_rs_timeseries_column");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("series.csv");
+        std::fs::write(&path, "time, obs ,flag\n0, 1.5 ,a\n1,2.5,b\n").unwrap();
+
+        let doubled = read_timeseries_column(&path, "obs", None, |v| v * 2.0).unwrap();
+        assert_eq!(doubled, [3.0, 5.0]);
+        let fallback = read_timeseries_column(&path, "Q_OUT", Some(1), |v| v).unwrap();
+        assert_eq!(fallback, [1.5, 2.5]);
+
+        let error = read_timeseries_column(&path, "Q_OUT", None, |v| v)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("columns: time, obs, flag"), "{}", error);
+        assert!(read_timeseries_column(&path, "flag", None, |v| v).is_err());
+    }
+
+    #[test]
+    fn report_floats_are_rounded_without_trailing_zeros() {
+        assert_eq!(format_float(1.234_567_9, 6), "1.23457");
+        assert_eq!(format_float(0.5, 6), "0.5");
+        assert_eq!(format_float(1000.0, 6), "1000");
+        assert_eq!(format_float(123_456_790.0, 6), "123457000");
+        assert_eq!(format_float(1e-7, 6), "0.0000001");
+        assert_eq!(format_float(-0.000_012_345, 3), "-0.0000123");
+        assert_eq!(format_float(9.999_999, 6), "10");
+        assert_eq!(format_float(12.75, 0), "10");
+        assert_eq!(format_float(-0.0, 6), "0");
+        assert_eq!(format_float(f32::NAN, 6), "NaN");
+    }
+
+    #[test]
+    fn wide_and_long_forcing_load_the_same_series() {
+        let dir = std::env::temp_dir().join("route_rs_forcing_layouts");
+        std::fs::create_dir_all(&dir).unwrap();
+        let wide = dir.join("wide.csv");
+        std::fs::write(&wide, "time,cat-7,cat-12\n0,0.5,1.0\n1,0.25,2.0\n").unwrap();
+        let long = dir.join("long.csv");
+        std::fs::write(
+            &long,
+            "Time,ID,Q_OUT\n0,cat-7,0.5\n0,cat-12,1.0\n1,cat-7,0.25\n1,cat-12,2.0\n",
+        )
+        .unwrap();
+
+        let id_parser = IdParser::new(r"^\D*?0*(\d+)$").unwrap();
+        let from_wide = load_wide_forcing(&wide, &id_parser).unwrap();
+        assert_eq!(from_wide[&7], vec![0.5, 0.25]);
+        assert_eq!(from_wide[&12], vec![1.0, 2.0]);
+        assert_eq!(load_long_forcing(&long, &id_parser).unwrap(), from_wide);
+        assert_eq!(forcing_time(&long, 1).unwrap().as_deref(), Some("1"));
+        assert_eq!(forcing_time(&wide, 1).unwrap().as_deref(), Some("1"));
+        assert_eq!(forcing_time(&long, 2).unwrap(), None);
+
+        std::fs::write(&wide, "time,cat-7,outlet\n0,0.5,1.0\n").unwrap();
+        let error = load_wide_forcing(&wide, &id_parser)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("'outlet'"), "{}", error);
+    }
+
+    #[test]
+    fn lateral_weights_must_be_non_negative_and_unique() {
+        let path = std::env::temp_dir().join("route_rs_lateral_weights.csv");
+        let id_parser = IdParser::new(r"^\D*?0*(\d+)$").unwrap();
+        std::fs::write(&path, "feature_id,weight\n7,0.25\n8,0.75\n").unwrap();
+        let weights = load_lateral_weights(&path, &id_parser).unwrap();
+        assert_eq!(weights, HashMap::from([(7, 0.25), (8, 0.75)]));
+
+        std::fs::write(&path, "feature_id,weight\n7,-0.5\n").unwrap();
+        assert!(load_lateral_weights(&path, &id_parser).is_err());
+        std::fs::write(&path, "feature_id,weight\n7,0.5\n7,0.5\n").unwrap();
+        assert!(load_lateral_weights(&path, &id_parser).is_err());
+        std::fs::write(&path, "feature_id,weight\n7,half\n").unwrap();
+        let error = format!("{:#}", load_lateral_weights(&path, &id_parser).unwrap_err());
+        assert!(
+            error.contains("Invalid weight 'half' in lateral weights record 0"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn lateral_weight_ids_are_read_with_the_id_pattern() {
+        let path = std::env::temp_dir().join("route_rs_lateral_weights_pattern.csv");
+        std::fs::write(&path, "feature_id,weight\nreach_0007,0.25\nreach_8,0.75\n").unwrap();
+        let id_parser = IdParser::new(r"^reach_0*(\d+)$").unwrap();
+        let weights = load_lateral_weights(&path, &id_parser).unwrap();
+        assert_eq!(weights, HashMap::from([(7, 0.25), (8, 0.75)]));
+
+        let error = load_lateral_weights(&path, &IdParser::new(r"^wb-(\d+)$").unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("'reach_0007'"), "{}", error);
+    }
+
+    #[test]
+    fn reach_types_parse_case_insensitively_and_reject_unknown_types() {
+        let path = std::env::temp_dir().join("route_rs_reach_types.csv");
+        let id_parser = IdParser::new(r"^\D*?0*(\d+)$").unwrap();
+        std::fs::write(&path, "feature_id,type\nwb-7,Waterbody\n8,junction\n").unwrap();
+        let types = load_reach_types(&path, &id_parser).unwrap();
+        assert_eq!(
+            types,
+            HashMap::from([(7, ReachType::Waterbody), (8, ReachType::Junction)])
+        );
+
+        std::fs::write(&path, "feature_id,type\n7,lake\n").unwrap();
+        assert!(load_reach_types(&path, &id_parser).is_err());
+        std::fs::write(&path, "feature_id,type\n7,channel\n7,junction\n").unwrap();
+        assert!(load_reach_types(&path, &id_parser).is_err());
+    }
+
+    #[test]
+    fn forcing_rows_ignore_line_endings_and_blank_lines() {
+        let dir = std::env::temp_dir().join("route_rs_forcing_rows");
+        std::fs::create_dir_all(&dir).unwrap();
+        let rows = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            count_forcing_rows(&path).unwrap()
+        };
+
+        for (name, contents) in [
+            ("trailing.csv", "Time,ID,Q_OUT\n0,cat-1,0.5\n1,cat-1,1.0\n"),
+            ("no_trailing.csv", "Time,ID,Q_OUT\n0,cat-1,0.5\n1,cat-1,1.0"),
+            (
+                "crlf.csv",
+                "Time,ID,Q_OUT\r\n0,cat-1,0.5\r\n1,cat-1,1.0\r\n",
+            ),
+            ("blank.csv", "Time,ID,Q_OUT\n0,cat-1,0.5\n\n1,cat-1,1.0\n\n"),
+        ] {
+            assert_eq!(rows(name, contents), Some(2), "{}", name);
+        }
+        assert_eq!(rows("header_only.csv", "Time,ID,Q_OUT\n"), Some(0));
+        assert_eq!(count_forcing_rows(&dir.join("missing.csv")).unwrap(), None);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::config::ForcingLayout;
+use crate::io::csv::{ForcingIndex, forcing_index, read_long_forcing, read_wide_forcing};
+use crate::network::IdParser;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+// Single-file forcing read a window of reaches at a time as they're dispatched for routing,
+// so forcing memory tracks the routing front rather than the whole domain. A wide window
+// costs one pass over the file; a long window reads only the span of rows holding its
+// reaches, found from the offsets recorded when the file was indexed.
+#[derive(Debug)]
+pub struct ForcingWindow {
+    path: PathBuf,
+    layout: ForcingLayout,
+    id_parser: IdParser,
+    size: usize,
+    index: ForcingIndex,
+    state: Mutex<WindowState>,
+    // Signalled whenever a window finishes reading
+    read_done: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct WindowState {
+    // Dispatched reaches whose forcing isn't loaded yet, in dispatch order. Reaches leave
+    // `queued` when loaded or released; their stale `pending` entries are skipped.
+    pending: VecDeque<u64>,
+    queued: HashSet<u64>,
+    // Raw Q_OUT read for reaches that haven't been routed yet
+    loaded: HashMap<u64, Vec<f32>>,
+    // Reaches in a window being read right now, without the lock held
+    reading: HashSet<u64>,
+}
+
+impl ForcingWindow {
+    // Index the file (which catchments it covers and its length) without keeping any values
+    pub fn open(
+        path: &Path,
+        layout: ForcingLayout,
+        id_parser: IdParser,
+        size: usize,
+    ) -> Result<Self> {
+        let index = forcing_index(path, layout, &id_parser)?;
+        Ok(ForcingWindow {
+            path: path.to_path_buf(),
+            layout,
+            id_parser,
+            size: size.max(1),
+            index,
+            state: Mutex::new(WindowState::default()),
+            read_done: Condvar::new(),
+        })
+    }
+
+    pub fn covers(&self, id: u64) -> bool {
+        self.index.covered.contains(&id)
+    }
+
+    pub fn covered(&self) -> &HashSet<u64> {
+        &self.index.covered
+    }
+
+    pub fn num_steps(&self) -> usize {
+        self.index.num_steps
+    }
+
+    // Queue a reach that's about to be routed so it's read with the next window
+    pub fn request(&self, id: u64) -> Result<()> {
+        if !self.covers(id) {
+            return Ok(());
+        }
+        let mut state = self.lock()?;
+        if !state.loaded.contains_key(&id)
+            && !state.reading.contains(&id)
+            && state.queued.insert(id)
+        {
+            state.pending.push_back(id);
+        }
+        Ok(())
+    }
+
+    // A reach's raw Q_OUT, reading it with the next window of queued reaches if it
+    // isn't loaded yet. The file is read without the lock held, so other workers keep
+    // queueing and taking loaded reaches meanwhile; one whose reach is in the window
+    // being read waits for it.
+    pub fn take(&self, id: u64) -> Result<Option<Vec<f32>>> {
+        if !self.covers(id) {
+            return Ok(None);
+        }
+        let mut state = self.lock()?;
+        while state.reading.contains(&id) {
+            state = self
+                .read_done
+                .wait(state)
+                .map_err(|e| anyhow::anyhow!("Failed to lock forcing window: {}", e))?;
+        }
+        if let Some(q_out) = state.loaded.remove(&id) {
+            return Ok(Some(q_out));
+        }
+
+        state.queued.remove(&id);
+        let mut window = HashSet::from([id]);
+        while window.len() < self.size
+            && let Some(next) = state.pending.pop_front()
+        {
+            if state.queued.remove(&next) {
+                window.insert(next);
+            }
+        }
+        state.reading.extend(&window);
+        drop(state);
+
+        let forcing = self.read(&window);
+
+        let mut state = self.lock()?;
+        // Reaches released while their window was read are no longer wanted
+        let wanted: HashSet<u64> = window
+            .iter()
+            .copied()
+            .filter(|reach| state.reading.remove(reach))
+            .collect();
+        self.read_done.notify_all();
+        for (reach, q_out) in forcing? {
+            if wanted.contains(&reach) {
+                state.loaded.insert(reach, q_out);
+            }
+        }
+        Ok(state.loaded.remove(&id))
+    }
+
+    // Read the window's reaches from the file, the long layout from just the span of
+    // rows that holds them
+    fn read(&self, window: &HashSet<u64>) -> Result<HashMap<u64, Vec<f32>>> {
+        let keep = |id: u64| window.contains(&id);
+        if self.layout == ForcingLayout::Wide {
+            return read_wide_forcing(&self.path, &self.id_parser, keep);
+        }
+        let spans = window.iter().filter_map(|id| self.index.spans.get(id));
+        let start = spans
+            .clone()
+            .map(|(start, _)| start)
+            .min_by_key(|start| start.byte());
+        let end = spans.map(|&(_, end)| end).max();
+        let span = start.cloned().zip(end);
+        read_long_forcing(&self.path, &self.id_parser, keep, span)
+    }
+
+    // Drop anything held for a reach that has been routed, skipped or failed
+    pub fn release(&self, id: u64) -> Result<()> {
+        let mut state = self.lock()?;
+        state.queued.remove(&id);
+        state.loaded.remove(&id);
+        state.reading.remove(&id);
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, WindowState>> {
+        self.state
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock forcing window: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::csv::{load_long_forcing, load_wide_forcing};
+
+    #[test]
+    fn windows_hold_at_most_their_size_and_match_full_loading() {
+        let dir = std::env::temp_dir().join("route_rs_forcing_window");
+        std::fs::create_dir_all(&dir).unwrap();
+        let wide = dir.join("wide.csv");
+        std::fs::write(
+            &wide,
+            "time,cat-1,cat-2,cat-3,cat-4\n0,0.1,0.2,0.3,0.4\n1,1.1,1.2,1.3,1.4\n",
+        )
+        .unwrap();
+        let id_parser = IdParser::new(r"^\D*?0*(\d+)$").unwrap();
+        let all = load_wide_forcing(&wide, &id_parser).unwrap();
+
+        let window = ForcingWindow::open(&wide, ForcingLayout::Wide, id_parser, 2).unwrap();
+        assert_eq!(window.num_steps(), 2);
+        assert!(window.covers(3) && !window.covers(5));
+
+        for id in [4, 2, 3, 1] {
+            window.request(id).unwrap();
+        }
+        // Reading 4 also reads the next queued reach, and nothing else
+        assert_eq!(window.take(4).unwrap().as_ref(), Some(&all[&4]));
+        assert_eq!(window.state.lock().unwrap().loaded.len(), 1);
+        for id in [2, 3, 1] {
+            assert_eq!(window.take(id).unwrap().as_ref(), Some(&all[&id]));
+            assert!(window.state.lock().unwrap().loaded.len() <= 2);
+        }
+        assert_eq!(window.take(5).unwrap(), None);
+    }
+
+    #[test]
+    fn long_windows_read_only_the_rows_of_their_reaches() {
+        let dir = std::env::temp_dir().join("route_rs_forcing_window_long");
+        std::fs::create_dir_all(&dir).unwrap();
+        let long = dir.join("long.csv");
+        let mut contents = String::from("Time,ID,Q_OUT\n");
+        for id in 1..=4 {
+            for step in 0..3 {
+                contents.push_str(&format!("{},cat-{},{}.{}\n", step, id, id, step));
+            }
+        }
+        std::fs::write(&long, contents).unwrap();
+        let id_parser = IdParser::new(r"^\D*?0*(\d+)$").unwrap();
+        let all = load_long_forcing(&long, &id_parser).unwrap();
+
+        let window = ForcingWindow::open(&long, ForcingLayout::Long, id_parser, 2).unwrap();
+        assert_eq!(window.num_steps(), 3);
+
+        // Reaches 2 and 3 sit between 1 and 4, so their span holds none of the others' rows
+        let read = window.read(&HashSet::from([2, 3])).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[&2], all[&2]);
+        assert_eq!(read[&3], all[&3]);
+
+        for id in [3, 1, 4, 2] {
+            window.request(id).unwrap();
+        }
+        for id in [3, 1, 4, 2] {
+            assert_eq!(window.take(id).unwrap().as_ref(), Some(&all[&id]));
+        }
+        let state = window.state.lock().unwrap();
+        assert!(state.loaded.is_empty() && state.reading.is_empty());
+    }
+}
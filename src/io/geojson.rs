@@ -0,0 +1,358 @@
+use crate::config::ColumnConfig;
+use crate::console_println;
+use crate::network::IdParser;
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// A reach's geometry: one or more linestrings of (x, y) in the gpkg's coordinate system
+pub type ReachLines = Vec<Vec<[f64; 2]>>;
+
+// Byte sizes of the envelope a GeoPackage geometry header can carry, by its indicator
+const ENVELOPE_SIZES: [usize; 5] = [0, 32, 48, 48, 64];
+
+struct WkbReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    little_endian: bool,
+}
+
+impl WkbReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| anyhow::anyhow!("Geometry ends after {} bytes", self.bytes.len()))?;
+        self.pos += N;
+        Ok(bytes.try_into()?)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.take::<4>()?;
+        Ok(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        let bytes = self.take::<8>()?;
+        Ok(if self.little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.pos)
+    }
+
+    // One WKB (multi)linestring, appending its parts to `lines`. Counts are checked against
+    // the bytes left before anything is read, so a corrupt header can't claim more points
+    // than the blob holds; the parts of a multilinestring must be plain linestrings.
+    fn read_lines(&mut self, lines: &mut ReachLines, part: bool) -> Result<()> {
+        self.little_endian = self.take::<1>()?[0] == 1;
+        let geometry_type = self.read_u32()?;
+        // ISO WKB adds 1000 for Z, 2000 for M and 3000 for both
+        let ordinates = match geometry_type / 1000 {
+            0 => 2,
+            1 | 2 => 3,
+            3 => 4,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Unknown WKB geometry type {}",
+                    geometry_type
+                ));
+            }
+        };
+        match geometry_type % 1000 {
+            2 => {
+                let points = self.read_u32()? as usize;
+                if points.saturating_mul(ordinates * 8) > self.remaining() {
+                    return Err(anyhow::anyhow!(
+                        "Linestring claims {} points but only {} bytes remain",
+                        points,
+                        self.remaining()
+                    ));
+                }
+                let mut line = Vec::new();
+                for _ in 0..points {
+                    let x = self.read_f64()?;
+                    let y = self.read_f64()?;
+                    for _ in 2..ordinates {
+                        self.read_f64()?;
+                    }
+                    line.push([x, y]);
+                }
+                lines.push(line);
+            }
+            5 if !part => {
+                for _ in 0..self.read_u32()? {
+                    self.read_lines(lines, true)?;
+                }
+            }
+            other => {
+                return Err(anyhow::anyhow!(
+                    "Expected a linestring or multilinestring, found WKB geometry type {}",
+                    other
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Parse a GeoPackage geometry blob: a `GP` header, optionally with an envelope, then WKB.
+// Z and M ordinates are dropped.
+pub fn parse_gpkg_lines(blob: &[u8]) -> Result<ReachLines> {
+    if blob.len() < 8 || &blob[..2] != b"GP" {
+        return Err(anyhow::anyhow!("Not a GeoPackage geometry"));
+    }
+    let flags = blob[3];
+    let mut lines = Vec::new();
+    // An empty geometry has nothing after the header
+    if flags & 0x10 != 0 {
+        return Ok(lines);
+    }
+    let envelope = ENVELOPE_SIZES
+        .get(((flags >> 1) & 0x07) as usize)
+        .ok_or_else(|| anyhow::anyhow!("Invalid GeoPackage envelope flags {:#x}", flags))?;
+    let wkb = blob.get(8 + envelope..).ok_or_else(|| {
+        anyhow::anyhow!(
+            "GeoPackage geometry of {} bytes is shorter than its {} byte envelope",
+            blob.len(),
+            envelope
+        )
+    })?;
+    let mut reader = WkbReader {
+        bytes: wkb,
+        pos: 0,
+        little_endian: true,
+    };
+    reader.read_lines(&mut lines, false)?;
+    Ok(lines)
+}
+
+// Geometry of each flowpath, and the coordinate system's `organization::code`, e.g. EPSG::5070
+pub fn load_flowpath_geometry(
+    conn: &Connection,
+    config: &ColumnConfig,
+) -> Result<(HashMap<u64, ReachLines>, Option<String>)> {
+    let (column, srs_id): (String, i64) = conn
+        .query_row(
+            "SELECT column_name, srs_id FROM gpkg_geometry_columns WHERE table_name = 'flowpaths'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .context("Failed to read gpkg_geometry_columns")?
+        .ok_or_else(|| anyhow::anyhow!("The gpkg lists no geometry column for flowpaths"))?;
+    let crs = conn
+        .query_row(
+            "SELECT organization, organization_coordsys_id FROM gpkg_spatial_ref_sys WHERE srs_id = ?1",
+            [srs_id],
+            |row| Ok(format!("{}::{}", row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()
+        .context("Failed to read gpkg_spatial_ref_sys")?;
+
+    let id_parser = IdParser::new(&config.id_pattern)?;
+    let query = format!("SELECT {}, \"{}\" FROM 'flowpaths'", config.key, column);
+    let mut stmt = conn
+        .prepare(&query)
+        .with_context(|| format!("Failed to read flowpath geometry column {}", column))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<Vec<u8>>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to read flowpath geometry")?;
+
+    let mut geometry = HashMap::new();
+    for (raw_id, blob) in rows {
+        let Some(blob) = blob else {
+            continue;
+        };
+        let id = id_parser
+            .parse(&raw_id)
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse id from {}", raw_id))?;
+        let lines = parse_gpkg_lines(&blob).with_context(|| format!("Flowpath {}", raw_id))?;
+        geometry.insert(id, lines);
+    }
+    Ok((geometry, crs))
+}
+
+// Write each routed reach's geometry with its `peak_flow` (m3/s) as a GeoJSON
+// FeatureCollection. Coordinates stay in the gpkg's coordinate system, which is named in
+// the collection's `crs` member for GIS tools that read it.
+pub fn write_peak_flow_geojson(
+    conn: &Connection,
+    config: &ColumnConfig,
+    peaks: &HashMap<u64, f32>,
+    path: &Path,
+) -> Result<()> {
+    let (geometry, crs) = load_flowpath_geometry(conn, config)?;
+
+    let mut ids: Vec<u64> = peaks.keys().copied().collect();
+    ids.sort_unstable();
+    let mut missing = 0;
+    let mut features = Vec::with_capacity(ids.len());
+    for id in ids {
+        let Some(lines) = geometry.get(&id) else {
+            missing += 1;
+            continue;
+        };
+        let shape = match lines.as_slice() {
+            [line] => json!({ "type": "LineString", "coordinates": line }),
+            _ => json!({ "type": "MultiLineString", "coordinates": lines }),
+        };
+        features.push(json!({
+            "type": "Feature",
+            "geometry": shape,
+            "properties": { "feature_id": id, "peak_flow": peaks[&id] },
+        }));
+    }
+
+    let mut collection = json!({ "type": "FeatureCollection", "features": features });
+    if let Some(crs) = &crs {
+        collection["crs"] = json!({
+            "type": "name",
+            "properties": { "name": format!("urn:ogc:def:crs:{}", crs) },
+        });
+    }
+
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create GeoJSON file: {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, &collection)
+        .with_context(|| format!("Failed to write GeoJSON to {}", path.display()))?;
+    writer.flush().context("Failed to flush GeoJSON file")?;
+
+    console_println!(
+        "Peak flows for {} reaches saved to {}",
+        features.len(),
+        path.display()
+    );
+    if missing > 0 {
+        console_println!(
+            "{} routed reaches have no geometry and were left out",
+            missing
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    // GeoPackage header with no envelope, then a little-endian WKB linestring
+    fn line_blob(points: &[[f64; 2]]) -> Vec<u8> {
+        let mut blob = vec![b'G', b'P', 0, 0x01, 0, 0, 0, 0];
+        blob.push(1);
+        blob.extend(2u32.to_le_bytes());
+        blob.extend((points.len() as u32).to_le_bytes());
+        for [x, y] in points {
+            blob.extend(x.to_le_bytes());
+            blob.extend(y.to_le_bytes());
+        }
+        blob
+    }
+
+    #[test]
+    fn parses_linestrings_with_envelopes_and_z() {
+        assert_eq!(
+            parse_gpkg_lines(&line_blob(&[[1.0, 2.0], [3.0, 4.0]])).unwrap(),
+            vec![vec![[1.0, 2.0], [3.0, 4.0]]]
+        );
+
+        // Big-endian MultiLineString Z behind a 32-byte xy envelope
+        let mut blob = vec![b'G', b'P', 0, 0x02, 0, 0, 0, 0];
+        blob.extend([0; 32]);
+        blob.push(0);
+        blob.extend(1005u32.to_be_bytes());
+        blob.extend(2u32.to_be_bytes());
+        for offset in [0.0, 10.0] {
+            blob.push(0);
+            blob.extend(1002u32.to_be_bytes());
+            blob.extend(2u32.to_be_bytes());
+            for value in [offset, 1.0, 99.0, offset + 1.0, 2.0, 99.0] {
+                blob.extend(f64::to_be_bytes(value));
+            }
+        }
+        assert_eq!(
+            parse_gpkg_lines(&blob).unwrap(),
+            vec![vec![[0.0, 1.0], [1.0, 2.0]], vec![[10.0, 1.0], [11.0, 2.0]]]
+        );
+
+        assert!(parse_gpkg_lines(b"not a geometry").is_err());
+    }
+
+    #[test]
+    fn truncated_geometries_are_rejected_before_reading() {
+        // A 64-byte envelope flag on a blob with nothing after the header
+        assert!(parse_gpkg_lines(&[b'G', b'P', 0, 0x08, 0, 0, 0, 0]).is_err());
+
+        // A point count far beyond the bytes that follow
+        let mut blob = line_blob(&[[1.0, 2.0]]);
+        blob[13..17].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = parse_gpkg_lines(&blob).unwrap_err().to_string();
+        assert!(error.contains("claims 4294967295 points"), "{}", error);
+
+        // A multilinestring nested inside a multilinestring
+        let mut blob = vec![b'G', b'P', 0, 0x01, 0, 0, 0, 0];
+        for _ in 0..2 {
+            blob.push(1);
+            blob.extend(5u32.to_le_bytes());
+            blob.extend(1u32.to_le_bytes());
+        }
+        assert!(parse_gpkg_lines(&blob).is_err());
+    }
+
+    #[test]
+    fn writes_peak_flows_onto_flowpath_lines() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        conn.execute_batch(
+            "ALTER TABLE 'flowpaths' ADD COLUMN geom BLOB;
+             CREATE TABLE gpkg_geometry_columns (table_name TEXT, column_name TEXT, srs_id INTEGER);
+             INSERT INTO gpkg_geometry_columns VALUES ('flowpaths', 'geom', 5070);
+             CREATE TABLE gpkg_spatial_ref_sys (srs_id INTEGER, organization TEXT, organization_coordsys_id INTEGER);
+             INSERT INTO gpkg_spatial_ref_sys VALUES (5070, 'EPSG', 5070);",
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE 'flowpaths' SET geom = ?1 WHERE id = 'wb-1'",
+            [line_blob(&[[0.0, 0.0], [5.0, 5.0]])],
+        )
+        .unwrap();
+
+        let path = std::env::temp_dir().join("route_rs_peak_flow.geojson");
+        let peaks = HashMap::from([(1, 12.5), (2, 3.0)]);
+        write_peak_flow_geojson(&conn, &config, &peaks, &path).unwrap();
+
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            written["crs"]["properties"]["name"],
+            "urn:ogc:def:crs:EPSG::5070"
+        );
+        // Reach 2 has no geometry
+        let features = written["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(features[0]["properties"]["feature_id"], 1);
+        assert_eq!(features[0]["properties"]["peak_flow"], 12.5);
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(
+            features[0]["geometry"]["coordinates"],
+            json!([[0.0, 0.0], [5.0, 5.0]])
+        );
+    }
+}
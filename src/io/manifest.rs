@@ -0,0 +1,89 @@
+use crate::console_println;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde_json::{Map, Value, json};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+// Provenance of a run: inputs, effective options, build, timing, and the policy decisions
+// made along the way, written next to the outputs once routing succeeds
+pub struct RunManifest {
+    path: PathBuf,
+    started: DateTime<Local>,
+    clock: Instant,
+    entries: Map<String, Value>,
+}
+
+impl RunManifest {
+    pub fn start(path: PathBuf) -> Self {
+        RunManifest {
+            path,
+            started: Local::now(),
+            clock: Instant::now(),
+            entries: Map::new(),
+        }
+    }
+
+    pub fn record(&mut self, key: &str, value: Value) {
+        self.entries.insert(key.to_string(), value);
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let mut manifest = json!({
+            "route_rs_version": env!("CARGO_PKG_VERSION"),
+            "git_hash": env!("ROUTE_RS_GIT_HASH"),
+            "started": self.started.to_rfc3339(),
+            "finished": Local::now().to_rfc3339(),
+            "wall_seconds": self.clock.elapsed().as_secs_f64(),
+        });
+        if let Value::Object(fields) = &mut manifest {
+            fields.extend(self.entries);
+        }
+        let content = serde_json::to_string_pretty(&manifest)?;
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write run manifest to {}", self.path.display()))?;
+        console_println!("Run manifest saved to {}", self.path.display());
+        Ok(())
+    }
+}
+
+// 64-bit FNV-1a of a file's contents as hex. Unlike the std hasher it is stable across
+// Rust releases, so a manifest can be checked against the inputs long after the run.
+pub fn file_digest(path: &Path) -> Result<String> {
+    let mut file =
+        std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut buffer = vec![0u8; 1 << 16];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buffer[..read] {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    Ok(format!("{:016x}", hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_matches_the_fnv1a_reference_values() {
+        let dir = std::env::temp_dir().join(format!("route_rs_digest_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty");
+        let text = dir.join("text");
+        std::fs::write(&empty, b"").unwrap();
+        std::fs::write(&text, b"foobar").unwrap();
+
+        assert_eq!(file_digest(&empty).unwrap(), "cbf29ce484222325");
+        assert_eq!(file_digest(&text).unwrap(), "85944171f73967e8");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,15 @@
+pub mod aggregate;
+pub mod cache;
+pub mod compare;
 pub mod csv;
+pub mod forcing_window;
+#[cfg(feature = "geometry")]
+pub mod geojson;
+pub mod manifest;
 pub mod netcdf;
+pub mod nudge;
 pub mod results;
+pub mod roughness;
+pub mod stats;
+pub mod timings;
+pub mod velocity;
@@ -1,77 +1,507 @@
+use crate::config::{OutputOptions, OutputSplit, OutputVariable};
 use crate::io::results::SimulationResults;
+use crate::mc_kernel::{MAX_ITERATIONS, MIN_DEPTH, RELATIVE_TOLERANCE};
 use anyhow::{Context, Result};
-use chrono::NaiveDateTime;
-use netcdf::{self, FileMut};
+use chrono::{Duration, NaiveDateTime};
+use netcdf::{self, AttributeValue, FileMut};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
+const NWM_TIME_UNITS: &str = "minutes since 1970-01-01 00:00:00 UTC";
+// Global attribute holding the run's input hash, also recorded in the run manifest
+const INPUT_HASH_ATTRIBUTE: &str = "route_rs_input_hash";
+
+// One output file covering a contiguous range of the output time axis
+pub struct OutputChunk {
+    pub filename: String,
+    pub first_step: usize,
+    pub num_steps: usize,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub file: FileMut,
+}
+
+// All output files for a run, plus the fixed feature slot of every reach
+pub struct NetCdfOutput {
+    pub chunks: Vec<OutputChunk>,
+    pub feature_index: HashMap<u64, usize>,
+    pub options: OutputOptions,
+    // Cumulative upstream drainage area per reach (km2)
+    pub drainage_area: HashMap<u64, f32>,
+    // Forcing member being routed, when the series have a `member` dimension
+    pub member: usize,
+}
+
+impl NetCdfOutput {
+    // Flush every output file to disk, surfacing errors that closing on drop would swallow
+    pub fn sync(&self) -> Result<()> {
+        for chunk in &self.chunks {
+            chunk
+                .file
+                .sync()
+                .with_context(|| format!("Failed to flush {}", chunk.filename))?;
+        }
+        Ok(())
+    }
+
+    // Leading index of the series written for the current member, if there are members
+    fn member_index(&self) -> Option<usize> {
+        (!self.options.members.is_empty()).then_some(self.member)
+    }
+}
+
+// Assign feature slots in the given order, which mustn't depend on completion order
+pub fn ordered_feature_index(feature_ids: &[u64]) -> HashMap<u64, usize> {
+    feature_ids
+        .iter()
+        .enumerate()
+        .map(|(idx, &id)| (id, idx))
+        .collect()
+}
+
+// Assign feature slots by ascending id so output ordering doesn't depend on completion order
+pub fn sorted_feature_index(feature_ids: impl IntoIterator<Item = u64>) -> HashMap<u64, usize> {
+    let mut ids: Vec<u64> = feature_ids.into_iter().collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids.into_iter()
+        .enumerate()
+        .map(|(idx, id)| (id, idx))
+        .collect()
+}
+
+// Group output timesteps into per-file chunks, returning each chunk's filename and step range
+pub fn plan_output_chunks(
+    reference_time: &NaiveDateTime,
+    timesteps: &[i64],
+    split: OutputSplit,
+) -> Vec<(String, Range<usize>)> {
+    let mut chunks: Vec<(String, Range<usize>)> = Vec::new();
+
+    for (step, &seconds) in timesteps.iter().enumerate() {
+        let time = *reference_time + Duration::seconds(seconds);
+        let filename = match split {
+            OutputSplit::None => {
+                format!("troute_output_{}.nc", reference_time.format("%Y%m%d%H%M"))
+            }
+            OutputSplit::Day => format!("troute_output_{}.nc", time.format("%Y%m%d")),
+            OutputSplit::Month => format!("troute_output_{}.nc", time.format("%Y%m")),
+        };
+
+        match chunks.last_mut() {
+            Some((name, range)) if *name == filename => range.end = step + 1,
+            _ => chunks.push((filename, step..step + 1)),
+        }
+    }
+
+    chunks
+}
+
+// Create one NetCDF file per time chunk, all sharing the same feature layout
+pub fn init_chunked_output(
+    timesteps: &[i64],
+    reference_time: &NaiveDateTime,
+    split: OutputSplit,
+    feature_index: HashMap<u64, usize>,
+    options: OutputOptions,
+    drainage_area: HashMap<u64, f32>,
+) -> Result<Arc<Mutex<NetCdfOutput>>> {
+    let mut chunks = Vec::new();
+
+    for (filename, range) in plan_output_chunks(reference_time, timesteps, split) {
+        let chunk_times = timesteps[range.clone()].to_vec();
+        let start_time = *reference_time + Duration::seconds(chunk_times[0]);
+        let end_time = *reference_time + Duration::seconds(chunk_times[chunk_times.len() - 1]);
+        let file = init_netcdf_output(&filename, chunk_times, reference_time, &options)?;
+
+        chunks.push(OutputChunk {
+            filename,
+            first_step: range.start,
+            num_steps: range.len(),
+            start_time,
+            end_time,
+            file,
+        });
+    }
+
+    Ok(Arc::new(Mutex::new(NetCdfOutput {
+        chunks,
+        feature_index,
+        options,
+        drainage_area,
+        member: 0,
+    })))
+}
+
+// Reopen a previous run's single-file output to fill in the features it is missing.
+// Returns the output and the ids of features it already holds.
+pub fn resume_output(
+    path: &Path,
+    timesteps: &[i64],
+    reference_time: &NaiveDateTime,
+    feature_index: HashMap<u64, usize>,
+    options: OutputOptions,
+    drainage_area: HashMap<u64, f32>,
+) -> Result<(Arc<Mutex<NetCdfOutput>>, HashSet<u64>)> {
+    let file = netcdf::append(path)
+        .with_context(|| format!("Failed to open {} for resuming", path.display()))?;
+
+    // Filling in features routed with other inputs or options would mix two runs in one file
+    if let Some(expected) = &options.input_hash {
+        let recorded = match file.attribute(INPUT_HASH_ATTRIBUTE) {
+            Some(attribute) => match attribute.value()? {
+                AttributeValue::Str(hash) => Some(hash),
+                _ => None,
+            },
+            None => None,
+        };
+        match recorded {
+            Some(recorded) if recorded == *expected => {}
+            Some(recorded) => {
+                return Err(anyhow::anyhow!(
+                    "{} was routed with different inputs or options (input hash {}, this run {})",
+                    path.display(),
+                    recorded,
+                    expected
+                ));
+            }
+            None => {
+                return Err(anyhow::anyhow!(
+                    "{} has no {} attribute, so the options it was routed with can't be checked",
+                    path.display(),
+                    INPUT_HASH_ATTRIBUTE
+                ));
+            }
+        }
+    }
+
+    let num_steps = file
+        .dimension("time")
+        .ok_or_else(|| anyhow::anyhow!("{} has no time dimension", path.display()))?
+        .len();
+    if num_steps != timesteps.len() {
+        return Err(anyhow::anyhow!(
+            "{} has {} timesteps but this run has {}",
+            path.display(),
+            num_steps,
+            timesteps.len()
+        ));
+    }
+
+    // Unwritten slots hold the integer fill value, which is never a valid id
+    let existing: Vec<i64> = file
+        .variable("feature_id")
+        .ok_or_else(|| anyhow::anyhow!("feature_id variable not found in {}", path.display()))?
+        .get_values(..)
+        .context("Failed to read existing feature ids")?;
+    let mut written = HashSet::new();
+    for (slot, &id) in existing.iter().enumerate() {
+        let Ok(id) = u64::try_from(id) else {
+            continue;
+        };
+        if feature_index.get(&id) != Some(&slot) {
+            return Err(anyhow::anyhow!(
+                "{} has feature {} in slot {}, which doesn't match this network",
+                path.display(),
+                id,
+                slot
+            ));
+        }
+        written.insert(id);
+    }
+
+    let start_time = *reference_time + Duration::seconds(timesteps[0]);
+    let end_time = *reference_time + Duration::seconds(timesteps[num_steps - 1]);
+    let chunk = OutputChunk {
+        filename: path.to_string_lossy().into_owned(),
+        first_step: 0,
+        num_steps,
+        start_time,
+        end_time,
+        file,
+    };
+
+    Ok((
+        Arc::new(Mutex::new(NetCdfOutput {
+            chunks: vec![chunk],
+            feature_index,
+            options,
+            drainage_area,
+            member: 0,
+        })),
+        written,
+    ))
+}
+
+// Reconcile the output files with what routing will produce: `total_timesteps` internal
+// steps sampled every `output_stride`, across contiguous chunks whose declared time
+// dimensions match. Checked before routing so a mismatch fails up front rather than as
+// a hyperslab error on the first write.
+pub fn check_time_dimension(output: &NetCdfOutput, total_timesteps: usize) -> Result<()> {
+    let stride = output.options.output_stride.max(1);
+    let output_steps = total_timesteps.div_ceil(stride);
+    let mut next_step = 0;
+    for chunk in &output.chunks {
+        let declared = chunk
+            .file
+            .dimension("time")
+            .map(|dimension| dimension.len())
+            .ok_or_else(|| anyhow::anyhow!("{} has no time dimension", chunk.filename))?;
+        if chunk.first_step != next_step || declared != chunk.num_steps {
+            return Err(anyhow::anyhow!(
+                "{} declares {} timesteps starting at output step {}, but should hold {} starting at step {}",
+                chunk.filename,
+                declared,
+                chunk.first_step,
+                chunk.num_steps,
+                next_step
+            ));
+        }
+        next_step += chunk.num_steps;
+    }
+    if next_step != output_steps {
+        return Err(anyhow::anyhow!(
+            "Output time dimension has {} steps, but routing {} internal timesteps sampled every {} produces {}",
+            next_step,
+            total_timesteps,
+            stride,
+            output_steps
+        ));
+    }
+    Ok(())
+}
+
+// Write an index of the chunk files and the time span each one covers
+pub fn write_chunk_index(path: &str, chunks: &[OutputChunk]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)
+        .with_context(|| format!("Failed to create chunk index at {}", path))?;
+
+    wtr.write_record(["filename", "start_time", "end_time", "num_steps"])
+        .context("Failed to write chunk index header")?;
+    for chunk in chunks {
+        wtr.write_record([
+            chunk.filename.clone(),
+            chunk.start_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            chunk.end_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            chunk.num_steps.to_string(),
+        ])
+        .context("Failed to write chunk index record")?;
+    }
+
+    wtr.flush().context("Failed to flush chunk index")?;
+    Ok(())
+}
+
 pub fn init_netcdf_output(
     filename: &str,
-    num_flowpaths: usize,
-    timesteps: Vec<f64>,
+    timesteps: Vec<i64>,
     reference_time: &NaiveDateTime,
-) -> Result<Arc<Mutex<FileMut>>> {
+    options: &OutputOptions,
+) -> Result<FileMut> {
     // Create NetCDF file
     let mut file = netcdf::create(filename)
         .with_context(|| format!("Failed to create NetCDF file: {}", filename))?;
 
+    // NWM files count minutes from the Unix epoch rather than seconds from the run start.
+    // Times stay whole seconds until they're written, so no float error accumulates.
+    let nwm_time = |seconds: i64| (reference_time.and_utc().timestamp() + seconds) as f64 / 60.0;
+    let first_valid_time = *reference_time + Duration::seconds(timesteps[0]);
+    let num_times = timesteps.len();
+    let (timesteps, time_units): (Vec<f64>, _) = if options.nwm_compat {
+        (
+            timesteps.into_iter().map(nwm_time).collect(),
+            NWM_TIME_UNITS.to_string(),
+        )
+    } else {
+        (
+            timesteps
+                .into_iter()
+                .map(|seconds| seconds as f64)
+                .collect(),
+            format!(
+                "seconds since {}",
+                options.time_format.format(reference_time)
+            ),
+        )
+    };
+
     // Add dimensions
     file.add_dimension("feature_id", 0)
         .context("Failed to add feature_id dimension")?;
     file.add_dimension("time", timesteps.len())
         .context("Failed to add time dimension")?;
+    if !options.members.is_empty() {
+        file.add_dimension("member", options.members.len())
+            .context("Failed to add member dimension")?;
+        let mut member_var = file
+            .add_variable::<i32>("member", &["member"])
+            .context("Failed to add member variable")?;
+        member_var.put_attribute("long_name", "Forcing member index")?;
+        member_var
+            .put_values(&(0..options.members.len() as i32).collect::<Vec<_>>(), ..)
+            .context("Failed to write member values")?;
+        file.add_attribute("forcing_members", options.members.join(","))?;
+    }
 
     // Add variables
     // Time variable
     let mut time_var = file
         .add_variable::<f64>("time", &["time"])
         .context("Failed to add time variable")?;
-    time_var.put_attribute("_FillValue", -9999.0)?;
+    time_var.put_attribute("_FillValue", options.fill_value as f64)?;
     time_var.put_attribute("long_name", "valid output time")?;
     time_var.put_attribute("standard_name", "time")?;
-    time_var.put_attribute(
-        "units",
-        format!(
-            "seconds since {}",
-            reference_time.format("%Y-%m-%d %H:%M:%S")
-        ),
-    )?;
-    time_var.put_attribute("missing_value", -9999.0)?;
+    time_var.put_attribute("units", time_units)?;
+    time_var.put_attribute("missing_value", options.fill_value as f64)?;
     time_var
         .put_values(&timesteps, ..)
         .context("Failed to write time values")?;
 
+    if options.nwm_compat {
+        file.add_dimension("reference_time", 1)
+            .context("Failed to add reference_time dimension")?;
+        let mut reference_var = file
+            .add_variable::<f64>("reference_time", &["reference_time"])
+            .context("Failed to add reference_time variable")?;
+        reference_var.put_attribute("long_name", "model initialization time")?;
+        reference_var.put_attribute("standard_name", "forecast_reference_time")?;
+        reference_var.put_attribute("units", NWM_TIME_UNITS)?;
+        reference_var
+            .put_values(&[nwm_time(0)], ..)
+            .context("Failed to write reference_time")?;
+    }
+
     // Feature ID variable
     let mut feature_var = file
         .add_variable::<i64>("feature_id", &["feature_id"])
         .context("Failed to add feature_id variable")?;
-    feature_var.put_attribute("long_name", "Segment ID")?;
-
-    // Flow variable
-    let mut flow_var = file
-        .add_variable::<f32>("flow", &["feature_id", "time"])
-        .context("Failed to add flow variable")?;
-    flow_var.put_attribute("_FillValue", -9999.0f32)?;
-    flow_var.put_attribute("long_name", "Flow")?;
-    flow_var.put_attribute("units", "m3 s-1")?;
-    flow_var.put_attribute("missing_value", -9999.0f32)?;
-
-    // Velocity variable
-    let mut velocity_var = file
-        .add_variable::<f32>("velocity", &["feature_id", "time"])
-        .context("Failed to add velocity variable")?;
-    velocity_var.put_attribute("_FillValue", -9999.0f32)?;
-    velocity_var.put_attribute("long_name", "Velocity")?;
-    velocity_var.put_attribute("units", "m/s")?;
-    velocity_var.put_attribute("missing_value", -9999.0f32)?;
-
-    // Depth variable
-    let mut depth_var = file
-        .add_variable::<f32>("depth", &["feature_id", "time"])
-        .context("Failed to add depth variable")?;
-    depth_var.put_attribute("_FillValue", -9999.0f32)?;
-    depth_var.put_attribute("long_name", "Depth")?;
-    depth_var.put_attribute("units", "m")?;
-    depth_var.put_attribute("missing_value", -9999.0f32)?;
+    if options.nwm_compat {
+        feature_var.put_attribute("long_name", "Reach ID")?;
+        feature_var.put_attribute("cf_role", "timeseries_id")?;
+    } else {
+        feature_var.put_attribute("long_name", "Segment ID")?;
+    }
+
+    // Routed variables
+    for &variable in &options.variables {
+        let (long_name, units) = match variable {
+            OutputVariable::Flow if options.nwm_compat => ("River Flow", "m3 s-1"),
+            OutputVariable::Velocity if options.nwm_compat => ("River Velocity", "m s-1"),
+            OutputVariable::Flow => ("Flow", "m3 s-1"),
+            OutputVariable::Velocity => ("Velocity", "m/s"),
+            OutputVariable::Depth => ("Depth", "m"),
+        };
+        add_series_variable(
+            &mut file,
+            options.variable_name(variable),
+            long_name,
+            units,
+            options.fill_value,
+            options.time_major(),
+        )?;
+    }
+    if options.specific_discharge {
+        add_series_variable(
+            &mut file,
+            "specific_discharge",
+            "Flow per unit upstream drainage area",
+            "m3 s-1 km-2",
+            options.fill_value,
+            options.time_major(),
+        )?;
+    }
+    if options.writes_nudge() {
+        add_series_variable(
+            &mut file,
+            "nudge",
+            "Flow correction applied toward observations",
+            "m3 s-1",
+            options.fill_value,
+            options.time_major(),
+        )?;
+    }
+    if options.muskingum {
+        add_series_variable(
+            &mut file,
+            "musk_x",
+            "Muskingum weighting factor X",
+            "1",
+            options.fill_value,
+            options.time_major(),
+        )?;
+        add_series_variable(
+            &mut file,
+            "celerity",
+            "Kinematic wave celerity",
+            "m s-1",
+            options.fill_value,
+            options.time_major(),
+        )?;
+    }
+    if options.cumulative_volume {
+        add_series_variable(
+            &mut file,
+            "cumulative_volume",
+            "Routed outflow volume since the first output time",
+            "m3",
+            options.fill_value,
+            options.time_major(),
+        )?;
+    }
+    if options.drainage_area {
+        add_feature_variable(
+            &mut file,
+            "drainage_area",
+            "Cumulative upstream drainage area",
+            "km2",
+            options.fill_value,
+        )?;
+    }
+    if options.iterations {
+        add_feature_variable(
+            &mut file,
+            "iterations",
+            "Mean solver iterations per timestep",
+            "1",
+            options.fill_value,
+        )?;
+        add_feature_variable(
+            &mut file,
+            "max_iterations",
+            "Most solver iterations in any timestep",
+            "1",
+            options.fill_value,
+        )?;
+    }
+    if options.travel_time {
+        add_feature_variable(
+            &mut file,
+            "travel_time",
+            "Mean travel time through the reach while it carries flow",
+            "s",
+            options.fill_value,
+        )?;
+    }
+    if options.floored_steps {
+        add_feature_variable(
+            &mut file,
+            "floored_steps",
+            "Timesteps whose outflow was raised to the minimum flow",
+            "1",
+            options.fill_value,
+        )?;
+    }
+    if !options.passthrough.is_empty() {
+        add_feature_variable(
+            &mut file,
+            "passthrough",
+            "1 where inflow was passed through unrouted, 0 where routed",
+            "1",
+            options.fill_value,
+        )?;
+    }
 
     // Global attributes
     file.add_attribute("TITLE", "OUTPUT FROM ROUTE_RS")?;
@@ -80,75 +510,533 @@ pub fn init_netcdf_output(
         reference_time.format("%Y-%m-%d_%H:%M:%S").to_string(),
     )?;
     file.add_attribute("code_version", "")?;
+    add_solver_attributes(&mut file, options)?;
+    if let Some(hash) = &options.input_hash {
+        file.add_attribute(INPUT_HASH_ATTRIBUTE, hash.as_str())?;
+    }
+
+    if options.nwm_compat {
+        // Features are channel reaches unless typed as waterbodies (see ReachType)
+        add_feature_variable(&mut file, "type", "Feature type", "1", options.fill_value)?;
+        let mut type_var = file
+            .variable_mut("type")
+            .ok_or_else(|| anyhow::anyhow!("type variable not found"))?;
+        type_var.put_attribute("flag_values", vec![0.0_f32, 1.0])?;
+        type_var.put_attribute("flag_meanings", "reach waterbody")?;
+
+        file.add_attribute("featureType", "timeSeries")?;
+        file.add_attribute("Conventions", "CF-1.6")?;
+        file.add_attribute("cdm_datatype", "Station")?;
+        file.add_attribute("station_dimension", "feature_id")?;
+        file.add_attribute("model_output_type", "channel_rt")?;
+        file.add_attribute(
+            "model_initialization_time",
+            reference_time.format("%Y-%m-%d_%H:%M:%S").to_string(),
+        )?;
+        file.add_attribute(
+            "model_output_valid_time",
+            first_valid_time.format("%Y-%m-%d_%H:%M:%S").to_string(),
+        )?;
+        file.add_attribute("model_total_valid_times", num_times as i32)?;
+    }
+
+    Ok(file)
+}
 
-    // Additional expected variables
-    let _ = file.add_variable::<f32>("type", &["feature_id"])?;
-    let _ = file.add_variable::<f32>("nudge", &["feature_id"])?;
+// CLI name of an option value, as it would be passed back in
+fn value_name(value: impl clap::ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
 
-    Ok(Arc::new(Mutex::new(file)))
+// Record how the file was routed, so files routed with different kernel settings can be
+// told apart without the run manifest
+fn add_solver_attributes(file: &mut FileMut, options: &OutputOptions) -> Result<()> {
+    let solver = &options.solver;
+    let kernel = match solver.kinematic_above_slope {
+        Some(slope) => format!("Muskingum-Cunge, kinematic wave above slope {}", slope),
+        None => "Muskingum-Cunge".to_string(),
+    };
+    file.add_attribute("routing_kernel", kernel)?;
+    file.add_attribute(
+        "internal_timestep_seconds",
+        options.internal_timestep_seconds as i32,
+    )?;
+    file.add_attribute(
+        "output_timestep_seconds",
+        (options.internal_timestep_seconds * options.output_stride) as i32,
+    )?;
+    file.add_attribute("solver_relative_tolerance", RELATIVE_TOLERANCE)?;
+    file.add_attribute("solver_min_depth_m", MIN_DEPTH)?;
+    file.add_attribute("solver_max_iterations", MAX_ITERATIONS)?;
+    file.add_attribute(
+        "solver_compound_channel",
+        if solver.compound_channel {
+            "true"
+        } else {
+            "false"
+        },
+    )?;
+    file.add_attribute(
+        "solver_nonconvergence_fallback",
+        value_name(solver.nonconvergence_fallback),
+    )?;
+    file.add_attribute("solver_secant_bracket", value_name(solver.bracket))?;
+    file.add_attribute("solver_bracket_upper", solver.bracket_upper)?;
+    file.add_attribute("solver_bracket_lower", solver.bracket_lower)?;
+    Ok(())
 }
 
 // Function to write results to NetCDF
 pub fn write_output(
-    output_file: &Arc<Mutex<FileMut>>,
+    output_file: &Arc<Mutex<NetCdfOutput>>,
     results: &Arc<SimulationResults>,
 ) -> Result<()> {
     // Get lock on file
-    let mut file = output_file
+    let mut output = output_file
         .lock()
         .map_err(|e| anyhow::anyhow!("Failed to acquire NetCDF file lock: {}", e))?;
+    let fidx = *output
+        .feature_index
+        .get(&(results.feature_id as u64))
+        .ok_or_else(|| anyhow::anyhow!("No output slot for feature {}", results.feature_id))?;
+    let output = &mut *output;
+    let member = output.member_index();
 
-    // figure out the downsampling that needs to be done
-    let expected_timesteps = file
-        .dimension("time")
-        .ok_or_else(|| anyhow::anyhow!("time dimension not found"))?;
-    let actual_timesteps = results.flow_data.len();
-    let downsampling = actual_timesteps / expected_timesteps.len();
-    let mut downsampled_flow_data = Vec::with_capacity(expected_timesteps.len());
-    let mut downsampled_velocity_data = Vec::with_capacity(expected_timesteps.len());
-    let mut downsampled_depth_data = Vec::with_capacity(expected_timesteps.len());
-    for i in 0..actual_timesteps {
-        let d = i * downsampling;
-        if d >= results.flow_data.len() {
+    let expected_timesteps: usize = output.chunks.iter().map(|chunk| chunk.num_steps).sum();
+    let stride = output.options.output_stride.max(1);
+    let downsampled_flow_data = downsample(&results.flow_data, stride);
+
+    // Catch shape mismatches here rather than as an opaque hyperslab error from netcdf
+    let mut downsampled_data = Vec::new();
+    for variable in &output.options.variables {
+        let data = match variable {
+            OutputVariable::Flow => &results.flow_data,
+            OutputVariable::Velocity => &results.velocity_data,
+            OutputVariable::Depth => &results.depth_data,
+        };
+        if data.len() != results.flow_data.len() {
+            return Err(anyhow::anyhow!(
+                "Feature {} has inconsistent result lengths: flow {}, {} {}",
+                results.feature_id,
+                results.flow_data.len(),
+                variable.name(),
+                data.len()
+            ));
+        }
+        downsampled_data.push((
+            output.options.variable_name(*variable),
+            downsample(data, stride),
+        ));
+    }
+
+    // Blank out output steps sampled from timesteps where the kernel didn't converge
+    if output.options.fill_nonconverged {
+        for &timestep in &results.nonconverged {
+            if timestep % stride == 0 {
+                for (_, data) in downsampled_data.iter_mut() {
+                    if let Some(value) = data.get_mut(timestep / stride) {
+                        *value = output.options.fill_value;
+                    }
+                }
+            }
+        }
+    }
+    if downsampled_flow_data.len() != expected_timesteps {
+        return Err(anyhow::anyhow!(
+            "Feature {} produced {} timesteps ({} output steps) but the time dimension has {}",
+            results.feature_id,
+            results.flow_data.len(),
+            downsampled_flow_data.len(),
+            expected_timesteps
+        ));
+    }
+
+    let fill_value = output.options.fill_value;
+    let area = output
+        .drainage_area
+        .get(&(results.feature_id as u64))
+        .copied()
+        .unwrap_or(0.0);
+    let mut scalars = Vec::new();
+    if output.options.drainage_area {
+        scalars.push(("drainage_area", area));
+    }
+    if output.options.iterations {
+        let mean = results.iterations as f32 / results.flow_data.len().max(1) as f32;
+        scalars.push(("iterations", mean));
+        scalars.push(("max_iterations", results.max_iterations as f32));
+    }
+    if output.options.travel_time {
+        let mean = match results.flowing_steps {
+            0 => fill_value,
+            steps => (results.travel_time / steps as f64) as f32,
+        };
+        scalars.push(("travel_time", mean));
+    }
+    if output.options.floored_steps {
+        scalars.push(("floored_steps", results.floored_steps as f32));
+    }
+    if output.options.nwm_compat {
+        let waterbody = output
+            .options
+            .waterbodies
+            .contains(&(results.feature_id as u64));
+        scalars.push(("type", if waterbody { 1.0 } else { 0.0 }));
+    }
+    if !output.options.passthrough.is_empty() {
+        let passthrough = output
+            .options
+            .passthrough
+            .contains(&(results.feature_id as u64));
+        scalars.push(("passthrough", if passthrough { 1.0 } else { 0.0 }));
+    }
+
+    let specific_discharge_data = if output.options.specific_discharge {
+        Some(
+            downsampled_flow_data
+                .iter()
+                .map(|&flow| if area > 0.0 { flow / area } else { fill_value })
+                .collect::<Vec<f32>>(),
+        )
+    } else {
+        None
+    };
+
+    // Reaches without observations get a zero correction
+    let nudge_data = if !output.options.writes_nudge() {
+        None
+    } else if results.nudge_data.is_empty() {
+        Some(vec![0.0; expected_timesteps])
+    } else {
+        Some(downsample(&results.nudge_data, stride))
+    };
+
+    let mut derived_series = Vec::new();
+    if output.options.muskingum {
+        derived_series.push(("musk_x", &results.musk_x_data));
+        derived_series.push(("celerity", &results.celerity_data));
+    }
+    if output.options.cumulative_volume {
+        derived_series.push(("cumulative_volume", &results.cumulative_volume_data));
+    }
+    let mut derived_data = Vec::new();
+    for (name, data) in derived_series {
+        if data.len() != results.flow_data.len() {
+            return Err(anyhow::anyhow!(
+                "Feature {} has inconsistent result lengths: flow {}, {} {}",
+                results.feature_id,
+                results.flow_data.len(),
+                name,
+                data.len()
+            ));
+        }
+        derived_data.push((name, downsample(data, stride)));
+    }
+
+    for chunk in output.chunks.iter_mut() {
+        let steps = chunk.first_step..chunk.first_step + chunk.num_steps;
+        let mut series: Vec<(&str, &[f32])> = downsampled_data
+            .iter()
+            .map(|(name, data)| (*name, &data[steps.clone()]))
+            .collect();
+        if let Some(data) = &specific_discharge_data {
+            series.push(("specific_discharge", &data[steps.clone()]));
+        }
+        if let Some(data) = &nudge_data {
+            series.push(("nudge", &data[steps.clone()]));
+        }
+        for (name, data) in &derived_data {
+            series.push((name, &data[steps.clone()]));
+        }
+
+        let time_major = output.options.time_major();
+        write_chunk(
+            &mut chunk.file,
+            member,
+            fidx,
+            results.feature_id,
+            &series,
+            &scalars,
+            time_major,
+        )
+        .with_context(|| format!("Failed to write to {}", chunk.filename))?;
+    }
+
+    Ok(())
+}
+
+// Write fill values for a reach that produced no results so it still occupies its slot
+pub fn write_fill(output_file: &Arc<Mutex<NetCdfOutput>>, feature_id: u64) -> Result<()> {
+    let mut output = output_file
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Failed to acquire NetCDF file lock: {}", e))?;
+    let fidx = *output
+        .feature_index
+        .get(&feature_id)
+        .ok_or_else(|| anyhow::anyhow!("No output slot for feature {}", feature_id))?;
+    let output = &mut *output;
+    let member = output.member_index();
+
+    let names = output.options.series_names();
+    let mut scalars = Vec::new();
+    if output.options.drainage_area {
+        let area = output.drainage_area.get(&feature_id).copied();
+        scalars.push(("drainage_area", area.unwrap_or(output.options.fill_value)));
+    }
+    if output.options.iterations {
+        scalars.push(("iterations", output.options.fill_value));
+        scalars.push(("max_iterations", output.options.fill_value));
+    }
+    if output.options.travel_time {
+        scalars.push(("travel_time", output.options.fill_value));
+    }
+    if output.options.floored_steps {
+        scalars.push(("floored_steps", output.options.fill_value));
+    }
+    if output.options.nwm_compat {
+        let waterbody = output.options.waterbodies.contains(&feature_id);
+        scalars.push(("type", if waterbody { 1.0 } else { 0.0 }));
+    }
+    if !output.options.passthrough.is_empty() {
+        scalars.push(("passthrough", output.options.fill_value));
+    }
+
+    for chunk in output.chunks.iter_mut() {
+        let fill = vec![output.options.fill_value; chunk.num_steps];
+        let series: Vec<(&str, &[f32])> = names.iter().map(|&name| (name, &fill[..])).collect();
+
+        let time_major = output.options.time_major();
+        write_chunk(
+            &mut chunk.file,
+            member,
+            fidx,
+            feature_id as i64,
+            &series,
+            &scalars,
+            time_major,
+        )
+        .with_context(|| format!("Failed to write to {}", chunk.filename))?;
+    }
+
+    Ok(())
+}
+
+// Reopen a finished output file and check its shape and contents against the run that
+// wrote it. Every variable is read in full, which surfaces truncated or corrupt files.
+// Returns a description of each problem found.
+pub fn verify_output(
+    path: &Path,
+    num_features: usize,
+    num_steps: usize,
+    options: &OutputOptions,
+) -> Vec<String> {
+    let file = match netcdf::open(path) {
+        Ok(file) => file,
+        Err(e) => return vec![format!("cannot be opened: {}", e)],
+    };
+    let mut problems = Vec::new();
+
+    for (name, expected) in [("feature_id", num_features), ("time", num_steps)] {
+        match file.dimension(name) {
+            Some(dim) if dim.len() == expected => {}
+            Some(dim) => problems.push(format!(
+                "{} dimension has length {}, expected {}",
+                name,
+                dim.len(),
+                expected
+            )),
+            None => problems.push(format!("{} dimension is missing", name)),
+        }
+    }
+    if !problems.is_empty() {
+        return problems;
+    }
+
+    // Unwritten slots hold the integer fill value, which is never a valid id
+    match file
+        .variable("feature_id")
+        .map(|var| var.get_values::<i64, _>(..))
+    {
+        Some(Ok(ids)) => {
+            let unwritten = ids.iter().filter(|&&id| u64::try_from(id).is_err()).count();
+            if unwritten > 0 {
+                problems.push(format!(
+                    "{} of {} features were never written",
+                    unwritten, num_features
+                ));
+            }
+        }
+        Some(Err(e)) => problems.push(format!("feature_id cannot be read: {}", e)),
+        None => problems.push("feature_id variable is missing".to_string()),
+    }
+
+    let is_fill =
+        |value: f32| value == options.fill_value || (options.fill_value.is_nan() && value.is_nan());
+    let mut variables: Vec<(&str, bool)> = options
+        .series_names()
+        .into_iter()
+        .map(|name| (name, true))
+        .collect();
+    if options.drainage_area {
+        variables.push(("drainage_area", false));
+    }
+
+    // Read in blocks of features to bound memory on large domains
+    const BLOCK: usize = 4096;
+    'variables: for (name, is_series) in variables {
+        let Some(var) = file.variable(name) else {
+            problems.push(format!("{} variable is missing", name));
             continue;
+        };
+        let mut any_data = false;
+        for start in (0..num_features).step_by(BLOCK) {
+            let block = start..(start + BLOCK).min(num_features);
+            let values = if is_series && options.time_major() {
+                var.get_values::<f32, _>((.., block))
+            } else if is_series {
+                var.get_values::<f32, _>((block, ..))
+            } else {
+                var.get_values::<f32, _>(block)
+            };
+            match values {
+                Ok(values) => any_data |= values.iter().any(|&value| !is_fill(value)),
+                Err(e) => {
+                    problems.push(format!(
+                        "{} cannot be read from feature {}: {}",
+                        name, start, e
+                    ));
+                    continue 'variables;
+                }
+            }
+        }
+        if !any_data && num_features > 0 && num_steps > 0 {
+            problems.push(format!("{} holds only fill values", name));
+        }
+    }
+
+    problems
+}
+
+// Sample internal timestep values onto the output time axis, taking every
+// `stride`-th value starting from the first
+pub fn downsample(data: &[f32], stride: usize) -> Vec<f32> {
+    data.iter().step_by(stride.max(1)).copied().collect()
+}
+
+// Add a (feature_id, time) variable, or (time, feature_id) when `time_major`, with the
+// standard fill attributes, led by the member dimension when the file has one
+fn add_series_variable(
+    file: &mut FileMut,
+    name: &str,
+    long_name: &str,
+    units: &str,
+    fill_value: f32,
+    time_major: bool,
+) -> Result<()> {
+    let series_dims = if time_major {
+        ["time", "feature_id"]
+    } else {
+        ["feature_id", "time"]
+    };
+    let mut dims = Vec::with_capacity(3);
+    if file.dimension("member").is_some() {
+        dims.push("member");
+    }
+    dims.extend(series_dims);
+    let mut var = file
+        .add_variable::<f32>(name, &dims)
+        .with_context(|| format!("Failed to add {} variable", name))?;
+    var.put_attribute("_FillValue", fill_value)?;
+    var.put_attribute("long_name", long_name)?;
+    var.put_attribute("units", units)?;
+    var.put_attribute("missing_value", fill_value)?;
+    Ok(())
+}
+
+// Add a per-feature variable with the standard fill attributes
+fn add_feature_variable(
+    file: &mut FileMut,
+    name: &str,
+    long_name: &str,
+    units: &str,
+    fill_value: f32,
+) -> Result<()> {
+    let mut var = file
+        .add_variable::<f32>(name, &["feature_id"])
+        .with_context(|| format!("Failed to add {} variable", name))?;
+    var.put_attribute("_FillValue", fill_value)?;
+    var.put_attribute("long_name", long_name)?;
+    var.put_attribute("units", units)?;
+    var.put_attribute("missing_value", fill_value)?;
+    Ok(())
+}
+
+// Write one feature's time slice into a single output file
+fn write_chunk(
+    file: &mut FileMut,
+    member: Option<usize>,
+    fidx: usize,
+    feature_id: i64,
+    series: &[(&str, &[f32])],
+    scalars: &[(&str, f32)],
+    time_major: bool,
+) -> Result<()> {
+    for (name, data) in series {
+        let mut var = file
+            .variable_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("{} variable not found", name))?;
+        match (member, time_major) {
+            (Some(member), false) => var.put_values(data, (member, fidx, ..)),
+            (Some(member), true) => var.put_values(data, (member, .., fidx)),
+            (None, false) => var.put_values(data, (fidx, ..)),
+            (None, true) => var.put_values(data, (.., fidx)),
         }
-        downsampled_flow_data.push(results.flow_data[d]);
-        downsampled_velocity_data.push(results.velocity_data[d]);
-        downsampled_depth_data.push(results.depth_data[d]);
+        .with_context(|| format!("Failed to write {} data", name))?;
+    }
+
+    for (name, value) in scalars {
+        let mut var = file
+            .variable_mut(name)
+            .ok_or_else(|| anyhow::anyhow!("{} variable not found", name))?;
+        var.put_value(*value, fidx)
+            .with_context(|| format!("Failed to write {} value", name))?;
     }
 
-    // Get feature variable
+    // Written last so a feature id only appears once its data is complete (see resume_output)
     let mut feature_var = file
         .variable_mut("feature_id")
         .ok_or_else(|| anyhow::anyhow!("feature_id variable not found"))?;
-    let fidx = feature_var.len();
     feature_var
-        .put_value(results.feature_id, fidx)
+        .put_value(feature_id, fidx)
         .context("Failed to write feature_id")?;
 
-    // Flow variable
-    let mut flow_var = file
-        .variable_mut("flow")
-        .ok_or_else(|| anyhow::anyhow!("flow variable not found"))?;
-    flow_var
-        .put_values(&downsampled_flow_data, (fidx, ..))
-        .context("Failed to write flow data")?;
-
-    // Velocity variable
-    let mut velocity_var = file
-        .variable_mut("velocity")
-        .ok_or_else(|| anyhow::anyhow!("velocity variable not found"))?;
-    velocity_var
-        .put_values(&downsampled_velocity_data, (fidx, ..))
-        .context("Failed to write velocity data")?;
-
-    // Depth variable
-    let mut depth_var = file
-        .variable_mut("depth")
-        .ok_or_else(|| anyhow::anyhow!("depth variable not found"))?;
-    depth_var
-        .put_values(&downsampled_depth_data, (fidx, ..))
-        .context("Failed to write depth data")?;
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_index_is_independent_of_completion_order() {
+        let first_run = sorted_feature_index([42, 7, 1003, 15]);
+        let second_run = sorted_feature_index([1003, 15, 42, 7]);
+
+        assert_eq!(first_run, second_run);
+        assert_eq!(first_run[&7], 0);
+        assert_eq!(first_run[&15], 1);
+        assert_eq!(first_run[&42], 2);
+        assert_eq!(first_run[&1003], 3);
+    }
+
+    #[test]
+    fn downsample_takes_every_stride_th_step_from_the_first() {
+        let data: Vec<f32> = (0..10).map(|step| step as f32).collect();
+        assert_eq!(downsample(&data, 1), data);
+        assert_eq!(downsample(&data, 4), vec![0.0, 4.0, 8.0]);
+        assert_eq!(downsample(&data, 5), vec![0.0, 5.0]);
+    }
+}
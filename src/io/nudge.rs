@@ -0,0 +1,156 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Observed flows at gauged reaches, used to nudge simulated flow during routing
+#[derive(Debug, Clone)]
+pub struct Nudging {
+    observations: HashMap<u64, Vec<f32>>,
+    // e-folding time (seconds) over which a correction fades once observations stop
+    pub decay_seconds: f32,
+}
+
+impl Nudging {
+    pub fn new(observations: HashMap<u64, Vec<f32>>, decay_seconds: f32) -> Self {
+        Nudging {
+            observations,
+            decay_seconds,
+        }
+    }
+
+    // A nudger for this reach, or None when it has no observations
+    pub fn nudger(&self, feature_id: u64) -> Option<Nudger<'_>> {
+        self.observations.get(&feature_id).map(|observed| Nudger {
+            observed,
+            decay_seconds: self.decay_seconds,
+            last_nudge: 0.0,
+            since_observation: 0.0,
+        })
+    }
+}
+
+// Load observed flows from a NetCDF file with `feature_id(feature_id)` and
+// `flow(feature_id, time)`, where time is the forcing (external) timestep.
+// Negative or non-finite values are treated as missing.
+pub fn load_observations(path: &Path) -> Result<HashMap<u64, Vec<f32>>> {
+    let file = netcdf::open(path)
+        .with_context(|| format!("Failed to open observations file: {}", path.display()))?;
+
+    let feature_ids: Vec<i64> = file
+        .variable("feature_id")
+        .ok_or_else(|| anyhow::anyhow!("feature_id variable not found in {}", path.display()))?
+        .get_values(..)
+        .context("Failed to read observation feature ids")?;
+    let flow_var = file
+        .variable("flow")
+        .ok_or_else(|| anyhow::anyhow!("flow variable not found in {}", path.display()))?;
+
+    // Negative ids, such as unwritten slots holding the integer fill value, name no reach
+    let mut observations = HashMap::new();
+    let mut negative = Vec::new();
+    for (i, &feature_id) in feature_ids.iter().enumerate() {
+        let Ok(id) = u64::try_from(feature_id) else {
+            negative.push(feature_id);
+            continue;
+        };
+        let flows: Vec<f32> = flow_var
+            .get_values((i, ..))
+            .with_context(|| format!("Failed to read observations for feature {}", feature_id))?;
+        observations.insert(id, flows);
+    }
+
+    println!(
+        "Loaded observations for {} gauged reaches",
+        observations.len()
+    );
+    if !negative.is_empty() {
+        println!(
+            "Skipped {} observation series with negative feature ids (first: {:?})",
+            negative.len(),
+            &negative[..negative.len().min(10)]
+        );
+    }
+    Ok(observations)
+}
+
+// Tracks the correction applied at one gauged reach as it routes
+pub struct Nudger<'a> {
+    observed: &'a [f32],
+    decay_seconds: f32,
+    last_nudge: f32,
+    since_observation: f32,
+}
+
+impl Nudger<'_> {
+    // Blend the simulated flow toward the observation for this external step.
+    // Without an observation the last correction decays exponentially.
+    // Returns (nudged flow, applied nudge).
+    pub fn apply(&mut self, external_step: usize, simulated: f32, dt: f32) -> (f32, f32) {
+        let nudge = match self.observed.get(external_step) {
+            Some(&observed) if observed.is_finite() && observed >= 0.0 => {
+                self.last_nudge = observed - simulated;
+                self.since_observation = 0.0;
+                self.last_nudge
+            }
+            _ => {
+                self.since_observation += dt;
+                self.last_nudge * (-self.since_observation / self.decay_seconds).exp()
+            }
+        };
+
+        let nudged = f32::max(simulated + nudge, 0.0);
+        (nudged, nudged - simulated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correction_matches_observation_then_decays() {
+        let nudging = Nudging::new(HashMap::from([(7, vec![12.0, f32::NAN, -9999.0])]), 3600.0);
+        assert!(nudging.nudger(8).is_none());
+
+        let mut nudger = nudging.nudger(7).unwrap();
+        assert_eq!(nudger.apply(0, 10.0, 3600.0), (12.0, 2.0));
+
+        // Missing observations: the +2 correction fades with an e-folding time of one step
+        let (flow, nudge) = nudger.apply(1, 10.0, 3600.0);
+        assert!((nudge - 2.0 * (-1.0f32).exp()).abs() < 1e-6);
+        assert_eq!(flow, 10.0 + nudge);
+        let (_, nudge) = nudger.apply(2, 10.0, 3600.0);
+        assert!((nudge - 2.0 * (-2.0f32).exp()).abs() < 1e-6);
+
+        // Past the end of the record behaves like a missing observation
+        let (_, nudge) = nudger.apply(10, 10.0, 3600.0);
+        assert!(nudge < 2.0 * (-2.0f32).exp());
+    }
+
+    #[test]
+    fn nudged_flow_is_never_negative() {
+        let nudging = Nudging::new(HashMap::from([(1, vec![0.0])]), 3600.0);
+        let mut nudger = nudging.nudger(1).unwrap();
+        assert_eq!(nudger.apply(0, 5.0, 300.0), (0.0, -5.0));
+    }
+
+    #[test]
+    fn observations_with_negative_ids_are_skipped() {
+        let path = std::env::temp_dir().join("route_rs_negative_observations.nc");
+        {
+            let mut file = netcdf::create(&path).unwrap();
+            file.add_dimension("feature_id", 2).unwrap();
+            file.add_dimension("time", 2).unwrap();
+            file.add_variable::<i64>("feature_id", &["feature_id"])
+                .unwrap()
+                .put_values(&[5, -9999], ..)
+                .unwrap();
+            file.add_variable::<f32>("flow", &["feature_id", "time"])
+                .unwrap()
+                .put_values(&[1.0, 2.0, 3.0, 4.0], ..)
+                .unwrap();
+        }
+        let observations = load_observations(&path).unwrap();
+        assert_eq!(observations, HashMap::from([(5, vec![1.0, 2.0])]));
+    }
+}
@@ -5,6 +5,34 @@ pub struct SimulationResults {
     pub flow_data: Vec<f32>,
     pub velocity_data: Vec<f32>,
     pub depth_data: Vec<f32>,
+    // Flow correction applied at each timestep; empty for reaches that weren't nudged
+    pub nudge_data: Vec<f32>,
+    // Muskingum weighting X and kinematic celerity (m/s) at each timestep; empty unless
+    // requested with --emit-muskingum
+    pub musk_x_data: Vec<f32>,
+    pub celerity_data: Vec<f32>,
+    // Routed outflow volume (m3) since the start of the output cycle, at the end of each
+    // timestep; empty unless requested with --emit-cumulative-volume
+    pub cumulative_volume_data: Vec<f32>,
+    // Outflow over the spin-up cycles, which feeds the downstream reach but isn't output
+    pub spinup_flow: Vec<f32>,
+    // Internal timesteps at which the kernel failed to converge
+    pub nonconverged: Vec<usize>,
+    // Kernel iterations across all timesteps, and wall time spent routing the reach
+    pub iterations: u64,
+    pub wall_micros: u64,
+    // Most kernel iterations in any one timestep
+    pub max_iterations: u32,
+    // Travel time dx/ck summed over the timesteps with flow, and how many there were
+    pub travel_time: f64,
+    pub flowing_steps: u32,
+    // The reach ran out of its iteration budget and finished at normal flow
+    pub iteration_capped: bool,
+    // Output timesteps whose outflow was raised to --min-flow
+    pub floored_steps: u32,
+    // Forcing steps found and the number the run needed, when they differed and the
+    // record was padded with zeros or truncated
+    pub forcing_mismatch: Option<(usize, usize)>,
 }
 
 impl SimulationResults {
@@ -14,6 +42,20 @@ impl SimulationResults {
             flow_data: Vec::new(),
             velocity_data: Vec::new(),
             depth_data: Vec::new(),
+            nudge_data: Vec::new(),
+            musk_x_data: Vec::new(),
+            celerity_data: Vec::new(),
+            cumulative_volume_data: Vec::new(),
+            spinup_flow: Vec::new(),
+            nonconverged: Vec::new(),
+            iterations: 0,
+            wall_micros: 0,
+            max_iterations: 0,
+            travel_time: 0.0,
+            flowing_steps: 0,
+            iteration_capped: false,
+            floored_steps: 0,
+            forcing_mismatch: None,
         }
     }
 }
\ No newline at end of file
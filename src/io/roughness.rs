@@ -0,0 +1,118 @@
+use crate::io::csv::Table;
+use crate::network::IdParser;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+// A roughness change taking effect at an external (forcing) step
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RoughnessChange {
+    step: usize,
+    n: f32,
+    ncc: Option<f32>,
+}
+
+// Time-varying Manning's roughness for reaches whose n changes over the run,
+// e.g. with ice cover or seasonal vegetation
+#[derive(Debug, Clone, Default)]
+pub struct Roughness {
+    changes: HashMap<u64, Vec<RoughnessChange>>,
+}
+
+impl Roughness {
+    // Load a CSV with columns `feature_id,step,n` and optionally `ncc`. Each row sets the
+    // reach's roughness from that external step until its next row; before its first
+    // row, and where `ncc` is blank, the static channel parameters apply. Ids are read
+    // with the network's id pattern.
+    pub fn load(path: &Path, id_parser: &IdParser) -> Result<Self> {
+        let table = Table::open(path, "roughness")?;
+        let (id_index, step_index, n_index) = (
+            table.required("feature_id")?,
+            table.required("step")?,
+            table.required("n")?,
+        );
+        let ncc_index = table.column("ncc");
+
+        let mut changes: HashMap<u64, Vec<RoughnessChange>> = HashMap::new();
+        table.for_each(|row| {
+            let feature_id = row.feature_id(id_index, id_parser)?;
+            let step: usize = row.parse(step_index, "step")?;
+            let n: f32 = row.parse(n_index, "n")?;
+            let ncc = match ncc_index {
+                Some(index) if !row.get(index).is_empty() => Some(row.parse::<f32>(index, "ncc")?),
+                _ => None,
+            };
+            if n <= 0.0 || ncc.is_some_and(|ncc| ncc <= 0.0) {
+                return Err(anyhow::anyhow!(
+                    "Roughness must be positive in record {} (feature {})",
+                    row.index,
+                    feature_id
+                ));
+            }
+            changes
+                .entry(feature_id)
+                .or_default()
+                .push(RoughnessChange { step, n, ncc });
+            Ok(())
+        })?;
+
+        for reach_changes in changes.values_mut() {
+            reach_changes.sort_by_key(|change| change.step);
+        }
+        println!(
+            "Loaded time-varying roughness for {} reaches",
+            changes.len()
+        );
+        Ok(Roughness { changes })
+    }
+
+    // The roughness schedule for this reach, or None when it only has static roughness
+    pub fn for_reach(&self, feature_id: u64) -> Option<ReachRoughness<'_>> {
+        self.changes
+            .get(&feature_id)
+            .map(|changes| ReachRoughness { changes })
+    }
+}
+
+// One reach's roughness changes
+pub struct ReachRoughness<'a> {
+    changes: &'a [RoughnessChange],
+}
+
+impl ReachRoughness<'_> {
+    // (n, ncc) in effect at this external step, falling back to the static values
+    pub fn at(&self, external_step: usize, n: f32, ncc: f32) -> (f32, f32) {
+        let applied = self
+            .changes
+            .partition_point(|change| change.step <= external_step);
+        match applied.checked_sub(1).map(|index| self.changes[index]) {
+            Some(change) => (change.n, change.ncc.unwrap_or(ncc)),
+            None => (n, ncc),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roughness_holds_until_the_next_change() {
+        let path = std::env::temp_dir().join("route_rs_roughness.csv");
+        std::fs::write(
+            &path,
+            "feature_id,step,n,ncc\nwb-7,24,0.1,\n7,2,0.08,0.2\n9,0,0.05,0.1\n",
+        )
+        .unwrap();
+        let id_parser = IdParser::new(r"^\D*?0*(\d+)$").unwrap();
+        let roughness = Roughness::load(&path, &id_parser).unwrap();
+        assert!(roughness.for_reach(8).is_none());
+
+        let reach = roughness.for_reach(7).unwrap();
+        assert_eq!(reach.at(0, 0.06, 0.12), (0.06, 0.12));
+        assert_eq!(reach.at(2, 0.06, 0.12), (0.08, 0.2));
+        assert_eq!(reach.at(23, 0.06, 0.12), (0.08, 0.2));
+        // A blank ncc keeps the static compound roughness
+        assert_eq!(reach.at(100, 0.06, 0.12), (0.1, 0.12));
+    }
+}
@@ -0,0 +1,325 @@
+use crate::console_println;
+use crate::io::results::SimulationResults;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// Quantiles reported for every variable
+const QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+// P-squared streaming quantile estimator (Jain & Chlamtac, 1985): constant memory per quantile
+#[derive(Debug, Clone)]
+struct P2Quantile {
+    p: f64,
+    heights: [f64; 5],
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+    count: usize,
+}
+
+impl P2Quantile {
+    fn new(p: f64) -> Self {
+        P2Quantile {
+            p,
+            heights: [0.0; 5],
+            positions: [1.0, 2.0, 3.0, 4.0, 5.0],
+            desired: [1.0, 1.0 + 2.0 * p, 1.0 + 4.0 * p, 3.0 + 2.0 * p, 5.0],
+            increments: [0.0, p / 2.0, p, (1.0 + p) / 2.0, 1.0],
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, x: f64) {
+        // The first five observations seed the markers directly
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_by(|a, b| a.total_cmp(b));
+            }
+            return;
+        }
+        self.count += 1;
+
+        let k = if x < self.heights[0] {
+            self.heights[0] = x;
+            0
+        } else if x >= self.heights[4] {
+            self.heights[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < self.heights[i + 1]).unwrap_or(3)
+        };
+
+        for position in &mut self.positions[k + 1..] {
+            *position += 1.0;
+        }
+        for (desired, increment) in self.desired.iter_mut().zip(self.increments) {
+            *desired += increment;
+        }
+
+        // Adjust the three middle markers toward their desired positions
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            if (d >= 1.0 && self.positions[i + 1] - self.positions[i] > 1.0)
+                || (d <= -1.0 && self.positions[i - 1] - self.positions[i] < -1.0)
+            {
+                let d = d.signum();
+                let parabolic = self.parabolic(i, d);
+                self.heights[i] =
+                    if self.heights[i - 1] < parabolic && parabolic < self.heights[i + 1] {
+                        parabolic
+                    } else {
+                        self.linear(i, d)
+                    };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (q, n) = (&self.heights, &self.positions);
+        q[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (q[i + 1] - q[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (q[i] - q[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let j = if d > 0.0 { i + 1 } else { i - 1 };
+        self.heights[i]
+            + d * (self.heights[j] - self.heights[i]) / (self.positions[j] - self.positions[i])
+    }
+
+    fn value(&self) -> Option<f64> {
+        match self.count {
+            0 => None,
+            // Too few observations for the markers; take the exact quantile of what we have
+            1..=4 => {
+                let mut seen = self.heights[..self.count].to_vec();
+                seen.sort_by(|a, b| a.total_cmp(b));
+                let idx = ((seen.len() - 1) as f64 * self.p).round() as usize;
+                Some(seen[idx])
+            }
+            _ => Some(self.heights[2]),
+        }
+    }
+}
+
+// Online summary statistics for one output variable
+#[derive(Debug, Clone)]
+pub struct StreamingStats {
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    m2: f64,
+    quantiles: Vec<P2Quantile>,
+}
+
+impl Default for StreamingStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamingStats {
+    pub fn new() -> Self {
+        StreamingStats {
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            mean: 0.0,
+            m2: 0.0,
+            quantiles: QUANTILES.iter().map(|&p| P2Quantile::new(p)).collect(),
+        }
+    }
+
+    pub fn add(&mut self, value: f32) {
+        if !value.is_finite() {
+            return;
+        }
+        let x = value as f64;
+        self.count += 1;
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+
+        // Welford's update keeps the variance numerically stable over billions of values
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+
+        for quantile in &mut self.quantiles {
+            quantile.add(x);
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        if self.count == 0 {
+            return json!({ "count": 0 });
+        }
+        let percentiles: serde_json::Map<String, serde_json::Value> = self
+            .quantiles
+            .iter()
+            .map(|q| (format!("p{}", (q.p * 100.0).round()), json!(q.value())))
+            .collect();
+        json!({
+            "count": self.count,
+            "min": self.min,
+            "max": self.max,
+            "mean": self.mean,
+            "std": (self.m2 / self.count as f64).sqrt(),
+            "percentiles": percentiles,
+        })
+    }
+}
+
+// Domain-wide statistics for each routed variable, fed by the writer thread
+pub struct OutputStatistics {
+    path: PathBuf,
+    flow: StreamingStats,
+    velocity: StreamingStats,
+    depth: StreamingStats,
+}
+
+impl OutputStatistics {
+    pub fn new(path: PathBuf) -> Self {
+        OutputStatistics {
+            path,
+            flow: StreamingStats::new(),
+            velocity: StreamingStats::new(),
+            depth: StreamingStats::new(),
+        }
+    }
+
+    pub fn add(&mut self, results: &SimulationResults) {
+        results.flow_data.iter().for_each(|&v| self.flow.add(v));
+        results
+            .velocity_data
+            .iter()
+            .for_each(|&v| self.velocity.add(v));
+        results.depth_data.iter().for_each(|&v| self.depth.add(v));
+    }
+
+    pub fn finish(self) -> Result<()> {
+        let summary = json!({
+            "flow": self.flow.to_json(),
+            "velocity": self.velocity.to_json(),
+            "depth": self.depth.to_json(),
+        });
+        let content = serde_json::to_string_pretty(&summary)?;
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write statistics to {}", self.path.display()))?;
+        console_println!("Output statistics saved to {}", self.path.display());
+        Ok(())
+    }
+}
+
+// Each reach's highest routed flow, collected by the workers for --peak-flow-geojson.
+// Over several forcing members a reach keeps the highest of their peaks.
+#[derive(Debug, Clone, Default)]
+pub struct PeakFlows(Arc<Mutex<HashMap<u64, f32>>>);
+
+impl PeakFlows {
+    pub fn record(&self, feature_id: u64, flow: &[f32]) -> Result<()> {
+        let Some(peak) = flow
+            .iter()
+            .copied()
+            .filter(|q| q.is_finite())
+            .reduce(f32::max)
+        else {
+            return Ok(());
+        };
+        let mut peaks = self
+            .0
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock peak flows: {}", e))?;
+        let entry = peaks.entry(feature_id).or_insert(peak);
+        *entry = entry.max(peak);
+        Ok(())
+    }
+
+    pub fn peaks(&self) -> Result<HashMap<u64, f32>> {
+        Ok(self
+            .0
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock peak flows: {}", e))?
+            .clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn p2_estimates_quantiles_of_a_shuffled_uniform_stream() {
+        // 0..10007 visited in a scrambled order (10007 is prime, so every value appears once)
+        let n = 10_007;
+        let values: Vec<f64> = (0..n).map(|i| ((i * 7_919) % n) as f64).collect();
+        for p in QUANTILES {
+            let mut quantile = P2Quantile::new(p);
+            values.iter().for_each(|&x| quantile.add(x));
+            let expected = p * (n - 1) as f64;
+            let estimate = quantile.value().unwrap();
+            assert!(
+                (estimate - expected).abs() < 0.01 * n as f64,
+                "p{}: {} vs {}",
+                p,
+                estimate,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn p2_tracks_a_skewed_stream() {
+        // Exponential quantiles: -ln(1 - u) for evenly spread u
+        let n = 20_000;
+        let mut median = P2Quantile::new(0.5);
+        let mut tail = P2Quantile::new(0.95);
+        for i in 0..n {
+            let u = ((i * 12_347) % n) as f64 / n as f64;
+            let x = -(1.0 - u).ln();
+            median.add(x);
+            tail.add(x);
+        }
+        assert!((median.value().unwrap() - 2f64.ln()).abs() < 0.02);
+        assert!((tail.value().unwrap() - 20f64.ln()).abs() < 0.1);
+    }
+
+    #[test]
+    fn p2_is_exact_before_the_markers_are_seeded() {
+        let mut quantile = P2Quantile::new(0.5);
+        assert_eq!(quantile.value(), None);
+        for x in [9.0, 1.0, 5.0] {
+            quantile.add(x);
+        }
+        assert_eq!(quantile.value(), Some(5.0));
+
+        // Five identical values seed flat markers that stay put
+        let mut quantile = P2Quantile::new(0.95);
+        for _ in 0..100 {
+            quantile.add(3.0);
+        }
+        assert_eq!(quantile.value(), Some(3.0));
+    }
+
+    #[test]
+    fn streaming_stats_skip_non_finite_values() {
+        let mut stats = StreamingStats::new();
+        for value in [1.0, 2.0, f32::NAN, 3.0, f32::INFINITY, 4.0] {
+            stats.add(value);
+        }
+        let summary = stats.to_json();
+        assert_eq!(summary["count"], 4);
+        assert_eq!(summary["min"], 1.0);
+        assert_eq!(summary["max"], 4.0);
+        assert_eq!(summary["mean"], 2.5);
+        assert!((summary["std"].as_f64().unwrap() - 1.25f64.sqrt()).abs() < 1e-12);
+        assert_eq!(summary["percentiles"]["p50"], 3.0);
+        assert_eq!(StreamingStats::new().to_json(), json!({ "count": 0 }));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::console_println;
+use crate::io::results::SimulationResults;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+// Per-reach routing cost, fed by the writer thread and written slowest first
+pub struct TimingReport {
+    path: PathBuf,
+    timings: Vec<(i64, u64, u64)>,
+}
+
+impl TimingReport {
+    pub fn new(path: PathBuf) -> Self {
+        TimingReport {
+            path,
+            timings: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, results: &SimulationResults) {
+        self.timings
+            .push((results.feature_id, results.wall_micros, results.iterations));
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.timings
+            .sort_unstable_by_key(|&(_, wall_micros, _)| std::cmp::Reverse(wall_micros));
+
+        let mut wtr = csv::Writer::from_path(&self.path)
+            .with_context(|| format!("Failed to create timings file: {}", self.path.display()))?;
+        wtr.write_record(["feature_id", "wall_micros", "total_iterations"])
+            .context("Failed to write timings header")?;
+        for (feature_id, wall_micros, iterations) in &self.timings {
+            wtr.write_record([
+                feature_id.to_string(),
+                wall_micros.to_string(),
+                iterations.to_string(),
+            ])
+            .context("Failed to write timings record")?;
+        }
+        wtr.flush().context("Failed to flush timings file")?;
+
+        console_println!("Per-reach timings saved to {}", self.path.display());
+        Ok(())
+    }
+}
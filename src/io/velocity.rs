@@ -0,0 +1,86 @@
+use crate::console_println;
+use crate::io::csv::format_float;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// One timestep at which a reach's velocity exceeded the threshold, with the channel
+// data most likely to explain it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlaggedStep {
+    pub feature_id: u64,
+    pub timestep: usize,
+    pub velocity: f32,
+    pub s0: f32,
+    pub dx: f32,
+}
+
+// Reach timesteps faster than --flag-velocity-above, collected by the workers as each
+// reach is routed and written to a QA report once routing finishes
+#[derive(Debug, Clone)]
+pub struct VelocityCheck {
+    pub threshold: f32,
+    flagged: Arc<Mutex<Vec<FlaggedStep>>>,
+}
+
+impl VelocityCheck {
+    pub fn new(threshold: f32) -> Self {
+        VelocityCheck {
+            threshold,
+            flagged: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn record(&self, steps: Vec<FlaggedStep>) -> Result<()> {
+        if steps.is_empty() {
+            return Ok(());
+        }
+        self.flagged
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock velocity flags: {}", e))?
+            .extend(steps);
+        Ok(())
+    }
+
+    // Flagged timesteps ordered by reach, then timestep
+    pub fn flagged(&self) -> Result<Vec<FlaggedStep>> {
+        let mut flagged = self
+            .flagged
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock velocity flags: {}", e))?
+            .clone();
+        flagged.sort_unstable_by_key(|step| (step.feature_id, step.timestep));
+        Ok(flagged)
+    }
+
+    // Write the report, with values rounded to `precision` significant digits
+    pub fn finish(&self, path: &Path, precision: usize) -> Result<()> {
+        let flagged = self.flagged()?;
+        let mut wtr = csv::Writer::from_path(path)
+            .with_context(|| format!("Failed to create velocity report: {}", path.display()))?;
+        wtr.write_record(["feature_id", "timestep", "velocity", "s0", "dx"])
+            .context("Failed to write velocity report header")?;
+        for step in &flagged {
+            wtr.write_record([
+                step.feature_id.to_string(),
+                step.timestep.to_string(),
+                format_float(step.velocity, precision),
+                format_float(step.s0, precision),
+                format_float(step.dx, precision),
+            ])
+            .context("Failed to write velocity report record")?;
+        }
+        wtr.flush().context("Failed to flush velocity report")?;
+
+        let mut reaches: Vec<u64> = flagged.iter().map(|step| step.feature_id).collect();
+        reaches.dedup();
+        console_println!(
+            "{} timesteps on {} reaches exceed {} m/s; listed in {}",
+            flagged.len(),
+            reaches.len(),
+            self.threshold,
+            path.display()
+        );
+        Ok(())
+    }
+}
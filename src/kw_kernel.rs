@@ -0,0 +1,327 @@
+use crate::config::SolverConfig;
+use crate::error::RouteError;
+use crate::mc_kernel::{KernelOutput, RoutingKernel};
+
+/// `kinematic_wave` as a `RoutingKernel`
+pub struct KinematicWave;
+
+impl RoutingKernel for KinematicWave {
+    fn route(
+        &self,
+        qup: f32,
+        quc: f32,
+        qdp: f32,
+        ql: f32,
+        dt: f32,
+        so: f32,
+        dx: f32,
+        n: f32,
+        cs: f32,
+        bw: f32,
+        tw: f32,
+        tw_cc: f32,
+        n_cc: f32,
+        depth_p: f32,
+        solver: &SolverConfig,
+    ) -> Result<KernelOutput, RouteError> {
+        kinematic_wave(
+            qup, quc, qdp, ql, dt, so, dx, n, cs, bw, tw, tw_cc, n_cc, depth_p, solver,
+        )
+    }
+}
+
+/// `passthrough` as a `RoutingKernel`
+pub struct Passthrough;
+
+impl RoutingKernel for Passthrough {
+    fn route(
+        &self,
+        qup: f32,
+        quc: f32,
+        qdp: f32,
+        ql: f32,
+        dt: f32,
+        so: f32,
+        dx: f32,
+        n: f32,
+        cs: f32,
+        bw: f32,
+        tw: f32,
+        tw_cc: f32,
+        n_cc: f32,
+        depth_p: f32,
+        solver: &SolverConfig,
+    ) -> Result<KernelOutput, RouteError> {
+        passthrough(
+            qup, quc, qdp, ql, dt, so, dx, n, cs, bw, tw, tw_cc, n_cc, depth_p, solver,
+        )
+    }
+}
+
+/// Kinematic-wave routing for steep reaches where the Muskingum-Cunge secant
+/// solve is unnecessary. Celerity comes from the normal depth of a reference
+/// flow and the Muskingum weighting is fixed at X = 0, so there is no
+/// diffusion term. Only the in-channel trapezoid is used (extended above
+/// bankfull), which is adequate for the small headwater reaches this targets.
+///
+/// Same arguments and output as `mc_kernel::submuskingcunge` so the two can
+/// be swapped per reach.
+#[allow(clippy::too_many_arguments)]
+pub fn kinematic_wave(
+    qup: f32,      // flow upstream previous timestep
+    quc: f32,      // flow upstream current timestep
+    qdp: f32,      // flow downstream previous timestep
+    ql: f32,       // lateral inflow through reach (m^3/sec)
+    dt: f32,       // routing period in seconds
+    so: f32,       // channel bottom slope (as fraction, not %)
+    dx: f32,       // channel length (m)
+    n: f32,        // mannings coefficient
+    cs: f32,       // channel side slope
+    bw: f32,       // bottom width (meters)
+    _tw: f32,      // top width before bankfull (meters)
+    _tw_cc: f32,   // top width of compound (meters)
+    _n_cc: f32,    // mannings of compound
+    _depth_p: f32, // depth of flow in channel
+    _solver: &SolverConfig,
+) -> Result<KernelOutput, RouteError> {
+    let z = if cs == 0.0 { 1.0 } else { 1.0 / cs };
+
+    if n <= 0.0 || so <= 0.0 || bw <= 0.0 {
+        return Err(RouteError::Kernel {
+            reaches: Vec::new(),
+            message: format!(
+                "Error in channel coefficients -> kinematic wave: n={}, so={}, z={}, bw={}",
+                n, so, z, bw
+            ),
+        });
+    }
+
+    if !(ql > 0.0 || qup > 0.0 || quc > 0.0 || qdp > 0.0) {
+        return Ok(KernelOutput {
+            qdc: 0.0,
+            velc: 0.0,
+            depthc: 0.0,
+            ck: 0.0,
+            cn: 0.0,
+            x: 0.0,
+            converged: true,
+            iterations: 0,
+            travel_time: 0.0,
+        });
+    }
+
+    // Celerity of the mean flow through the reach over the timestep
+    let q_ref = f32::max(0.5 * (quc + qdp) + 0.5 * ql, 0.0);
+    let ck = celerity(normal_depth(q_ref, so, n, bw, z), so, n, bw, z);
+
+    let travel_time = if ck > 0.0 { dx / ck } else { 0.0 };
+    let km = if ck > 0.0 {
+        f32::max(dt, travel_time)
+    } else {
+        dt
+    };
+    let d = km + dt / 2.0;
+    let c1 = (dt / 2.0) / d;
+    let c2 = (dt / 2.0) / d;
+    let c3 = (km - dt / 2.0) / d;
+    let c4 = (ql * dt) / d;
+
+    let qdc = f32::max(c1 * qup + c2 * quc + c3 * qdp + c4, 0.0);
+    let depth_c = normal_depth(qdc, so, n, bw, z);
+
+    let velc = if depth_c > 0.0 {
+        (1.0 / n) * hydraulic_radius(depth_c, bw, z).powf(2.0 / 3.0) * so.sqrt()
+    } else {
+        0.0
+    };
+
+    Ok(KernelOutput {
+        qdc,
+        velc,
+        depthc: depth_c,
+        ck,
+        cn: ck * (dt / dx),
+        x: 0.0,
+        converged: true,
+        iterations: 0,
+        travel_time,
+    })
+}
+
+/// Passthrough for reaches too short (or modeled elsewhere) to attenuate flow:
+/// outflow is the current upstream plus lateral inflow, at the in-channel Manning
+/// normal depth. Same arguments and output as `mc_kernel::submuskingcunge`.
+#[allow(clippy::too_many_arguments)]
+pub fn passthrough(
+    _qup: f32,
+    quc: f32,
+    _qdp: f32,
+    ql: f32,
+    _dt: f32,
+    so: f32,
+    _dx: f32,
+    n: f32,
+    cs: f32,
+    bw: f32,
+    _tw: f32,
+    _tw_cc: f32,
+    _n_cc: f32,
+    _depth_p: f32,
+    _solver: &SolverConfig,
+) -> Result<KernelOutput, RouteError> {
+    let z = if cs == 0.0 { 1.0 } else { 1.0 / cs };
+    let qdc = f32::max(quc + ql, 0.0);
+    let depthc = if n > 0.0 && so > 0.0 && bw > 0.0 {
+        normal_depth(qdc, so, n, bw, z)
+    } else {
+        0.0
+    };
+    let velc = if depthc > 0.0 {
+        (1.0 / n) * hydraulic_radius(depthc, bw, z).powf(2.0 / 3.0) * so.sqrt()
+    } else {
+        0.0
+    };
+
+    Ok(KernelOutput {
+        qdc,
+        velc,
+        depthc,
+        ck: 0.0,
+        cn: 0.0,
+        x: 0.0,
+        converged: true,
+        iterations: 0,
+        travel_time: 0.0,
+    })
+}
+
+pub(crate) fn hydraulic_radius(h: f32, bw: f32, z: f32) -> f32 {
+    let area = (bw + h * z) * h;
+    let wp = bw + 2.0 * h * (1.0 + z * z).sqrt();
+    area / wp
+}
+
+// Manning discharge of the trapezoid at depth h
+fn manning_flow(h: f32, so: f32, n: f32, bw: f32, z: f32) -> f32 {
+    let area = (bw + h * z) * h;
+    (1.0 / n) * area * hydraulic_radius(h, bw, z).powf(2.0 / 3.0) * so.sqrt()
+}
+
+// Kinematic celerity dQ/dA of the trapezoid at depth h
+pub(crate) fn celerity(h: f32, so: f32, n: f32, bw: f32, z: f32) -> f32 {
+    if h <= 0.0 {
+        return 0.0;
+    }
+    let r = hydraulic_radius(h, bw, z);
+    f32::max(
+        0.0,
+        (so.sqrt() / n)
+            * ((5.0 / 3.0) * r.powf(2.0 / 3.0)
+                - (2.0 / 3.0)
+                    * r.powf(5.0 / 3.0)
+                    * (2.0 * (1.0 + z * z).sqrt() / (bw + 2.0 * h * z))),
+    )
+}
+
+// Depth at which Manning flow equals q, by bisection (Manning flow is monotonic in depth)
+pub(crate) fn normal_depth(q: f32, so: f32, n: f32, bw: f32, z: f32) -> f32 {
+    if q <= 0.0 {
+        return 0.0;
+    }
+    let mut lo = 0.0;
+    let mut hi = 1.0;
+    while manning_flow(hi, so, n, bw, z) < q && hi < 1.0e4 {
+        hi *= 2.0;
+    }
+    for _ in 0..40 {
+        let mid = 0.5 * (lo + hi);
+        if manning_flow(mid, so, n, bw, z) < q {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    0.5 * (lo + hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+    use crate::mc_kernel::submuskingcunge;
+
+    #[test]
+    fn agrees_with_muskingum_cunge_on_moderate_slope() {
+        let params = fixtures::default_params();
+        let solver = SolverConfig::default();
+        let dt = 300.0;
+
+        // Triangular inflow hydrograph peaking at 20 m3/s
+        let inflow: Vec<f32> = (0..120)
+            .map(|step| {
+                let t = step as f32;
+                if t < 20.0 {
+                    1.0 + t
+                } else {
+                    f32::max(1.0, 21.0 - (t - 20.0) * 0.5)
+                }
+            })
+            .collect();
+
+        let route = |kinematic: bool| -> Vec<f32> {
+            let kernel = if kinematic {
+                kinematic_wave
+            } else {
+                submuskingcunge
+            };
+            let (mut qup, mut qdp, mut depth_p) = (0.0, 0.0, 0.0);
+            inflow
+                .iter()
+                .map(|&quc| {
+                    let output = kernel(
+                        qup,
+                        quc,
+                        qdp,
+                        0.0,
+                        dt,
+                        params.s0,
+                        params.dx,
+                        params.n,
+                        params.cs,
+                        params.bw,
+                        params.tw,
+                        params.twcc,
+                        params.ncc,
+                        depth_p,
+                        &solver,
+                    )
+                    .unwrap();
+                    qup = quc;
+                    qdp = output.qdc;
+                    depth_p = output.depthc;
+                    output.qdc
+                })
+                .collect()
+        };
+
+        let kw = route(true);
+        let mc = route(false);
+
+        let peak = |series: &[f32]| series.iter().cloned().fold(0.0, f32::max);
+        let peak_step = |series: &[f32]| {
+            series
+                .iter()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(b.1))
+                .unwrap()
+                .0
+        };
+        // Without diffusion the kinematic peak is slightly higher and earlier
+        assert!(peak(&kw) >= peak(&mc));
+        assert!((peak(&kw) - peak(&mc)) / peak(&mc) < 0.05);
+        assert!(peak_step(&mc) - peak_step(&kw) <= 3);
+        let total = |series: &[f32]| series.iter().sum::<f32>();
+        assert!((total(&kw) - total(&inflow)).abs() / total(&inflow) < 0.01);
+        assert!((total(&kw) - total(&mc)).abs() / total(&mc) < 0.05);
+    }
+}
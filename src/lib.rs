@@ -0,0 +1,16 @@
+pub mod config;
+pub mod console;
+pub mod error;
+#[cfg(test)]
+mod fixtures;
+pub mod graph;
+pub mod interrupt;
+pub mod io;
+pub mod kw_kernel;
+pub mod mc_kernel;
+pub mod memory;
+pub mod network;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod routing;
+pub mod state;
@@ -1,26 +1,67 @@
 use anyhow::{Context, Result};
 use chrono::{Duration, NaiveDateTime};
-use indicatif::{ProgressBar, ProgressStyle};
-use std::collections::HashMap;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
 use std::sync::Arc;
 
 mod cli;
-mod config;
-mod io;
-mod mc_kernel;
-mod network;
-mod routing;
-mod state;
-
-use cli::get_args;
-use config::{ChannelParams, ColumnConfig, OutputFormat};
-use io::netcdf::init_netcdf_output;
-use network::build_network_topology;
-use routing::process_routing_parallel;
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches};
+use cli::{Args, Command, get_args};
+use route_rs::config::{
+    self, ChannelParams, ColumnConfig, FeatureOrder, ForcingLayout, NonconvergenceFallback,
+    OutputFormat, OutputOptions, OutputSplit, OutputVariable, ProgressMode, ReachType,
+    SolverConfig, TimeFormat,
+};
+use route_rs::console_warn;
+use route_rs::io::{
+    self,
+    aggregate::{GroupAggregator, load_aggregate_map},
+    cache::ResultsCache,
+    forcing_window::ForcingWindow,
+    manifest::{RunManifest, file_digest},
+    netcdf::{
+        check_time_dimension, init_chunked_output, ordered_feature_index, resume_output,
+        sorted_feature_index, verify_output, write_chunk_index,
+    },
+    nudge::{Nudging, load_observations},
+    roughness::Roughness,
+    stats::{OutputStatistics, PeakFlows},
+    timings::TimingReport,
+    velocity::VelocityCheck,
+};
+use route_rs::memory::{self, spawn_memory_report};
+use route_rs::network::{self, NetworkTopology, build_network_topology};
+use route_rs::routing::{
+    RoutingOptions, process_routing_parallel, replay_results, resume_skip_set, spawn_json_progress,
+};
 
 fn main() -> Result<()> {
     // Configuration
-    let (_, csv_dir, db_path, internal_timestep_seconds) = get_args()?;
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+    let (_, csv_dir, db_path, args) = get_args(args)?;
+    let mut manifest = RunManifest::start(args.run_manifest.clone());
+    manifest.record(
+        "command_line",
+        serde_json::json!(std::env::args().collect::<Vec<_>>()),
+    );
+    manifest.record("options", effective_options(&matches));
+    // Read once: the results cache's input hash reuses it
+    let gpkg_digest = file_digest(&db_path)?;
+    manifest.record(
+        "gpkg",
+        serde_json::json!({
+            "path": db_path.display().to_string(),
+            "fnv1a64": gpkg_digest,
+        }),
+    );
+    let internal_timestep_seconds = args.internal_timestep_seconds;
     let dt = internal_timestep_seconds as f32;
     let output_format = OutputFormat::NetCdf;
 
@@ -28,102 +69,1316 @@ fn main() -> Result<()> {
     let conn = rusqlite::Connection::open(&db_path)
         .with_context(|| format!("Failed to open database: {:?}", db_path))?;
 
-    let column_config = ColumnConfig::new();
+    let mut column_config = ColumnConfig::new();
+    if let Some(id_pattern) = &args.id_pattern {
+        column_config.id_pattern = id_pattern.clone();
+    }
 
     // Build network topology
     println!("Building network topology...");
-    let topology = build_network_topology(&conn, &column_config, &csv_dir)?;
+    let mut topology = build_network_topology(&conn, &column_config, &csv_dir, args.strict)?;
+    if let Some(path) = &args.lateral_weights {
+        let id_parser = network::IdParser::new(&column_config.id_pattern)?;
+        let weights = io::csv::load_lateral_weights(path, &id_parser)?;
+        println!("Loaded lateral weights for {} reaches", weights.len());
+        network::apply_lateral_weights(&mut topology, &weights, args.strict)?;
+    }
+    // Reach types from the gpkg column, then the override file on top
+    let mut reach_types = HashMap::new();
+    if let Some(column) = &args.reach_type_column {
+        reach_types = network::load_reach_type_column(&conn, &column_config, column)?;
+    }
+    if let Some(path) = &args.reach_types {
+        let id_parser = network::IdParser::new(&column_config.id_pattern)?;
+        reach_types.extend(io::csv::load_reach_types(path, &id_parser)?);
+    }
+    if !reach_types.is_empty() {
+        println!("Loaded reach types for {} reaches", reach_types.len());
+        network::apply_reach_types(&mut topology, &reach_types, args.strict)?;
+    }
+
+    // Single-file forcing layouts are read up front and held in memory, unless read
+    // window by window as routing reaches them
+    let mut forcing_window = None;
+    if let Some(forcing_file) = &args.forcing_file {
+        let id_parser = network::IdParser::new(&column_config.id_pattern)?;
+        if let Some(size) = args.forcing_window {
+            println!("Indexing forcing in {}...", forcing_file.display());
+            let window = ForcingWindow::open(forcing_file, args.forcing_layout, id_parser, size)?;
+            network::attach_forcing_window(&mut topology, &window, args.strict)?;
+            forcing_window = Some(Arc::new(window));
+        } else {
+            println!("Loading forcing from {}...", forcing_file.display());
+            let forcing = match args.forcing_layout {
+                ForcingLayout::Wide => io::csv::load_wide_forcing(forcing_file, &id_parser)?,
+                _ => io::csv::load_long_forcing(forcing_file, &id_parser)?,
+            };
+            network::attach_forcing(&mut topology, forcing, args.strict)?;
+        }
+    }
+    // A replay reads routed results from the cache, so it needs no forcing
+    if args.preload_forcing && !args.replay {
+        println!("Preloading forcing from {}...", csv_dir.display());
+        network::preload_forcing(&mut topology, num_cpus::get())?;
+    }
+    manifest.record("forcing", forcing_provenance(&csv_dir, &args)?);
+    if !args.forcing_members.is_empty() {
+        manifest.record(
+            "forcing_members",
+            serde_json::json!(
+                args.forcing_members
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>()
+            ),
+        );
+    }
+
+    // A headwater with no forcing usually means a misnamed forcing directory
+    let dry_reaches = network::find_dry_reaches(&topology);
+    if !dry_reaches.is_empty() {
+        let message = format!(
+            "{} of {} reaches have no upstream inflow and no forcing data in {} (first: {:?})",
+            dry_reaches.len(),
+            topology.nodes.len(),
+            csv_dir.display(),
+            &dry_reaches[..dry_reaches.len().min(10)]
+        );
+        if !args.allow_dry_reaches {
+            return Err(anyhow::anyhow!(
+                "{}; pass --allow-dry-reaches to route them anyway",
+                message
+            ));
+        }
+        console_warn!(args.strict, "{}", message)?;
+    }
 
     // Load channel parameters
     println!("Loading channel parameters...");
-    let channel_params_map = network::load_channel_parameters(&conn, &topology, &column_config)?;
+    let (mut channel_params_map, null_parameters) = match &args.attributes_csv {
+        Some(path) => (
+            network::load_channel_parameters_csv(path, &topology, &column_config)?,
+            Vec::new(),
+        ),
+        None => network::read_channel_parameters(
+            &conn,
+            &topology,
+            &column_config,
+            args.default_channel.as_ref(),
+            args.strict,
+        )?,
+    };
+    let mut default_channel_reaches = Vec::new();
+    if let Some(default_channel) = &args.default_channel {
+        for id in &topology.routing_order {
+            if !channel_params_map.contains_key(id) {
+                println!("Using --default-channel for reach {}", id);
+                channel_params_map.insert(*id, default_channel.clone());
+                default_channel_reaches.push(*id);
+            }
+        }
+    }
+    network::check_missing_parameters(&topology, &channel_params_map, args.strict)?;
+    let degenerate_geometry = network::check_channel_geometry(
+        &mut channel_params_map,
+        args.geometry_check,
+        args.tw_clamp_factor,
+    )?;
+
+    // Reaches whose inflow passes straight through without routing
+    let mut passthrough: HashSet<u64> = HashSet::new();
+    if let Some(min_length) = args.passthrough_below_length {
+        passthrough.extend(
+            channel_params_map
+                .iter()
+                .filter(|(_, params)| params.dx < min_length)
+                .map(|(id, _)| *id),
+        );
+    }
+    for id in &args.passthrough_ids {
+        if topology.nodes.contains_key(id) {
+            passthrough.insert(*id);
+        } else {
+            console_warn!(
+                args.strict,
+                "--passthrough-ids reach {} is not in the network",
+                id
+            )?;
+        }
+    }
+    // Reaches typed as anything but a channel aren't routed either
+    passthrough.extend(
+        topology
+            .nodes
+            .values()
+            .filter(|node| !node.reach_type.is_routed())
+            .map(|node| node.id),
+    );
+    let waterbodies: HashSet<u64> = topology
+        .nodes
+        .values()
+        .filter(|node| node.reach_type == ReachType::Waterbody)
+        .map(|node| node.id)
+        .collect();
+    network::check_reach_lengths(&channel_params_map, &passthrough)?;
+    network::check_timestep_resolution(&channel_params_map, &passthrough, dt);
+    if !passthrough.is_empty() {
+        println!(
+            "Passing inflow through unrouted on {} reaches",
+            passthrough.len()
+        );
+    }
 
-    // Set up CSV output if needed
-    let mut csv_writer = if matches!(output_format, OutputFormat::Csv | OutputFormat::Both) {
+    // Set up CSV output if needed; the writer thread owns and flushes it
+    let csv_output = matches!(output_format, OutputFormat::Csv | OutputFormat::Both);
+    let csv_writer = if csv_output {
         Some(io::csv::create_csv_writer("network_routing_results.csv")?)
     } else {
         None
     };
 
     // Get simulation parameters
-    let (max_external_steps, reference_time) =
-        get_simulation_params(&csv_dir, &channel_params_map)?;
+    let (max_external_steps, reference_time) = get_simulation_params(
+        &csv_dir,
+        &topology,
+        &channel_params_map,
+        &args,
+        forcing_window.as_deref(),
+    )?;
+    // Members share one output time axis, so they must cover the same period
+    for member_dir in args.forcing_members.iter().skip(1) {
+        let member =
+            get_simulation_params(member_dir, &topology, &channel_params_map, &args, None)?;
+        if member != (max_external_steps, reference_time) {
+            return Err(anyhow::anyhow!(
+                "Forcing member {} has {} steps from {}, but {} has {} from {}",
+                member_dir.display(),
+                member.0,
+                member.1,
+                csv_dir.display(),
+                max_external_steps,
+                reference_time
+            ));
+        }
+    }
 
-    let start_time = reference_time;
-    let end_time = start_time + Duration::seconds((3600 * max_external_steps) as i64);
+    let external_timestep_seconds = config::EXTERNAL_TIMESTEP_SECONDS;
+    let (total_timesteps, duration) = simulation_length(
+        max_external_steps + args.recession_steps,
+        external_timestep_seconds,
+        internal_timestep_seconds,
+    )?;
+
+    // Output samples every `output_stride`-th internal step, starting from the first
+    let output_timestep_seconds = args.output_timestep.unwrap_or(external_timestep_seconds);
+    let output_stride = output_timestep_seconds / internal_timestep_seconds;
 
-    let external_timestep_seconds = 3600;
-    let total_timesteps =
-        (max_external_steps + 1) * (external_timestep_seconds / internal_timestep_seconds);
+    let start_time = reference_time;
+    let end_time = start_time
+        .checked_add_signed(duration)
+        .ok_or_else(|| anyhow::anyhow!("Simulation end time is out of range"))?;
 
     println!("\nSimulation Configuration:");
     println!("  Period: {} to {}", start_time, end_time);
     println!("  Internal timestep: {} seconds", internal_timestep_seconds);
+    println!("  Output timestep: {} seconds", output_timestep_seconds);
     println!("  Network nodes: {}", topology.routing_order.len());
     println!("  Total timesteps: {}", total_timesteps);
+    if args.spinup_cycles > 0 {
+        println!(
+            "  Spin-up: {} cycles of the forcing before the output cycle",
+            args.spinup_cycles
+        );
+    }
+    if args.recession_steps > 0 {
+        println!(
+            "  Recession: {} steps past the end of the forcing",
+            args.recession_steps
+        );
+    }
 
     // Initialize NetCDF output
-    let timesteps: Vec<f64> = (0..=max_external_steps)
-        .map(|step| (step * 3600) as f64)
+    let timesteps: Vec<i64> = (0..total_timesteps.div_ceil(output_stride))
+        .map(|step| (step * output_timestep_seconds) as i64)
         .collect();
 
-    let nc_filename = format!("troute_output_{}.nc", reference_time.format("%Y%m%d%H%M"));
-    let netcdf_writer = init_netcdf_output(
-        &nc_filename,
-        topology.routing_order.len(),
-        timesteps,
-        &reference_time,
-    )?;
+    let aggregator = match &args.aggregate_map {
+        Some(path) => Some(GroupAggregator::new(
+            &load_aggregate_map(path)?,
+            &topology,
+            format!(
+                "troute_output_{}_aggregated.nc",
+                reference_time.format("%Y%m%d%H%M")
+            ),
+            timesteps.clone(),
+            reference_time,
+            args.time_format.clone(),
+            output_stride,
+        )),
+        None => None,
+    };
 
-    // Create progress bar
-    let pb = ProgressBar::new(topology.routing_order.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} nodes ({eta})")?
-            .progress_chars("#>-")
+    if (args.emit_iterations || args.emit_travel_time || args.emit_muskingum) && args.replay {
+        console_warn!(
+            args.strict,
+            "solver diagnostics aren't cached, so --emit-iterations, --emit-travel-time and --emit-muskingum are ignored with --replay"
+        )?;
+    }
+    if args.emit_cumulative_volume && args.replay {
+        console_warn!(
+            args.strict,
+            "volumes aren't cached, so --emit-cumulative-volume is ignored with --replay"
+        )?;
+    }
+    let solver = SolverConfig {
+        compound_channel: !args.no_compound,
+        kinematic_above_slope: args.kinematic_above_slope,
+        nonconvergence_fallback: args.nonconvergence_fallback,
+        bracket: args.secant_bracket,
+        bracket_upper: args.bracket_upper,
+        bracket_lower: args.bracket_lower,
+    };
+    // Recorded in the output and the manifest so a resumed run can be checked against it
+    let run_input_hash = input_hash(&gpkg_digest, &csv_dir, &args)?;
+    manifest.record(
+        "input_hash",
+        serde_json::json!(format!("{:016x}", run_input_hash)),
     );
+    let output_options = OutputOptions {
+        variables: args.variables.clone(),
+        specific_discharge: args.emit_specific_discharge,
+        drainage_area: args.emit_drainage_area,
+        iterations: args.emit_iterations && !args.replay,
+        travel_time: args.emit_travel_time && !args.replay,
+        muskingum: args.emit_muskingum && !args.replay,
+        cumulative_volume: args.emit_cumulative_volume && !args.replay,
+        floored_steps: args.min_flow.is_some() && !args.replay,
+        fill_value: args.fill_value,
+        nwm_compat: args.nwm_compat,
+        nudge: args.nudge.is_some(),
+        fill_nonconverged: args.nonconvergence_fallback == NonconvergenceFallback::Fill,
+        passthrough: passthrough.clone(),
+        waterbodies: waterbodies.clone(),
+        members: args
+            .forcing_members
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect(),
+        output_stride,
+        time_format: args.time_format.clone(),
+        solver: solver.clone(),
+        internal_timestep_seconds: args.internal_timestep_seconds,
+        input_hash: Some(format!("{:016x}", run_input_hash)),
+    };
+    let drainage_area = if output_options.specific_discharge || output_options.drainage_area {
+        network::cumulative_area(&topology, args.strict)?
+    } else {
+        HashMap::new()
+    };
 
-    // Run parallel routing
-    println!("\nStarting parallel wave-front routing...");
-    process_routing_parallel(
-        &topology,
-        &channel_params_map,
-        total_timesteps,
-        dt,
-        netcdf_writer,
-        Arc::new(pb),
-    )?;
+    let feature_index = match args.feature_order {
+        FeatureOrder::Id => sorted_feature_index(topology.routing_order.iter().copied()),
+        FeatureOrder::Topo => ordered_feature_index(&topology.stable_routing_order()),
+        FeatureOrder::Database => ordered_feature_index(&topology.source_order),
+    };
+    let (netcdf_writer, already_written) = match &args.resume_output {
+        Some(path) => {
+            if args.split_output_by != OutputSplit::None {
+                return Err(anyhow::anyhow!(
+                    "--resume-output only supports single-file output"
+                ));
+            }
+            resume_output(
+                path,
+                &timesteps,
+                &reference_time,
+                feature_index,
+                output_options,
+                drainage_area,
+            )?
+        }
+        None => (
+            init_chunked_output(
+                &timesteps,
+                &reference_time,
+                args.split_output_by,
+                feature_index,
+                output_options,
+                drainage_area,
+            )?,
+            HashSet::new(),
+        ),
+    };
+    // File names and lengths of the output chunks, plus the options needed to verify them
+    let (nc_chunks, verify_options): (Vec<(String, usize)>, OutputOptions) = {
+        let output = netcdf_writer
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire NetCDF file lock: {}", e))?;
+        check_time_dimension(&output, total_timesteps)?;
+        if args.split_output_by != OutputSplit::None {
+            write_chunk_index("troute_output_index.csv", &output.chunks)?;
+            println!("  Output chunks: {}", output.chunks.len());
+        }
+        let chunks = output
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.filename.clone(), chunk.num_steps))
+            .collect();
+        (chunks, output.options.clone())
+    };
+
+    // Create progress bar; JSON mode and --quiet track progress on a hidden bar
+    let total_nodes = topology.routing_order.len() as u64;
+    let pb = if args.quiet || args.progress == ProgressMode::Json {
+        ProgressBar::with_draw_target(Some(total_nodes), ProgressDrawTarget::hidden())
+    } else {
+        let pb = ProgressBar::new(total_nodes);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} nodes ({eta})")?
+                .progress_chars("#>-")
+        );
+        pb
+    };
+    let pb = Arc::new(pb);
+    let json_progress = (!args.quiet && args.progress == ProgressMode::Json)
+        .then(|| spawn_json_progress(Arc::clone(&pb), std::time::Duration::from_secs(5)));
+    if args.report_memory && !memory::AVAILABLE {
+        console_warn!(
+            args.strict,
+            "--report-memory needs route_rs built with --features memory-stats; memory will not be reported"
+        )?;
+    }
+    let memory_report = (args.report_memory && memory::AVAILABLE)
+        .then(|| spawn_memory_report(Arc::clone(&pb), std::time::Duration::from_secs(10)));
+
+    let mut routing_options = RoutingOptions {
+        solver,
+        ..RoutingOptions::default()
+    };
+    routing_options.passthrough = passthrough;
+    routing_options.check_nonnegative = args.check_nonnegative;
+    routing_options.min_flow = args.min_flow;
+    routing_options.abort_above_flow = args.abort_above_flow;
+    routing_options.confluence = args.confluence;
+    let velocity_check = args.flag_velocity_above.map(VelocityCheck::new);
+    routing_options.velocity_check = velocity_check.clone();
+    let peak_flows = args
+        .peak_flow_geojson
+        .as_ref()
+        .map(|_| PeakFlows::default());
+    routing_options.peak_flows = peak_flows.clone();
+    routing_options.skip_first_forcing = args.skip_first_forcing;
+    routing_options.spinup_cycles = args.spinup_cycles;
+    routing_options.recession_steps = args.recession_steps;
+    routing_options.forcing_window = forcing_window;
+    routing_options.by_component = args.by_component;
+    routing_options.write_retries = args.write_retries;
+    routing_options.record_muskingum = args.emit_muskingum;
+    routing_options.record_cumulative_volume = args.emit_cumulative_volume;
+    routing_options.max_reach_iterations = args.max_reach_iterations;
+    routing_options.strict = args.strict;
+    if args.resume_output.is_some() {
+        routing_options.skip_routing = resume_skip_set(&topology, &already_written);
+        println!(
+            "Resuming: {} reaches already written, {} need routing",
+            already_written.len(),
+            topology.routing_order.len() - routing_options.skip_routing.len()
+        );
+        routing_options.already_written = already_written;
+    }
+    // Cached results keep every variable so a replay can select a different set
+    if args.results_cache.is_none() {
+        routing_options.discarded_variables = OutputVariable::ALL
+            .into_iter()
+            .filter(|variable| !args.variables.contains(variable))
+            .collect();
+    }
+    if let Some(path) = &args.roughness_file {
+        let id_parser = network::IdParser::new(&column_config.id_pattern)?;
+        routing_options.roughness = Some(Roughness::load(path, &id_parser)?);
+    }
+    if let Some(path) = &args.nudge {
+        routing_options.nudging = Some(Nudging::new(load_observations(path)?, args.nudge_decay));
+    }
+
+    // Reaches the run treated specially rather than routing as given
+    let mut floored_slopes: Vec<u64> = channel_params_map
+        .iter()
+        .filter(|(id, params)| {
+            params.s0 == 0.0
+                && !routing_options.passthrough.contains(id)
+                && !routing_options.skip_routing.contains(id)
+        })
+        .map(|(id, _)| *id)
+        .collect();
+    floored_slopes.sort_unstable();
+    let mut passthrough_reaches: Vec<u64> = routing_options.passthrough.iter().copied().collect();
+    passthrough_reaches.sort_unstable();
+    let mut waterbody_reaches: Vec<u64> = waterbodies.into_iter().collect();
+    waterbody_reaches.sort_unstable();
+    let mut resumed_reaches: Vec<u64> = routing_options.skip_routing.iter().copied().collect();
+    resumed_reaches.sort_unstable();
+    manifest.record("nodes", serde_json::json!(topology.routing_order.len()));
+    manifest.record(
+        "policy",
+        serde_json::json!({
+            "floored_zero_slopes": floored_slopes,
+            "passthrough": passthrough_reaches,
+            "waterbodies": waterbody_reaches,
+            "default_channel": default_channel_reaches,
+            "null_parameters": null_parameters,
+            "bottom_width_at_least_top_width": degenerate_geometry,
+            "geometry_check": format!("{:?}", args.geometry_check),
+            "dry_reaches": dry_reaches,
+            "skipped_already_written": resumed_reaches,
+            "replayed_from_cache": args.replay,
+        }),
+    );
+
+    let statistics = args.stats_file.clone().map(OutputStatistics::new);
+
+    // From here an interrupt stops routing and leaves the outputs flushed and readable
+    route_rs::interrupt::install()?;
+    if args.replay {
+        let cache_dir = args
+            .results_cache
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("--replay requires --results-cache"))?;
+        let cache = ResultsCache::open(cache_dir, run_input_hash)?;
+
+        println!("\nReplaying cached results from {}...", cache_dir.display());
+        replay_results(
+            &topology,
+            &cache,
+            netcdf_writer,
+            csv_writer,
+            aggregator,
+            statistics,
+            args.write_retries,
+            args.strict,
+            Arc::clone(&pb),
+        )?;
+    } else {
+        if let Some(cache_dir) = &args.results_cache {
+            routing_options.results_cache = Some(ResultsCache::create(cache_dir, run_input_hash)?);
+        }
+
+        if args.forcing_members.is_empty() {
+            // Run parallel routing
+            println!("\nStarting parallel wave-front routing...");
+            process_routing_parallel(
+                &topology,
+                &channel_params_map,
+                total_timesteps,
+                dt,
+                routing_options,
+                netcdf_writer,
+                csv_writer,
+                aggregator,
+                statistics,
+                args.timings_file.clone().map(TimingReport::new),
+                Arc::clone(&pb),
+            )?;
+        } else {
+            // One routing pass per member over the same topology and parameters
+            let members = args.forcing_members.len();
+            for (member, member_dir) in args.forcing_members.iter().enumerate() {
+                if member > 0 {
+                    network::use_forcing_dir(&mut topology, member_dir)?;
+                    pb.reset();
+                }
+                netcdf_writer
+                    .lock()
+                    .map_err(|e| anyhow::anyhow!("Failed to acquire NetCDF file lock: {}", e))?
+                    .member = member;
+                println!(
+                    "\nRouting forcing member {} of {} ({})...",
+                    member + 1,
+                    members,
+                    member_dir.display()
+                );
+                process_routing_parallel(
+                    &topology,
+                    &channel_params_map,
+                    total_timesteps,
+                    dt,
+                    routing_options.clone(),
+                    Arc::clone(&netcdf_writer),
+                    None,
+                    None,
+                    None,
+                    None,
+                    Arc::clone(&pb),
+                )?;
+            }
+        }
+        if let Some(check) = &velocity_check {
+            check.finish(&args.velocity_report, args.float_precision)?;
+        }
+        #[cfg(feature = "geometry")]
+        if let (Some(path), Some(peaks)) = (&args.peak_flow_geojson, &peak_flows) {
+            io::geojson::write_peak_flow_geojson(&conn, &column_config, &peaks.peaks()?, path)?;
+        }
+    }
+
+    if let Some(handle) = json_progress {
+        pb.finish();
+        let _ = handle.join();
+    }
+    if let Some(handle) = memory_report {
+        pb.finish();
+        let _ = handle.join();
+    }
 
-    // Final flush for CSV
-    if let Some(mut wtr) = csv_writer {
-        wtr.flush().context("Failed to flush CSV writer")?;
+    if csv_output {
         println!("CSV results saved to network_routing_results.csv");
     }
 
     println!(
         "\nNetwork routing complete. Output saved to {}",
-        nc_filename
+        nc_chunks
+            .iter()
+            .map(|(filename, _)| filename.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    if args.verify_output {
+        let mut failed = 0;
+        for (filename, num_steps) in &nc_chunks {
+            let problems = verify_output(
+                Path::new(filename),
+                topology.routing_order.len(),
+                *num_steps,
+                &verify_options,
+            );
+            if problems.is_empty() {
+                println!("Verified {}: OK", filename);
+            } else {
+                failed += 1;
+                for problem in problems {
+                    eprintln!("Verification of {} failed: {}", filename, problem);
+                }
+            }
+        }
+        if failed > 0 {
+            return Err(anyhow::anyhow!(
+                "{} of {} output files failed verification",
+                failed,
+                nc_chunks.len()
+            ));
+        }
+    }
+
+    manifest.record(
+        "outputs",
+        serde_json::json!(
+            nc_chunks
+                .iter()
+                .map(|(filename, _)| filename.as_str())
+                .collect::<Vec<_>>()
+        ),
+    );
+    manifest.finish()
+}
+
+// Every option's value as routing saw it, defaults included; unset options are null
+fn effective_options(matches: &ArgMatches) -> serde_json::Value {
+    let options: serde_json::Map<String, serde_json::Value> = Args::command()
+        .get_arguments()
+        .map(|arg| arg.get_id().as_str())
+        .filter(|id| !matches!(*id, "help" | "version"))
+        .map(|id| {
+            let values: Option<Vec<String>> = matches.get_raw(id).map(|values| {
+                values
+                    .map(|value| value.to_string_lossy().into_owned())
+                    .collect()
+            });
+            let value = match values {
+                Some(values) if values.len() == 1 => serde_json::json!(values[0]),
+                Some(values) => serde_json::json!(values),
+                None => serde_json::Value::Null,
+            };
+            (id.to_string(), value)
+        })
+        .collect();
+    serde_json::Value::Object(options)
+}
+
+// Where forcing came from and how many files it spans
+fn forcing_provenance(csv_dir: &Path, args: &cli::Args) -> Result<serde_json::Value> {
+    Ok(match &args.forcing_file {
+        Some(forcing_file) => serde_json::json!({
+            "path": forcing_file.display().to_string(),
+            "files": 1,
+        }),
+        None => {
+            let files = std::fs::read_dir(csv_dir)
+                .with_context(|| {
+                    format!("Failed to read forcing directory: {}", csv_dir.display())
+                })?
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_file())
+                .count();
+            serde_json::json!({
+                "path": csv_dir.display().to_string(),
+                "files": files,
+            })
+        }
+    })
+}
+
+fn run_command(command: &Command) -> Result<()> {
+    match command {
+        Command::Schema { gpkg, id_pattern } => print_schema(
+            gpkg.as_deref(),
+            &command_column_config(id_pattern.as_deref()),
+        ),
+        Command::Topology {
+            gpkg,
+            list_outlets,
+            id_pattern,
+        } => print_topology(
+            gpkg,
+            *list_outlets,
+            &command_column_config(id_pattern.as_deref()),
+        ),
+        Command::Validate {
+            gpkg,
+            output_dir,
+            forcing_dir,
+        } => validate_ids(gpkg, output_dir, forcing_dir.as_deref()),
+        Command::Compare {
+            a,
+            b,
+            abs_tolerance,
+            rel_tolerance,
+            top,
+        } => compare_outputs(
+            a,
+            b,
+            io::compare::Tolerance {
+                absolute: *abs_tolerance,
+                relative: *rel_tolerance,
+            },
+            *top,
+        ),
+    }
+}
+
+// Summarise how two output files differ, per variable and for the most divergent
+// features, and fail when any value is beyond the tolerance
+fn compare_outputs(
+    a: &Path,
+    b: &Path,
+    tolerance: io::compare::Tolerance,
+    top: usize,
+) -> Result<()> {
+    let comparison = io::compare::compare_outputs(a, b, tolerance, top)?;
+
+    println!(
+        "{:<24} {:>12} {:>12} {:>12} {:>12} {:>12} {:>10}",
+        "variable", "compared", "max abs", "mean abs", "max rel", "mean rel", "exceeding"
+    );
+    for diff in &comparison.variables {
+        println!(
+            "{:<24} {:>12} {:>12.4e} {:>12.4e} {:>12.4e} {:>12.4e} {:>10}",
+            diff.name,
+            diff.compared,
+            diff.max_abs,
+            diff.mean_abs,
+            diff.max_rel,
+            diff.mean_rel,
+            diff.exceeding
+        );
+        if diff.fill_mismatches > 0 {
+            println!(
+                "  {} values are fill in one file only",
+                diff.fill_mismatches
+            );
+        }
+    }
+    if !comparison.skipped.is_empty() {
+        println!(
+            "Not compared (in one file only, or not per feature): {}",
+            comparison.skipped.join(", ")
+        );
+    }
+
+    if !comparison.worst_features.is_empty() {
+        println!("\nMost divergent features:");
+        for feature in &comparison.worst_features {
+            println!(
+                "  {:>12}  {:<24} max abs {:.4e}, max rel {:.4e}",
+                feature.feature_id, feature.variable, feature.max_abs, feature.max_rel
+            );
+        }
+    }
+    for (ids, file) in [(&comparison.only_in_a, a), (&comparison.only_in_b, b)] {
+        if !ids.is_empty() {
+            println!(
+                "{} features only in {} (first: {:?})",
+                ids.len(),
+                file.display(),
+                &ids[..ids.len().min(10)]
+            );
+        }
+    }
+
+    if !comparison.passed() {
+        return Err(anyhow::anyhow!(
+            "{} and {} differ beyond the tolerance (absolute {}, relative {})",
+            a.display(),
+            b.display(),
+            tolerance.absolute,
+            tolerance.relative
+        ));
+    }
+    println!("Outputs match within the tolerance");
+    Ok(())
+}
+
+// Report reaches without parameters and parameters without reaches, writing each list
+// to a CSV so the hydrofabric can be fixed before a run fails on it
+fn validate_ids(gpkg: &Path, output_dir: &Path, forcing_dir: Option<&Path>) -> Result<()> {
+    let column_config = ColumnConfig::new();
+    let conn =
+        rusqlite::Connection::open_with_flags(gpkg, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database: {:?}", gpkg))?;
+    let (missing_attributes, missing_flowpaths) = network::cross_check_ids(&conn, &column_config)?;
+
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("Failed to create {}", output_dir.display()))?;
+    write_id_list(
+        output_dir,
+        "flowpaths_without_attributes.csv",
+        &column_config.key,
+        missing_attributes.iter().map(String::as_str),
+        "routed flowpaths have no row in flowpath-attributes",
+    )?;
+    write_id_list(
+        output_dir,
+        "attributes_without_flowpaths.csv",
+        &column_config.key,
+        missing_flowpaths.iter().map(String::as_str),
+        "flowpath-attributes rows match no routed flowpath",
+    )?;
+
+    if let Some(forcing_dir) = forcing_dir {
+        report_forcing_coverage(&conn, &column_config, forcing_dir, output_dir)?;
+    }
+
+    // Parameters without a reach are unused; reaches without parameters stop a run
+    if !missing_attributes.is_empty() {
+        return Err(anyhow::anyhow!(
+            "{} reaches in {} have no channel parameters (first: {:?})",
+            missing_attributes.len(),
+            gpkg.display(),
+            &missing_attributes[..missing_attributes.len().min(10)]
+        ));
+    }
+    Ok(())
+}
+
+// Report how many routed reaches have a forcing file, writing the reaches without one and
+// the files without a reach to CSVs. Gaps are reported rather than failed on, since a
+// network may be forced in part on purpose.
+fn report_forcing_coverage(
+    conn: &rusqlite::Connection,
+    column_config: &ColumnConfig,
+    forcing_dir: &Path,
+    output_dir: &Path,
+) -> Result<()> {
+    let topology = build_network_topology(conn, column_config, forcing_dir, false)?;
+    let coverage = network::forcing_coverage(&topology, forcing_dir)?;
+    println!(
+        "{} of {} reaches ({:.1}%) have a forcing file in {}",
+        coverage.covered,
+        topology.routing_order.len(),
+        coverage.percent(),
+        forcing_dir.display()
     );
+
+    write_id_list(
+        output_dir,
+        "reaches_without_forcing.csv",
+        &column_config.key,
+        coverage
+            .missing
+            .iter()
+            .map(|id| topology.raw_ids[id].as_str()),
+        "routed reaches have no forcing file",
+    )?;
+    write_id_list(
+        output_dir,
+        "forcing_without_reaches.csv",
+        "file",
+        coverage.unmatched.iter().map(String::as_str),
+        "forcing files match no routed reach",
+    )
+}
+
+// Write a one-column CSV of ids to the output directory and report how many it holds
+fn write_id_list<'a>(
+    output_dir: &Path,
+    name: &str,
+    header: &str,
+    ids: impl Iterator<Item = &'a str>,
+    description: &str,
+) -> Result<()> {
+    let path = output_dir.join(name);
+    let mut content = format!("{}\n", header);
+    let mut count = 0;
+    for id in ids {
+        content.push_str(id);
+        content.push('\n');
+        count += 1;
+    }
+    std::fs::write(&path, content)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    println!("{} {} ({})", count, description, path.display());
+    Ok(())
+}
+
+// Default columns, with the subcommand's --id-pattern when given
+fn command_column_config(id_pattern: Option<&str>) -> ColumnConfig {
+    let mut column_config = ColumnConfig::new();
+    if let Some(id_pattern) = id_pattern {
+        column_config.id_pattern = id_pattern.to_string();
+    }
+    column_config
+}
+
+// Summarise the network in a gpkg, optionally listing each outlet's basin, largest first
+fn print_topology(gpkg: &Path, list_outlets: bool, column_config: &ColumnConfig) -> Result<()> {
+    let conn =
+        rusqlite::Connection::open_with_flags(gpkg, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database: {:?}", gpkg))?;
+    let topology = build_network_topology(&conn, column_config, Path::new(""), false)?;
+    let headwaters = topology
+        .nodes
+        .values()
+        .filter(|node| node.upstream_ids.is_empty())
+        .count();
+    println!("{} headwater reaches", headwaters);
+
+    if !list_outlets {
+        return Ok(());
+    }
+    let drainage_area = network::cumulative_area(&topology, false)?;
+    println!("\nfeature_id,id,reaches,drainage_area_km2");
+    for basin in topology.components() {
+        let Some(&outlet) = basin.last() else {
+            continue;
+        };
+        println!(
+            "{},{},{},{}",
+            outlet,
+            topology.raw_ids.get(&outlet).map_or("", String::as_str),
+            basin.len(),
+            drainage_area.get(&outlet).copied().unwrap_or_default()
+        );
+    }
     Ok(())
 }
 
+// List the tables and columns the routing queries use, optionally checking a gpkg for them
+fn print_schema(gpkg: Option<&Path>, column_config: &ColumnConfig) -> Result<()> {
+    println!("route_rs reads these gpkg tables and columns:");
+    for (table, columns) in column_config.expected_columns() {
+        println!("  {}: {}", table, columns.join(", "));
+    }
+    println!(
+        "Ids in the id and toid columns are parsed with {} (see --id-pattern)",
+        column_config.id_pattern
+    );
+
+    let Some(gpkg) = gpkg else {
+        return Ok(());
+    };
+    let conn =
+        rusqlite::Connection::open_with_flags(gpkg, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database: {:?}", gpkg))?;
+    let problems = network::check_schema(&conn, column_config)?;
+    if problems.is_empty() {
+        println!("\n{} has every expected table and column", gpkg.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        eprintln!("{}: {}", gpkg.display(), problem);
+    }
+    Err(anyhow::anyhow!(
+        "{} schema problems found in {}",
+        problems.len(),
+        gpkg.display()
+    ))
+}
+
+// Number of internal timesteps and the simulated period, checked so extreme
+// run lengths or tiny internal steps produce an error rather than overflowing
+fn simulation_length(
+    max_external_steps: usize,
+    external_timestep_seconds: usize,
+    internal_timestep_seconds: usize,
+) -> Result<(usize, Duration)> {
+    let too_long = || {
+        anyhow::anyhow!(
+            "Simulation of {} forcing steps at {} s internal timesteps is too long",
+            max_external_steps.saturating_add(1),
+            internal_timestep_seconds
+        )
+    };
+
+    let total_timesteps = max_external_steps
+        .checked_add(1)
+        .and_then(|steps| steps.checked_mul(external_timestep_seconds / internal_timestep_seconds))
+        .ok_or_else(too_long)?;
+
+    // Each routed series is a Vec<f32> of total_timesteps, which can't exceed isize::MAX bytes
+    if total_timesteps
+        .checked_mul(std::mem::size_of::<f32>())
+        .is_none_or(|bytes| bytes > isize::MAX as usize)
+    {
+        return Err(too_long());
+    }
+
+    let duration = i64::try_from(max_external_steps)
+        .ok()
+        .and_then(|steps| steps.checked_mul(external_timestep_seconds as i64))
+        .and_then(Duration::try_seconds)
+        .ok_or_else(too_long)?;
+
+    Ok((total_timesteps, duration))
+}
+
 fn get_simulation_params(
-    csv_dir: &std::path::PathBuf,
-    features: &HashMap<u32, ChannelParams>,
+    csv_dir: &std::path::Path,
+    topology: &NetworkTopology,
+    features: &HashMap<u64, ChannelParams>,
+    args: &cli::Args,
+    forcing_window: Option<&ForcingWindow>,
 ) -> Result<(usize, NaiveDateTime)> {
-    let first_id = features
-        .keys()
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("No features found"))?;
+    // One external step per forcing flux, less the initial state row when it's skipped
+    let skipped = args.skip_first_forcing as usize;
 
-    let file_name = csv_dir.join(format!("cat-{}.csv", first_id));
-    let content = std::fs::read_to_string(&file_name)
-        .with_context(|| format!("Failed to read file: {:?}", file_name))?;
+    if features.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No reach has channel parameters, so there is nothing to route"
+        ));
+    }
+
+    // Forcing from a single file, already loaded or indexed
+    if let Some(forcing_file) = &args.forcing_file {
+        let reference_time = forcing_start_time(
+            forcing_file,
+            skipped,
+            &args.time_format,
+            args.time_epsilon,
+            args.strict,
+        )?;
+        let forcing_steps = match forcing_window {
+            Some(window) => window.num_steps(),
+            None => topology
+                .nodes
+                .values()
+                .filter_map(|node| node.lateral_inflow.as_ref().map(Vec::len))
+                .max()
+                .unwrap_or(0),
+        };
+        return Ok((forcing_steps.saturating_sub(1 + skipped), reference_time));
+    }
+
+    let (first_id, rows) = per_file_forcing_rows(csv_dir, features)?;
+    let forcing_file = csv_dir.join(format!("cat-{}.csv", first_id));
+    if rows <= 1 + skipped {
+        return Err(anyhow::anyhow!(
+            "{} has {} data rows, which leaves no forcing steps to route",
+            forcing_file.display(),
+            rows
+        ));
+    }
+    let reference_time = forcing_start_time(
+        &forcing_file,
+        skipped,
+        &args.time_format,
+        args.time_epsilon,
+        args.strict,
+    )?;
+    Ok((rows - 1 - skipped, reference_time))
+}
+
+// The lowest catchment id with a non-empty forcing file and that file's row count, after
+// checking every other file has the same number of rows. Missing and empty files are
+// routed as dry, but a file of a different length would run out of forcing, or leave
+// some unused, partway through the run.
+fn per_file_forcing_rows(
+    csv_dir: &Path,
+    features: &HashMap<u64, ChannelParams>,
+) -> Result<(u64, usize)> {
+    let mut ids: Vec<u64> = features.keys().copied().collect();
+    ids.sort_unstable();
+
+    let mut reference = None;
+    let mut mismatched = Vec::new();
+    for id in ids {
+        let file_name = csv_dir.join(format!("cat-{}.csv", id));
+        let rows = match io::csv::count_forcing_rows(&file_name)? {
+            Some(rows) if rows > 0 => rows,
+            _ => continue,
+        };
+        match reference {
+            None => reference = Some((id, rows)),
+            Some((_, expected)) if rows != expected => mismatched.push((id, rows)),
+            Some(_) => {}
+        }
+    }
+
+    let (first_id, rows) = reference.ok_or_else(|| {
+        anyhow::anyhow!("No forcing files with data found in {}", csv_dir.display())
+    })?;
+    if !mismatched.is_empty() {
+        let listed: Vec<String> = mismatched
+            .iter()
+            .take(10)
+            .map(|(id, rows)| format!("cat-{}.csv ({} rows)", id, rows))
+            .collect();
+        return Err(anyhow::anyhow!(
+            "Forcing files in {} differ in length: cat-{}.csv has {} rows, but {} others don't: {}",
+            csv_dir.display(),
+            first_id,
+            rows,
+            mismatched.len(),
+            listed.join(", ")
+        ));
+    }
+    Ok((first_id, rows))
+}
+
+// Time of the first routed forcing row. Times that can't be auto-detected fall back to
+// 2000-01-01, but an explicit --time-format must match.
+fn forcing_start_time(
+    forcing_file: &Path,
+    skipped: usize,
+    time_format: &TimeFormat,
+    time_epsilon: f64,
+    strict: bool,
+) -> Result<NaiveDateTime> {
+    let time = io::csv::forcing_time(forcing_file, skipped)?;
+    let problem = match time.as_deref().map(|time| time_format.parse(time)) {
+        Some(Ok(time)) => {
+            return Ok(align_to_grid(
+                time,
+                config::EXTERNAL_TIMESTEP_SECONDS as i64,
+                time_epsilon,
+            ));
+        }
+        Some(Err(e)) => e,
+        None => "it has no rows to route".to_string(),
+    };
+    if let TimeFormat::Pattern(_) = time_format {
+        return Err(anyhow::anyhow!(
+            "Failed to read the start time from {}: {}",
+            forcing_file.display(),
+            problem
+        ));
+    }
+
+    console_warn!(
+        strict,
+        "no start time in {} ({}); using 2000-01-01 00:00:00",
+        forcing_file.display(),
+        problem
+    )?;
+    NaiveDateTime::parse_from_str("2000-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+        .context("Failed to parse reference time")
+}
+
+// Snap a time onto the nearest multiple of `grid_seconds` since the epoch when it's
+// within `epsilon` seconds of it, dropping any fraction of a second. Times further off
+// the grid are kept as they are.
+fn align_to_grid(time: NaiveDateTime, grid_seconds: i64, epsilon: f64) -> NaiveDateTime {
+    let utc = time.and_utc();
+    let below = utc.timestamp() - utc.timestamp().rem_euclid(grid_seconds);
+    let offset = (utc.timestamp() - below) as f64 + utc.timestamp_subsec_nanos() as f64 * 1e-9;
+    let (nearest, distance) = if offset * 2.0 < grid_seconds as f64 {
+        (below, offset)
+    } else {
+        (below + grid_seconds, grid_seconds as f64 - offset)
+    };
+    if distance > epsilon {
+        return time;
+    }
+    chrono::DateTime::from_timestamp(nearest, 0).map_or(time, |aligned| aligned.naive_utc())
+}
 
-    let max_external_steps = content.lines().count().saturating_sub(2);
+// Hash everything that affects routed values (not output formatting) to validate the results cache
+fn input_hash(gpkg_digest: &str, csv_dir: &Path, args: &cli::Args) -> Result<u64> {
+    let mut hasher = DefaultHasher::new();
 
-    let reference_time = NaiveDateTime::parse_from_str("2000-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
-        .context("Failed to parse reference time")?;
+    // Files are streamed through their digest rather than read into memory whole
+    let hash_file = |hasher: &mut DefaultHasher, path: &Path| -> Result<()> {
+        file_digest(path)?.hash(hasher);
+        Ok(())
+    };
+    gpkg_digest.hash(&mut hasher);
+    if let Some(attributes_csv) = &args.attributes_csv {
+        hash_file(&mut hasher, attributes_csv)?;
+    }
+    if let Some(roughness_file) = &args.roughness_file {
+        hash_file(&mut hasher, roughness_file)?;
+    }
+    if let Some(lateral_weights) = &args.lateral_weights {
+        hash_file(&mut hasher, lateral_weights)?;
+    }
+    if let Some(reach_types) = &args.reach_types {
+        hash_file(&mut hasher, reach_types)?;
+    }
+    args.reach_type_column.hash(&mut hasher);
+    if let Some(nudge) = &args.nudge {
+        hash_file(&mut hasher, nudge)?;
+        args.nudge_decay.to_bits().hash(&mut hasher);
+    }
+
+    // Forcing files are identified by name, size, and modification time rather than read in full
+    let mut forcing: Vec<_> = match &args.forcing_file {
+        Some(forcing_file) => {
+            let metadata = std::fs::metadata(forcing_file)
+                .with_context(|| format!("Failed to read {}", forcing_file.display()))?;
+            vec![(
+                forcing_file.as_os_str().to_owned(),
+                metadata.len(),
+                metadata.modified().ok(),
+            )]
+        }
+        None => std::fs::read_dir(csv_dir)
+            .with_context(|| format!("Failed to read forcing directory: {}", csv_dir.display()))?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((entry.file_name(), metadata.len(), metadata.modified().ok()))
+            })
+            .collect(),
+    };
+    forcing.sort();
+    forcing.hash(&mut hasher);
+    args.forcing_layout.hash(&mut hasher);
+
+    args.internal_timestep_seconds.hash(&mut hasher);
+    args.id_pattern.hash(&mut hasher);
+    args.no_compound.hash(&mut hasher);
+    args.kinematic_above_slope
+        .map(f32::to_bits)
+        .hash(&mut hasher);
+    args.nonconvergence_fallback.hash(&mut hasher);
+    args.secant_bracket.hash(&mut hasher);
+    args.bracket_upper.to_bits().hash(&mut hasher);
+    args.bracket_lower.to_bits().hash(&mut hasher);
+    args.max_reach_iterations.hash(&mut hasher);
+    format!("{:?}", args.default_channel).hash(&mut hasher);
+    args.passthrough_below_length
+        .map(f32::to_bits)
+        .hash(&mut hasher);
+    args.passthrough_ids.hash(&mut hasher);
+    args.min_flow.map(f32::to_bits).hash(&mut hasher);
+    args.confluence.hash(&mut hasher);
+    args.skip_first_forcing.hash(&mut hasher);
+    args.time_epsilon.to_bits().hash(&mut hasher);
+    args.spinup_cycles.hash(&mut hasher);
+    args.recession_steps.hash(&mut hasher);
+    args.geometry_check.hash(&mut hasher);
+    args.tw_clamp_factor.to_bits().hash(&mut hasher);
+
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok((max_external_steps, reference_time))
+    #[test]
+    fn simulation_length_rejects_overflow() {
+        let (total, duration) = simulation_length(23, 3600, 300).unwrap();
+        assert_eq!(total, 24 * 12);
+        assert_eq!(duration, Duration::hours(23));
+
+        assert!(simulation_length(usize::MAX, 3600, 300).is_err());
+        assert!(simulation_length(usize::MAX / 2, 3600, 1).is_err());
+    }
+
+    #[test]
+    fn forcing_times_within_epsilon_snap_to_the_grid() {
+        let time = |value: &str| TimeFormat::Auto.parse(value).unwrap();
+
+        assert_eq!(
+            align_to_grid(time("2020-01-01 00:59:59.999"), 3600, 1.0),
+            time("2020-01-01 01:00:00")
+        );
+        assert_eq!(
+            align_to_grid(time("2020-01-01 01:00:00.4"), 3600, 1.0),
+            time("2020-01-01 01:00:00")
+        );
+        // A half-hour offset is a real start time, not drift
+        assert_eq!(
+            align_to_grid(time("2020-01-01 00:30:00"), 3600, 1.0),
+            time("2020-01-01 00:30:00")
+        );
+        assert_eq!(
+            align_to_grid(time("2020-01-01 00:59:58"), 3600, 1.0),
+            time("2020-01-01 00:59:58")
+        );
+    }
+
+    #[test]
+    fn effective_options_include_defaults_and_unset_options() {
+        let matches = Args::command()
+            .try_get_matches_from(["route_rs", "data", "--passthrough-ids", "3,4"])
+            .unwrap();
+        let options = effective_options(&matches);
+
+        assert_eq!(options["route_dir"], "data");
+        assert_eq!(options["internal_timestep_seconds"], "3600");
+        assert_eq!(options["strict"], "false");
+        assert_eq!(options["passthrough_ids"], serde_json::json!(["3", "4"]));
+        assert!(options["stats_file"].is_null());
+        assert!(options.get("help").is_none());
+    }
+
+    #[test]
+    fn per_file_forcing_must_agree_on_length() {
+        let dir = std::env::temp_dir().join("route_rs_forcing_lengths");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |id: u64, rows: usize| {
+            let mut contents = String::from("Time,ID,Q_OUT\n");
+            for step in 0..rows {
+                contents.push_str(&format!("{},cat-{},1.0\n", step, id));
+            }
+            std::fs::write(dir.join(format!("cat-{}.csv", id)), contents).unwrap();
+        };
+        let params = ChannelParams {
+            dx: 1000.0,
+            n: 0.06,
+            ncc: 0.12,
+            s0: 0.001,
+            bw: 5.0,
+            tw: 10.0,
+            twcc: 30.0,
+            cs: 0.5,
+        };
+        let features: HashMap<u64, ChannelParams> =
+            (1..=4).map(|id| (id, params.clone())).collect();
+
+        // 1 has no file and 2 only a header, so 3 sets the length
+        write(2, 0);
+        write(3, 5);
+        write(4, 5);
+        assert_eq!(per_file_forcing_rows(&dir, &features).unwrap(), (3, 5));
+
+        write(4, 4);
+        let error = per_file_forcing_rows(&dir, &features)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("cat-4.csv (4 rows)"), "{}", error);
+    }
 }
@@ -1,5 +1,98 @@
+use crate::config::{NonconvergenceFallback, SecantBracket, SolverConfig};
+use crate::console_eprintln;
+use crate::error::RouteError;
+use crate::kw_kernel::{hydraulic_radius, normal_depth};
+
+// The secant depth solve stops once the relative change in depth is below
+// RELATIVE_TOLERANCE or the absolute change is below MIN_DEPTH (m). After MAX_ITERATIONS
+// the bracket is widened and the solve retried, up to 4 times with 25 more iterations each.
+pub const RELATIVE_TOLERANCE: f32 = 0.01;
+pub const MIN_DEPTH: f32 = 0.01;
+pub const MAX_ITERATIONS: i32 = 100;
+
+// Result of routing one reach over one timestep
+#[derive(Debug, Clone, Copy)]
+pub struct KernelOutput {
+    pub qdc: f32,    // flow downstream current timestep
+    pub velc: f32,   // velocity
+    pub depthc: f32, // depth
+    pub ck: f32,     // kinematic celerity
+    pub cn: f32,     // Courant number
+    pub x: f32,      // Muskingum weighting
+    // False when the depth solve exhausted its retries
+    pub converged: bool,
+    // Secant iterations across all retries
+    pub iterations: u32,
+    // Travel time dx/ck through the reach (s), before K is held to at least dt for the
+    // routing coefficients; 0 when there's no flow
+    pub travel_time: f32,
+}
+
+/// A scheme for routing one reach over one timestep. Every kernel takes the same
+/// arguments as `submuskingcunge` so they can be swapped per reach.
+pub trait RoutingKernel: Sync {
+    #[allow(clippy::too_many_arguments)]
+    fn route(
+        &self,
+        qup: f32,
+        quc: f32,
+        qdp: f32,
+        ql: f32,
+        dt: f32,
+        so: f32,
+        dx: f32,
+        n: f32,
+        cs: f32,
+        bw: f32,
+        tw: f32,
+        tw_cc: f32,
+        n_cc: f32,
+        depth_p: f32,
+        solver: &SolverConfig,
+    ) -> Result<KernelOutput, RouteError>;
+}
+
+/// The NWM Muskingum-Cunge kernel, `submuskingcunge`
+pub struct MuskingumCunge;
+
+impl RoutingKernel for MuskingumCunge {
+    fn route(
+        &self,
+        qup: f32,
+        quc: f32,
+        qdp: f32,
+        ql: f32,
+        dt: f32,
+        so: f32,
+        dx: f32,
+        n: f32,
+        cs: f32,
+        bw: f32,
+        tw: f32,
+        tw_cc: f32,
+        n_cc: f32,
+        depth_p: f32,
+        solver: &SolverConfig,
+    ) -> Result<KernelOutput, RouteError> {
+        submuskingcunge(
+            qup, quc, qdp, ql, dt, so, dx, n, cs, bw, tw, tw_cc, n_cc, depth_p, solver,
+        )
+    }
+}
+
 /// Muskingum-Cunge routing implementation for channel flow calculations
 /// Updated to match Fortran version from NWM - now using f32 for performance
+///
+/// Precision contract: every input, output, and intermediate of the kernel is f32.
+/// Channel parameters are read from the database straight into f32 `ChannelParams`.
+/// The one widening happens before the kernel: upstream inflow is summed across
+/// reaches in f64 and narrowed to f32 once per timestep as `qup`/`quc`.
+// Kept line-for-line with the Fortran, including its min/max bounds on X
+#[allow(
+    clippy::too_many_arguments,
+    clippy::needless_late_init,
+    clippy::manual_clamp
+)]
 pub fn submuskingcunge(
     qup: f32,     // flow upstream previous timestep
     quc: f32,     // flow upstream current timestep
@@ -15,9 +108,8 @@ pub fn submuskingcunge(
     tw_cc: f32,   // top width of compound (meters)
     n_cc: f32,    // mannings of compound
     depth_p: f32, // depth of flow in channel
-) -> (f32, f32, f32, f32, f32, f32) {
-    // Returns (qdc, velc, depthc, ck, cn, x)
-    //
+    solver: &SolverConfig,
+) -> Result<KernelOutput, RouteError> {
     #[inline(always)]
     fn pow_2_3(x: f32) -> f32 {
         x.powf(2.0 / 3.0)
@@ -36,6 +128,7 @@ pub fn submuskingcunge(
     let mut c3: f32 = 0.0;
     let mut c4: f32 = 0.0;
     let mut km: f32;
+    let mut travel_time: f32 = 0.0;
     let mut x: f32 = 0.0;
     let mut ck: f32 = 0.0;
     let mut cn: f32 = 0.0;
@@ -58,9 +151,11 @@ pub fn submuskingcunge(
     let mut aerror: f32 = 0.01;
     let mut rerror: f32 = 1.0;
     let mut iter: i32;
-    let mut maxiter: i32 = 100;
-    let mindepth: f32 = 0.01;
+    let mut maxiter: i32 = MAX_ITERATIONS;
+    let mindepth: f32 = MIN_DEPTH;
     let mut tries: i32 = 0;
+    let mut converged = true;
+    let mut iterations: u32 = 0;
 
     // Set trapezoid distance
     z = if cs == 0.0 { 1.0 } else { 1.0 / cs };
@@ -74,21 +169,34 @@ pub fn submuskingcunge(
         (tw - bw) / (2.0 * z)
     };
 
-    // Check for invalid channel coefficients
-    if n <= 0.0 || so <= 0.0 || z <= 0.0 || bw <= 0.0 {
-        panic!(
-            "Error in channel coefficients -> Muskingum cunge: n={}, so={}, z={}, bw={}",
-            n, so, z, bw
-        );
+    // Flow above bankfull only enters the compound channel when it is defined and enabled
+    let compound = solver.compound_channel && tw_cc > 0.0 && n_cc > 0.0;
+
+    // Check for invalid channel coefficients; dx divides the travel time and X
+    if n <= 0.0 || so <= 0.0 || z <= 0.0 || bw <= 0.0 || dx.is_nan() || dx <= 0.0 {
+        return Err(RouteError::Kernel {
+            reaches: Vec::new(),
+            message: format!(
+                "Error in channel coefficients -> Muskingum cunge: n={}, so={}, z={}, bw={}, dx={}",
+                n, so, z, bw, dx
+            ),
+        });
     }
 
     // Initialize depth
     let mut depth_c = f32::max(depth_p, 0.0);
-    h = (depth_c * 1.33) + mindepth;
-    h_0 = depth_c * 0.67;
+    let seed = match solver.bracket {
+        SecantBracket::PreviousDepth => depth_c,
+        // Only rising inflow moves the seed; on recession the previous depth is the better guess
+        SecantBracket::Inflow => {
+            f32::max(depth_c, normal_depth(f32::max(quc + ql, 0.0), so, n, bw, z))
+        }
+    };
+    h = (seed * solver.bracket_upper) + mindepth;
+    h_0 = seed * solver.bracket_lower;
 
-    let qdc: f32;
-    let velc: f32;
+    let mut qdc: f32;
+    let mut velc: f32;
 
     // Only solve if there's water to flux
     if ql > 0.0 || qup > 0.0 || quc > 0.0 || qdp > 0.0 {
@@ -96,16 +204,15 @@ pub fn submuskingcunge(
             iter = 0;
 
             // Secant method loop
-            while rerror > 0.01 && aerror >= mindepth && iter <= maxiter {
+            while rerror > RELATIVE_TOLERANCE && aerror >= mindepth && iter <= maxiter {
                 // Lower interval (h_0)
                 wp_c = 0.0;
-                area = 0.0;
                 area_c = 0.0;
 
                 // Calculate hydraulic geometry for h_0
                 twl = bw + 2.0 * z * h_0;
 
-                if h_0 > bfd && tw_cc > 0.0 && n_cc > 0.0 {
+                if compound && h_0 > bfd {
                     // Water outside of defined channel
                     area = (bw + bfd * z) * bfd;
                     area_c = tw_cc * (h_0 - bfd);
@@ -119,7 +226,7 @@ pub fn submuskingcunge(
                 }
 
                 // Calculate kinematic celerity
-                if h_0 > bfd && tw_cc > 0.0 && n_cc > 0.0 {
+                if compound && h_0 > bfd {
                     ck = f32::max(
                         0.0,
                         ((so.sqrt() / n)
@@ -147,10 +254,15 @@ pub fn submuskingcunge(
                     ck = 0.0;
                 }
 
-                km = if ck > 0.0 { f32::max(dt, dx / ck) } else { dt };
+                travel_time = if ck > 0.0 { dx / ck } else { 0.0 };
+                km = if ck > 0.0 {
+                    f32::max(dt, travel_time)
+                } else {
+                    dt
+                };
 
                 // Calculate X parameter for h_0 (interval = 1)
-                if h_0 > bfd && tw_cc > 0.0 && n_cc > 0.0 && ck > 0.0 {
+                if compound && h_0 > bfd && ck > 0.0 {
                     x = f32::min(
                         0.5,
                         f32::max(0.0, 0.5 * (1.0 - (qj_0 / (2.0 * tw_cc * so * ck * dx)))),
@@ -166,7 +278,10 @@ pub fn submuskingcunge(
 
                 d = km * (1.0 - x) + dt / 2.0;
                 if d == 0.0 {
-                    panic!("FATAL ERROR: D is 0 in MUSKINGCUNGE");
+                    return Err(RouteError::Kernel {
+                        reaches: Vec::new(),
+                        message: "FATAL ERROR: D is 0 in MUSKINGCUNGE".to_string(),
+                    });
                 }
 
                 c1 = (km * x + dt / 2.0) / d;
@@ -182,12 +297,11 @@ pub fn submuskingcunge(
 
                 // Upper interval (h)
                 wp_c = 0.0;
-                area = 0.0;
                 area_c = 0.0;
 
                 twl = bw + 2.0 * z * h;
 
-                if h > bfd && tw_cc > 0.0 && n_cc > 0.0 {
+                if compound && h > bfd {
                     area = (bw + bfd * z) * bfd;
                     area_c = tw_cc * (h - bfd);
                     wp = bw + 2.0 * bfd * (1.0 + z * z).sqrt();
@@ -199,7 +313,7 @@ pub fn submuskingcunge(
                     r = if wp > 0.0 { area / wp } else { 0.0 };
                 }
 
-                if h > bfd && tw_cc > 0.0 && n_cc > 0.0 {
+                if compound && h > bfd {
                     ck = f32::max(
                         0.0,
                         ((so.sqrt() / n)
@@ -227,12 +341,17 @@ pub fn submuskingcunge(
                     ck = 0.0;
                 }
 
-                km = if ck > 0.0 { f32::max(dt, dx / ck) } else { dt };
+                travel_time = if ck > 0.0 { dx / ck } else { 0.0 };
+                km = if ck > 0.0 {
+                    f32::max(dt, travel_time)
+                } else {
+                    dt
+                };
 
                 let flow_sum = c1 * qup + c2 * quc + c3 * qdp + c4;
 
                 // Calculate X parameter for h (interval = 2)
-                if h > bfd && tw_cc > 0.0 && n_cc > 0.0 && ck > 0.0 {
+                if compound && h > bfd && ck > 0.0 {
                     x = f32::min(
                         0.5,
                         f32::max(
@@ -251,7 +370,10 @@ pub fn submuskingcunge(
 
                 d = km * (1.0 - x) + dt / 2.0;
                 if d == 0.0 {
-                    panic!("FATAL ERROR: D is 0 in MUSKINGCUNGE");
+                    return Err(RouteError::Kernel {
+                        reaches: Vec::new(),
+                        message: "FATAL ERROR: D is 0 in MUSKINGCUNGE".to_string(),
+                    });
                 }
 
                 c1 = (km * x + dt / 2.0) / d;
@@ -291,6 +413,7 @@ pub fn submuskingcunge(
                 h_0 = f32::max(0.0, h);
                 h = f32::max(0.0, h_1);
                 iter += 1;
+                iterations += 1;
 
                 if h < mindepth {
                     break;
@@ -300,14 +423,15 @@ pub fn submuskingcunge(
             if iter >= maxiter {
                 tries += 1;
                 if tries <= 4 {
-                    h = h * 1.33;
-                    h_0 = h_0 * 0.67;
-                    maxiter = maxiter + 25;
+                    h *= solver.bracket_upper;
+                    h_0 *= solver.bracket_lower;
+                    maxiter += 25;
                     continue 'outer;
                 }
 
-                eprintln!("Musk Cunge WARNING: Failure to converge");
-                eprintln!("err,iters,tries: {} {} {}", rerror, iter, tries);
+                console_eprintln!("Musk Cunge WARNING: Failure to converge");
+                console_eprintln!("err,iters,tries: {} {} {}", rerror, iter, tries);
+                converged = false;
             }
 
             // Calculate final flow
@@ -330,6 +454,18 @@ pub fn submuskingcunge(
             velc = (1.0 / n) * pow_2_3(r) * so.sqrt();
             depth_c = h;
 
+            // Replace the unconverged iterate with steady normal flow: outflow equals
+            // inflow at the in-channel Manning normal depth
+            if !converged && solver.nonconvergence_fallback != NonconvergenceFallback::Last {
+                qdc = f32::max(quc + ql, 0.0);
+                depth_c = normal_depth(qdc, so, n, bw, z);
+                velc = if depth_c > 0.0 {
+                    (1.0 / n) * pow_2_3(hydraulic_radius(depth_c, bw, z)) * so.sqrt()
+                } else {
+                    0.0
+                };
+            }
+
             break;
         }
     } else {
@@ -345,8 +481,8 @@ pub fn submuskingcunge(
         let h_lt_bf = f32::min(bfd, depth_c);
 
         // Exception for NWM 3.0: if depth > bankfull but floodplain width is zero,
-        // extend trapezoidal channel upwards
-        let (h_gt_bf, h_lt_bf) = if h_gt_bf > 0.0 && tw_cc <= 0.0 {
+        // extend trapezoidal channel upwards (also done when the compound channel is disabled)
+        let (h_gt_bf, h_lt_bf) = if h_gt_bf > 0.0 && (tw_cc <= 0.0 || !solver.compound_channel) {
             (0.0, depth_c)
         } else {
             (h_gt_bf, h_lt_bf)
@@ -377,5 +513,266 @@ pub fn submuskingcunge(
         cn = ck * (dt / dx);
     }
 
-    (qdc, velc, depth_c, ck, cn, x)
+    debug_assert!(
+        qdc >= 0.0 || qdc.is_nan(),
+        "Negative outflow {} from qup={}, quc={}, qdp={}, ql={}",
+        qdc,
+        qup,
+        quc,
+        qdp,
+        ql
+    );
+
+    Ok(KernelOutput {
+        qdc,
+        velc,
+        depthc: depth_c,
+        ck,
+        cn,
+        x,
+        converged,
+        iterations,
+        travel_time,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn zero_length_reach_is_rejected() {
+        let params = fixtures::default_params();
+        let error = submuskingcunge(
+            0.0,
+            10.0,
+            0.0,
+            0.0,
+            3600.0,
+            params.s0,
+            0.0,
+            params.n,
+            params.cs,
+            params.bw,
+            params.tw,
+            params.twcc,
+            params.ncc,
+            0.0,
+            &SolverConfig::default(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, RouteError::Kernel { .. }));
+        assert!(error.to_string().contains("dx=0"), "{}", error);
+    }
+
+    #[test]
+    fn outflow_is_never_negative() {
+        let params = fixtures::default_params();
+        let solver = SolverConfig::default();
+        let flows = [0.0, 0.01, 1.0, 100.0, 10000.0];
+        // Large losses exercise the branch that clamps a negative lateral term
+        let lateral = [-1000.0, -10.0, -0.01, 0.0, 0.01, 10.0];
+
+        for dt in [60.0, 300.0, 3600.0] {
+            for dx in [10.0, 1000.0, 20000.0] {
+                for so in [0.00001, 0.001, 0.1] {
+                    for qup in flows {
+                        for quc in flows {
+                            for qdp in flows {
+                                for ql in lateral {
+                                    let output = submuskingcunge(
+                                        qup,
+                                        quc,
+                                        qdp,
+                                        ql,
+                                        dt,
+                                        so,
+                                        dx,
+                                        params.n,
+                                        params.cs,
+                                        params.bw,
+                                        params.tw,
+                                        params.twcc,
+                                        params.ncc,
+                                        0.0,
+                                        &solver,
+                                    )
+                                    .unwrap();
+                                    assert!(
+                                        output.qdc >= 0.0,
+                                        "qdc={} for qup={}, quc={}, qdp={}, ql={}, dt={}, dx={}, so={}",
+                                        output.qdc,
+                                        qup,
+                                        quc,
+                                        qdp,
+                                        ql,
+                                        dt,
+                                        dx,
+                                        so
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_compound_routes_overbank_flow_as_if_there_were_no_floodplain() {
+        let params = fixtures::default_params();
+        // Route a steady inflow until the reach settles, returning the final step
+        let route = |inflow: f32, twcc: f32, compound_channel: bool| {
+            let solver = SolverConfig {
+                compound_channel,
+                ..SolverConfig::default()
+            };
+            let mut output = submuskingcunge(
+                0.0, 0.0, 0.0, 0.0, 3600.0, params.s0, params.dx, params.n, params.cs, params.bw,
+                params.tw, twcc, params.ncc, 0.0, &solver,
+            )
+            .unwrap();
+            for _ in 0..50 {
+                output = submuskingcunge(
+                    inflow,
+                    inflow,
+                    output.qdc,
+                    0.0,
+                    3600.0,
+                    params.s0,
+                    params.dx,
+                    params.n,
+                    params.cs,
+                    params.bw,
+                    params.tw,
+                    twcc,
+                    params.ncc,
+                    output.depthc,
+                    &solver,
+                )
+                .unwrap();
+            }
+            output
+        };
+
+        // Bankfull depth is (tw - bw) * cs / 2 = 1.25 m
+        let overbank = route(100.0, params.twcc, true);
+        let in_channel = route(100.0, params.twcc, false);
+        let no_floodplain = route(100.0, 0.0, true);
+        assert!(overbank.depthc > 1.25);
+        assert_ne!(in_channel.depthc, overbank.depthc);
+        assert_eq!(in_channel.depthc, no_floodplain.depthc);
+        assert_eq!(in_channel.qdc, no_floodplain.qdc);
+        assert_eq!(in_channel.velc, no_floodplain.velc);
+        assert_eq!(in_channel.ck, no_floodplain.ck);
+
+        // Below bankfull the floodplain is never reached, so the flag changes nothing
+        let low = route(1.0, params.twcc, true);
+        assert!(low.depthc < 1.25);
+        assert_eq!(low.depthc, route(1.0, params.twcc, false).depthc);
+        assert_eq!(low.qdc, route(1.0, params.twcc, false).qdc);
+    }
+
+    #[test]
+    fn nonconvergence_fallback_replaces_the_last_iterate_with_normal_flow() {
+        // A short, flat reach hit by a flood wave whose secant solve exhausts its retries
+        let route = |nonconvergence_fallback| {
+            let solver = SolverConfig {
+                nonconvergence_fallback,
+                ..SolverConfig::default()
+            };
+            submuskingcunge(
+                0.02, 300.0, 400.0, 0.02, 300.0, 0.0001, 30.0, 0.023, 0.46, 0.83, 1.04, 5.4, 0.046,
+                1.1, &solver,
+            )
+            .unwrap()
+        };
+
+        // The last secant iterate, after every retry; its exact value depends on libm
+        let last = route(NonconvergenceFallback::Last);
+        assert!(!last.converged);
+        assert!(last.iterations > MAX_ITERATIONS as u32);
+        assert!((last.qdc - 140.028).abs() < 0.01 * 140.028, "{}", last.qdc);
+
+        // Outflow is the inflow, at the Manning normal depth that carries it
+        let normal = route(NonconvergenceFallback::NormalFlow);
+        assert!(!normal.converged);
+        assert_eq!(normal.qdc, 300.0_f32 + 0.02_f32);
+        let z = 1.0 / 0.46;
+        assert_eq!(
+            normal.depthc,
+            normal_depth(normal.qdc, 0.0001, 0.023, 0.83, z)
+        );
+        let area = (0.83 + z * normal.depthc) * normal.depthc;
+        assert!((normal.velc * area - normal.qdc).abs() < 0.01 * normal.qdc);
+
+        // Fill routes on with normal flow too; only the written values are blanked
+        let fill = route(NonconvergenceFallback::Fill);
+        assert_eq!(fill.qdc, normal.qdc);
+        assert_eq!(fill.depthc, normal.depthc);
+        assert_eq!(fill.velc, normal.velc);
+    }
+
+    // Benchmark for --secant-bracket. Neither bracketing failed to converge on any of the
+    // flashy hydrographs tried (spikes of 10-1000 m3/s, 300 s and hourly steps). The inflow
+    // bracket saves iterations at sub-hourly steps like this one; at hourly steps the routed
+    // peak is attenuated well below the inflow and the previous depth seeds as well or better.
+    #[test]
+    fn inflow_bracket_solves_flashy_hydrographs_in_fewer_iterations() {
+        let params = fixtures::default_params();
+        // A 1000 m3/s spike every 40 steps on a 0.5 m3/s baseflow
+        let inflow: Vec<f32> = (0..400)
+            .map(|t| match t % 40 {
+                0 => 1000.0,
+                1 => 500.0,
+                _ => 0.5,
+            })
+            .collect();
+
+        let route = |bracket: SecantBracket| {
+            let solver = SolverConfig {
+                bracket,
+                ..SolverConfig::default()
+            };
+            let (mut qup, mut qdp, mut depth_p) = (0.0, 0.0, 0.0);
+            let mut iterations = 0;
+            for &quc in &inflow {
+                let output = submuskingcunge(
+                    qup,
+                    quc,
+                    qdp,
+                    0.0,
+                    300.0,
+                    0.0001,
+                    params.dx,
+                    params.n,
+                    params.cs,
+                    params.bw,
+                    params.tw,
+                    params.twcc,
+                    params.ncc,
+                    depth_p,
+                    &solver,
+                )
+                .unwrap();
+                assert!(output.converged);
+                iterations += output.iterations;
+                qup = quc;
+                qdp = output.qdc;
+                depth_p = output.depthc;
+            }
+            iterations
+        };
+
+        let previous_iterations = route(SecantBracket::PreviousDepth);
+        let seeded_iterations = route(SecantBracket::Inflow);
+        assert!(
+            seeded_iterations < previous_iterations,
+            "{} iterations with the inflow bracket, {} with the previous depth",
+            seeded_iterations,
+            previous_iterations
+        );
+    }
 }
@@ -0,0 +1,58 @@
+use crate::console_println;
+use indicatif::ProgressBar;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Whether this build can query process memory (the `memory-stats` feature)
+pub const AVAILABLE: bool = cfg!(feature = "memory-stats");
+
+// Resident set size of this process in bytes, if the platform query is available
+pub fn resident_bytes() -> Option<usize> {
+    #[cfg(feature = "memory-stats")]
+    {
+        memory_stats::memory_stats().map(|usage| usage.physical_mem)
+    }
+    #[cfg(not(feature = "memory-stats"))]
+    {
+        None
+    }
+}
+
+fn mebibytes(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+// Print resident memory every `interval` until the bar is finished, then the peak.
+// Memory is sampled more often than it is printed so short spikes count toward the peak.
+pub fn spawn_memory_report(
+    progress_bar: Arc<ProgressBar>,
+    interval: Duration,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut peak = 0;
+        let mut last_report = Instant::now();
+        while !progress_bar.is_finished() {
+            thread::sleep(Duration::from_millis(100));
+            let Some(resident) = resident_bytes() else {
+                continue;
+            };
+            peak = peak.max(resident);
+            if last_report.elapsed() >= interval {
+                console_println!(
+                    "Memory: {:.1} MiB resident ({}/{} nodes)",
+                    mebibytes(resident),
+                    progress_bar.position(),
+                    progress_bar.length().unwrap_or(0)
+                );
+                last_report = Instant::now();
+            }
+        }
+        if let Some(resident) = resident_bytes() {
+            peak = peak.max(resident);
+        }
+        if peak > 0 {
+            console_println!("Peak resident memory: {:.1} MiB", mebibytes(peak));
+        }
+    })
+}
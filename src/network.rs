@@ -1,27 +1,43 @@
-use crate::config::{ChannelParams, ColumnConfig};
+use crate::config::{ChannelParams, ColumnConfig, GeometryCheck, ReachType};
+use crate::error::RouteError;
+use crate::io::csv::{load_external_flows, runoff_to_flow};
+use crate::io::forcing_window::ForcingWindow;
+use crate::kw_kernel::celerity;
 use crate::state::NodeStatus;
+use crate::{console_println, console_warn};
 use anyhow::{Context, Result};
+use regex::Regex;
 use rusqlite::Connection;
-use std::collections::{HashMap, VecDeque};
-use std::path::PathBuf;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, RwLock};
 
 // Network node representing a catchment/nexus
 #[derive(Debug, Clone)]
 pub struct NetworkNode {
-    pub id: u32,
-    pub downstream_id: Option<u32>,
-    pub upstream_ids: Vec<u32>,
+    pub id: u64,
+    pub downstream_id: Option<u64>,
+    pub upstream_ids: Vec<u64>,
     pub area_sqkm: Option<f32>,
     pub status: Arc<RwLock<NodeStatus>>,
     pub qlat_file: PathBuf,
-    pub inflow_storage: Arc<Mutex<VecDeque<f32>>>,
+    // Summed upstream flow, accumulated in f64 so large confluences don't lose precision
+    pub inflow_storage: Arc<Mutex<VecDeque<f64>>>,
+    // Lateral inflow (m3/s per forcing step) supplied in memory instead of read from qlat_file
+    pub lateral_inflow: Option<Vec<f32>>,
+    // Lateral inflow is read from the forcing window when the reach is routed
+    pub windowed_forcing: bool,
+    // Share of the catchment runoff this reach receives, applied before the area conversion
+    pub lateral_weight: f32,
+    // Selects the kernel the reach is routed with
+    pub reach_type: ReachType,
 }
 
 impl NetworkNode {
     pub fn new(
-        id: u32,
-        downstream_id: Option<u32>,
+        id: u64,
+        downstream_id: Option<u64>,
         area_sqkm: Option<f32>,
         qlat_file: PathBuf,
     ) -> Self {
@@ -33,15 +49,52 @@ impl NetworkNode {
             status: Arc::new(RwLock::new(NodeStatus::NotReady)),
             qlat_file,
             inflow_storage: Arc::new(Mutex::new(VecDeque::new())),
+            lateral_inflow: None,
+            windowed_forcing: false,
+            lateral_weight: 1.0,
+            reach_type: ReachType::Channel,
         }
     }
 }
 
-// Network topology
+// Extracts numeric ids from prefixed database ids such as `wb-123` or `cat-0001`
 #[derive(Debug, Clone)]
+pub struct IdParser {
+    pattern: Regex,
+}
+
+impl IdParser {
+    pub fn new(pattern: &str) -> Result<Self> {
+        let pattern =
+            Regex::new(pattern).with_context(|| format!("Invalid id pattern: {}", pattern))?;
+        if pattern.captures_len() < 2 {
+            return Err(anyhow::anyhow!(
+                "Id pattern {} must contain a capture group for the numeric id",
+                pattern
+            ));
+        }
+        Ok(IdParser { pattern })
+    }
+
+    // Ids must also fit the NetCDF's int64 `feature_id`, so they're never written wrapped
+    pub fn parse(&self, raw_id: &str) -> Option<u64> {
+        self.pattern
+            .captures(raw_id)
+            .and_then(|caps| caps.get(1))
+            .and_then(|m| m.as_str().parse::<u64>().ok())
+            .filter(|&id| i64::try_from(id).is_ok())
+    }
+}
+
+// Network topology
+#[derive(Debug, Clone, Default)]
 pub struct NetworkTopology {
-    pub nodes: HashMap<u32, NetworkNode>,
-    pub routing_order: Vec<u32>,
+    pub nodes: HashMap<u64, NetworkNode>,
+    pub routing_order: Vec<u64>,
+    // Original database ids, used when querying other tables by id
+    pub raw_ids: HashMap<u64, String>,
+    // Ids in the order they were added: the `flowpaths` row order for a gpkg
+    pub source_order: Vec<u64>,
 }
 
 impl NetworkTopology {
@@ -49,29 +102,51 @@ impl NetworkTopology {
         NetworkTopology {
             nodes: HashMap::new(),
             routing_order: Vec::new(),
+            raw_ids: HashMap::new(),
+            source_order: Vec::new(),
         }
     }
 
     pub fn add_node(
         &mut self,
-        id: u32,
-        downstream_id: Option<u32>,
+        id: u64,
+        downstream_id: Option<u64>,
         area_sqkm: Option<f32>,
         qlat_file: PathBuf,
     ) {
         let node = NetworkNode::new(id, downstream_id, area_sqkm, qlat_file);
-        self.nodes.insert(id, node);
+        if self.nodes.insert(id, node).is_none() {
+            self.source_order.push(id);
+        }
+    }
+
+    // Treat reaches draining to an id outside the network as outlets, returning their ids
+    pub fn resolve_boundary_outlets(&mut self) -> Vec<u64> {
+        let boundary: Vec<u64> = self
+            .nodes
+            .values()
+            .filter(|node| {
+                node.downstream_id
+                    .is_some_and(|downstream| !self.nodes.contains_key(&downstream))
+            })
+            .map(|node| node.id)
+            .collect();
+
+        for id in &boundary {
+            if let Some(node) = self.nodes.get_mut(id) {
+                node.downstream_id = None;
+            }
+        }
+
+        boundary
     }
 
     pub fn build_upstream_connections(&mut self) {
-        let mut upstream_map: HashMap<u32, Vec<u32>> = HashMap::new();
+        let mut upstream_map: HashMap<u64, Vec<u64>> = HashMap::new();
 
         for (id, node) in &self.nodes {
             if let Some(downstream) = &node.downstream_id {
-                upstream_map
-                    .entry(*downstream)
-                    .or_insert_with(Vec::new)
-                    .push(*id);
+                upstream_map.entry(*downstream).or_default().push(*id);
             }
         }
 
@@ -82,9 +157,10 @@ impl NetworkTopology {
         }
     }
 
+    #[allow(clippy::collapsible_if)]
     pub fn topological_sort(&mut self) -> Result<()> {
-        let mut in_degree: HashMap<u32, usize> = HashMap::new();
-        let mut queue: VecDeque<u32> = VecDeque::new();
+        let mut in_degree: HashMap<u64, usize> = HashMap::new();
+        let mut queue: VecDeque<u64> = VecDeque::new();
 
         // Calculate in-degrees
         for id in self.nodes.keys() {
@@ -114,9 +190,13 @@ impl NetworkTopology {
         }
 
         if queue.is_empty() {
-            return Err(anyhow::anyhow!(
-                "No headwater nodes found - possible cycle in network"
-            ));
+            let mut reaches: Vec<u64> = self.nodes.keys().copied().collect();
+            reaches.sort_unstable();
+            return Err(RouteError::Topology {
+                reaches,
+                message: "No headwater nodes found - possible cycle in network".to_string(),
+            }
+            .into());
         }
 
         self.routing_order.clear();
@@ -137,28 +217,543 @@ impl NetworkTopology {
         }
 
         if self.routing_order.len() != self.nodes.len() {
-            return Err(anyhow::anyhow!(
-                "Cycle detected in network topology: processed {} nodes out of {}",
-                self.routing_order.len(),
-                self.nodes.len()
-            ));
+            // Reaches on or draining into the cycle are never reached
+            let ordered: HashSet<u64> = self.routing_order.iter().copied().collect();
+            let mut reaches: Vec<u64> = self
+                .nodes
+                .keys()
+                .filter(|id| !ordered.contains(id))
+                .copied()
+                .collect();
+            reaches.sort_unstable();
+            return Err(RouteError::Topology {
+                reaches,
+                message: format!(
+                    "Cycle detected in network topology: processed {} nodes out of {}",
+                    self.routing_order.len(),
+                    self.nodes.len()
+                ),
+            }
+            .into());
         }
 
         Ok(())
     }
+
+    // A routing order that is the same on every run: of the reaches ready at each point,
+    // the lowest id comes first. `routing_order` breaks those ties arbitrarily.
+    pub fn stable_routing_order(&self) -> Vec<u64> {
+        let mut pending: HashMap<u64, usize> = self
+            .nodes
+            .values()
+            .map(|node| (node.id, node.upstream_ids.len()))
+            .collect();
+        let mut ready: BinaryHeap<Reverse<u64>> = pending
+            .iter()
+            .filter(|&(_, &count)| count == 0)
+            .map(|(&id, _)| Reverse(id))
+            .collect();
+
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(Reverse(id)) = ready.pop() {
+            order.push(id);
+            if let Some(downstream) = self.nodes[&id].downstream_id
+                && let Some(count) = pending.get_mut(&downstream)
+            {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(Reverse(downstream));
+                }
+            }
+        }
+        order
+    }
+
+    // Independent basins (reaches draining to the same outlet), each in routing order,
+    // largest first. Requires `topological_sort`.
+    pub fn components(&self) -> Vec<Vec<u64>> {
+        // Downstream reaches come later in routing order, so walk it backwards
+        let mut outlet: HashMap<u64, u64> = HashMap::with_capacity(self.nodes.len());
+        for &id in self.routing_order.iter().rev() {
+            let root = self
+                .nodes
+                .get(&id)
+                .and_then(|node| node.downstream_id)
+                .and_then(|downstream| outlet.get(&downstream).copied())
+                .unwrap_or(id);
+            outlet.insert(id, root);
+        }
+
+        let mut components: HashMap<u64, Vec<u64>> = HashMap::new();
+        for id in &self.routing_order {
+            components.entry(outlet[id]).or_default().push(*id);
+        }
+        let mut components: Vec<(u64, Vec<u64>)> = components.into_iter().collect();
+        components
+            .sort_unstable_by_key(|(root, reaches)| (std::cmp::Reverse(reaches.len()), *root));
+        components.into_iter().map(|(_, reaches)| reaches).collect()
+    }
+}
+
+// Headwater reaches with no forcing data, which can only ever produce zero flow
+pub fn find_dry_reaches(topology: &NetworkTopology) -> Vec<u64> {
+    let has_forcing = |node: &NetworkNode| {
+        if node.windowed_forcing {
+            return true;
+        }
+        if let Some(flows) = &node.lateral_inflow {
+            return !flows.is_empty();
+        }
+        // A header line alone counts as empty
+        std::fs::File::open(&node.qlat_file)
+            .map(|file| {
+                std::io::BufRead::lines(std::io::BufReader::new(file))
+                    .map_while(Result::ok)
+                    .filter(|line| !line.trim().is_empty())
+                    .nth(1)
+                    .is_some()
+            })
+            .unwrap_or(false)
+    };
+
+    let mut dry: Vec<u64> = topology
+        .nodes
+        .values()
+        .filter(|node| node.upstream_ids.is_empty() && !has_forcing(node))
+        .map(|node| node.id)
+        .collect();
+    dry.sort_unstable();
+    dry
+}
+
+// How the per-reach forcing files in a directory line up with the reaches routed
+#[derive(Debug, Default, PartialEq)]
+pub struct ForcingCoverage {
+    // Reaches whose forcing file is present
+    pub covered: usize,
+    // Reaches in routing order without their forcing file
+    pub missing: Vec<u64>,
+    // CSV files in the directory that no reach reads, by name
+    pub unmatched: Vec<String>,
+}
+
+impl ForcingCoverage {
+    pub fn percent(&self) -> f64 {
+        let total = self.covered + self.missing.len();
+        if total == 0 {
+            return 100.0;
+        }
+        100.0 * self.covered as f64 / total as f64
+    }
+}
+
+// Compare the file names in a forcing directory with the file each reach reads, without
+// opening any of them. A directory-path or id-format mistake shows up as low coverage
+// with the intended files unmatched, rather than as reaches silently routed without
+// lateral inflow.
+pub fn forcing_coverage(topology: &NetworkTopology, csv_dir: &Path) -> Result<ForcingCoverage> {
+    let mut unmatched: HashSet<String> = std::fs::read_dir(csv_dir)
+        .with_context(|| format!("Failed to read forcing directory: {}", csv_dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".csv"))
+        .collect();
+
+    let mut coverage = ForcingCoverage::default();
+    for id in &topology.routing_order {
+        let found = topology.nodes[id]
+            .qlat_file
+            .file_name()
+            .is_some_and(|name| unmatched.remove(name.to_string_lossy().as_ref()));
+        if found {
+            coverage.covered += 1;
+        } else {
+            coverage.missing.push(*id);
+        }
+    }
+    coverage.missing.sort_unstable();
+    coverage.unmatched = unmatched.into_iter().collect();
+    coverage.unmatched.sort_unstable();
+    Ok(coverage)
+}
+
+// Point each reach's per-file forcing at the same file in another directory, and clear
+// what the previous run left behind, so one topology can be routed once per forcing member
+pub fn use_forcing_dir(topology: &mut NetworkTopology, csv_dir: &Path) -> Result<()> {
+    for node in topology.nodes.values_mut() {
+        if let Some(file_name) = node.qlat_file.file_name() {
+            node.qlat_file = csv_dir.join(file_name);
+        }
+    }
+    reset_routing_state(topology)
+}
+
+// Empty every reach's upstream inflow and mark it not ready, as before a first run
+pub fn reset_routing_state(topology: &NetworkTopology) -> Result<()> {
+    for node in topology.nodes.values() {
+        node.inflow_storage
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock inflow storage: {}", e))?
+            .clear();
+        *node
+            .status
+            .write()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire status write lock: {}", e))? =
+            NodeStatus::NotReady;
+    }
+    Ok(())
+}
+
+// Lateral inflow is runoff over the reach's catchment, so it can't be converted without an area
+pub fn no_area(id: u64) -> anyhow::Error {
+    RouteError::Forcing {
+        reaches: vec![id],
+        message: format!("Node {} has no area defined", id),
+    }
+    .into()
+}
+
+// Use forcing read from a single file (raw Q_OUT per catchment) as each reach's lateral
+// inflow. Reaches it doesn't cover get none rather than falling back to per-file forcing.
+pub fn attach_forcing(
+    topology: &mut NetworkTopology,
+    mut forcing: HashMap<u64, Vec<f32>>,
+    strict: bool,
+) -> Result<()> {
+    for node in topology.nodes.values_mut() {
+        let flows = match forcing.remove(&node.id) {
+            Some(q_out) => {
+                let area = node.area_sqkm.ok_or_else(|| no_area(node.id))?;
+                q_out
+                    .iter()
+                    .map(|&ql| runoff_to_flow(ql * node.lateral_weight, area))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        node.lateral_inflow = Some(flows);
+    }
+
+    if !forcing.is_empty() {
+        let mut unknown: Vec<u64> = forcing.into_keys().collect();
+        unknown.sort_unstable();
+        console_warn!(
+            strict,
+            "ignoring forcing for {} catchments not in the network: {:?}",
+            unknown.len(),
+            &unknown[..unknown.len().min(10)]
+        )?;
+    }
+    Ok(())
+}
+
+// Read every reach's per-catchment forcing file up front on `threads` threads and hold it
+// as the reach's lateral inflow, so workers don't open the files one by one as they route.
+// Reaches already given forcing in memory, or with no area to convert it, are left alone.
+// Reaches without a file get no lateral inflow, reported in one line rather than per file.
+pub fn preload_forcing(topology: &mut NetworkTopology, threads: usize) -> Result<()> {
+    let jobs: Vec<(u64, PathBuf, f32, f32)> = topology
+        .nodes
+        .values()
+        .filter(|node| node.lateral_inflow.is_none() && !node.windowed_forcing)
+        .filter_map(|node| {
+            node.area_sqkm
+                .map(|area| (node.id, node.qlat_file.clone(), area, node.lateral_weight))
+        })
+        .collect();
+    let threads = threads.clamp(1, jobs.len().max(1));
+
+    let loaded = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|thread| {
+                let jobs = &jobs;
+                scope.spawn(move || {
+                    jobs.iter()
+                        .skip(thread)
+                        .step_by(threads)
+                        .map(|(id, path, area, weight)| {
+                            if !path.exists() {
+                                return Ok((*id, None));
+                            }
+                            load_external_flows(path.clone(), id, *area, *weight)
+                                .map(|flows| (*id, Some(Vec::from(flows))))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("A forcing preload thread panicked"))?
+            })
+            .collect::<Result<Vec<_>>>()
+    })?;
+
+    let mut missing = Vec::new();
+    for (id, flows) in loaded.into_iter().flatten() {
+        if flows.is_none() {
+            missing.push(id);
+        }
+        if let Some(node) = topology.nodes.get_mut(&id) {
+            node.lateral_inflow = Some(flows.unwrap_or_default());
+        }
+    }
+    if !missing.is_empty() {
+        missing.sort_unstable();
+        console_println!(
+            "No forcing file for {} of {} preloaded reaches; they route upstream inflow alone \
+             (first: {:?})",
+            missing.len(),
+            jobs.len(),
+            &missing[..missing.len().min(10)]
+        );
+    }
+    Ok(())
+}
+
+// Parse a reach type as written in a gpkg column or override file, ignoring case
+pub fn parse_reach_type(value: &str) -> Result<ReachType> {
+    <ReachType as clap::ValueEnum>::from_str(value.trim(), true).map_err(|_| {
+        anyhow::anyhow!(
+            "Unknown reach type '{}' (expected channel, waterbody, junction or passthrough)",
+            value
+        )
+    })
+}
+
+// Read each reach's type from a column of the gpkg `flowpath-attributes` table.
+// NULL or empty values leave the reach a channel.
+pub fn load_reach_type_column(
+    conn: &Connection,
+    config: &ColumnConfig,
+    column: &str,
+) -> Result<HashMap<u64, ReachType>> {
+    let id_parser = IdParser::new(&config.id_pattern)?;
+    let query = format!(
+        "SELECT {}, {} FROM 'flowpath-attributes'",
+        config.key, column
+    );
+    let mut stmt = conn
+        .prepare(&query)
+        .with_context(|| format!("Failed to read reach type column {}", column))?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to read reach types")?;
+
+    let mut types = HashMap::new();
+    for (raw_id, value) in rows {
+        let Some(value) = value.filter(|value| !value.trim().is_empty()) else {
+            continue;
+        };
+        let id = id_parser
+            .parse(&raw_id)
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse id from {}", raw_id))?;
+        let reach_type =
+            parse_reach_type(&value).with_context(|| format!("Reach {} ({})", raw_id, column))?;
+        types.insert(id, reach_type);
+    }
+    Ok(types)
+}
+
+// Set the type of each listed reach, warning about ids that aren't in the network
+pub fn apply_reach_types(
+    topology: &mut NetworkTopology,
+    types: &HashMap<u64, ReachType>,
+    strict: bool,
+) -> Result<()> {
+    let mut unknown = Vec::new();
+    for (&id, &reach_type) in types {
+        match topology.nodes.get_mut(&id) {
+            Some(node) => node.reach_type = reach_type,
+            None => unknown.push(id),
+        }
+    }
+    if !unknown.is_empty() {
+        unknown.sort_unstable();
+        console_warn!(
+            strict,
+            "ignoring reach types for {} reaches not in the network: {:?}",
+            unknown.len(),
+            &unknown[..unknown.len().min(10)]
+        )?;
+    }
+    Ok(())
+}
+
+// Give reaches that receive only part of their catchment's runoff their share of it.
+// Must be applied before `attach_forcing`, which converts runoff to flow.
+pub fn apply_lateral_weights(
+    topology: &mut NetworkTopology,
+    weights: &HashMap<u64, f32>,
+    strict: bool,
+) -> Result<()> {
+    let mut unknown = Vec::new();
+    for (&id, &weight) in weights {
+        match topology.nodes.get_mut(&id) {
+            Some(node) => node.lateral_weight = weight,
+            None => unknown.push(id),
+        }
+    }
+    if !unknown.is_empty() {
+        unknown.sort_unstable();
+        console_warn!(
+            strict,
+            "ignoring lateral weights for {} reaches not in the network: {:?}",
+            unknown.len(),
+            &unknown[..unknown.len().min(10)]
+        )?;
+    }
+    Ok(())
+}
+
+// Like `attach_forcing`, but reaches `window` covers read theirs from it when routed
+pub fn attach_forcing_window(
+    topology: &mut NetworkTopology,
+    window: &ForcingWindow,
+    strict: bool,
+) -> Result<()> {
+    for node in topology.nodes.values_mut() {
+        if window.covers(node.id) {
+            if node.area_sqkm.is_none() {
+                return Err(no_area(node.id));
+            }
+            node.windowed_forcing = true;
+            node.lateral_inflow = None;
+        } else {
+            node.windowed_forcing = false;
+            node.lateral_inflow = Some(Vec::new());
+        }
+    }
+
+    let mut unknown: Vec<u64> = window
+        .covered()
+        .iter()
+        .copied()
+        .filter(|id| !topology.nodes.contains_key(id))
+        .collect();
+    if !unknown.is_empty() {
+        unknown.sort_unstable();
+        console_warn!(
+            strict,
+            "ignoring forcing for {} catchments not in the network: {:?}",
+            unknown.len(),
+            &unknown[..unknown.len().min(10)]
+        )?;
+    }
+    Ok(())
+}
+
+// Sum each reach's own area with all of its transitive upstream areas (km2)
+pub fn cumulative_area(topology: &NetworkTopology, strict: bool) -> Result<HashMap<u64, f32>> {
+    let mut cumulative: HashMap<u64, f32> = HashMap::with_capacity(topology.nodes.len());
+    let mut missing_area = Vec::new();
+
+    // Routing order is topological, so every upstream total is known before it's needed
+    for id in &topology.routing_order {
+        if let Some(node) = topology.nodes.get(id) {
+            let upstream: f32 = node
+                .upstream_ids
+                .iter()
+                .filter_map(|upstream_id| cumulative.get(upstream_id))
+                .sum();
+            let own_area = node.area_sqkm.unwrap_or_else(|| {
+                missing_area.push(*id);
+                0.0
+            });
+            cumulative.insert(*id, own_area + upstream);
+        }
+    }
+
+    if !missing_area.is_empty() {
+        console_warn!(
+            strict,
+            "No area for {} nodes, treating as zero in drainage area: {:?}",
+            missing_area.len(),
+            missing_area
+        )?;
+    }
+
+    Ok(cumulative)
+}
+
+// Compare the gpkg against the columns `config` will query and the ids against its id
+// pattern, describing each mismatch
+pub fn check_schema(conn: &Connection, config: &ColumnConfig) -> Result<Vec<String>> {
+    let id_parser = IdParser::new(&config.id_pattern)?;
+    let mut problems = Vec::new();
+    for (table, expected) in config.expected_columns() {
+        let mut stmt = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")
+            .context("Failed to prepare schema query")?;
+        let actual = stmt
+            .query_map([table], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to read columns of {}", table))?;
+
+        if actual.is_empty() {
+            problems.push(format!("table '{}' not found", table));
+            continue;
+        }
+        // SQLite column names are case-insensitive
+        for column in expected {
+            if !actual.iter().any(|name| name.eq_ignore_ascii_case(column)) {
+                problems.push(format!(
+                    "table '{}' has no column '{}' (found: {})",
+                    table,
+                    column,
+                    actual.join(", ")
+                ));
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Ok(problems);
+    }
+
+    // Reaches whose ids the pattern can't parse would be dropped when building the network
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM 'flowpaths'", config.key))
+        .context("Failed to prepare flowpath id query")?;
+    let unparsed = stmt
+        .query_map([], |row| row.get::<_, Option<String>>(0))?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to read flowpath ids")?
+        .into_iter()
+        .flatten()
+        .filter(|id| id_parser.parse(id).is_none())
+        .collect::<Vec<_>>();
+    if let Some(first) = unparsed.first() {
+        problems.push(format!(
+            "{} ids in flowpaths.{} don't match the id pattern {} (first: '{}')",
+            unparsed.len(),
+            config.key,
+            config.id_pattern,
+            first
+        ));
+    }
+    Ok(problems)
 }
 
 // Function to build network topology from database
 pub fn build_network_topology(
     conn: &Connection,
     config: &ColumnConfig,
-    csv_dir: &PathBuf,
+    csv_dir: &Path,
+    strict: bool,
 ) -> Result<NetworkTopology> {
     let mut topology = NetworkTopology::new();
+    let id_parser = IdParser::new(&config.id_pattern)?;
 
+    // Rows are read in rowid order, which `source_order` records for --feature-order database
     let network_query = format!(
-        "SELECT {}, {}, areasqkm FROM 'flowpaths' WHERE {} IS NOT NULL GROUP BY {}",
-        config.key, config.downstream, config.downstream, config.key
+        "SELECT {}, {}, areasqkm FROM 'flowpaths' WHERE {} IS NOT NULL ORDER BY rowid",
+        config.key, config.downstream, config.downstream
     );
     let mut stmt = conn
         .prepare(&network_query)
@@ -172,25 +767,196 @@ pub fn build_network_topology(
         ))
     })?;
 
+    let mut invalid_ids = Vec::new();
+    // Rows repeating an id: exact copies are dropped, but rows that disagree can't be resolved
+    let mut repeated = 0;
+    let mut conflicts = Vec::new();
+
     for row in rows {
         let (id, downstream_id, area_sqkm) = row.context("Failed to read row")?;
 
-        let n_id = id
-            .split('-')
-            .nth(1)
-            .and_then(|s| s.parse::<u32>().ok())
-            .ok_or_else(|| anyhow::anyhow!("Invalid ID format: {}", id))?;
+        let (Some(n_id), Some(n_downstream_id)) =
+            (id_parser.parse(&id), id_parser.parse(&downstream_id))
+        else {
+            invalid_ids.push(format!("{} -> {}", id, downstream_id));
+            continue;
+        };
 
-        let n_downstream_id = downstream_id
-            .split('-')
-            .nth(1)
-            .and_then(|s| s.parse::<u32>().ok())
-            .ok_or_else(|| anyhow::anyhow!("Invalid toID format: {}", downstream_id))?;
+        if let Some(existing) = topology.nodes.get(&n_id) {
+            // Distinct ids such as wb-12 and wb-012 can't share a reach, even if they agree
+            if topology.raw_ids[&n_id] == id
+                && existing.downstream_id == Some(n_downstream_id)
+                && existing.area_sqkm == Some(area_sqkm)
+            {
+                repeated += 1;
+            } else {
+                conflicts.push(format!(
+                    "{} (toid {}, area {}) vs {} (toid {}, area {})",
+                    topology.raw_ids[&n_id],
+                    existing.downstream_id.unwrap_or_default(),
+                    existing.area_sqkm.unwrap_or_default(),
+                    id,
+                    n_downstream_id,
+                    area_sqkm
+                ));
+            }
+            continue;
+        }
 
         let qlat_file_path = csv_dir.join(format!("cat-{}.csv", n_id));
         topology.add_node(n_id, Some(n_downstream_id), Some(area_sqkm), qlat_file_path);
+        topology.raw_ids.insert(n_id, id);
+    }
+
+    if !invalid_ids.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Failed to parse {} ids with pattern {}: {:?}",
+            invalid_ids.len(),
+            config.id_pattern,
+            invalid_ids
+        ));
+    }
+    if !conflicts.is_empty() {
+        return Err(RouteError::Topology {
+            reaches: Vec::new(),
+            message: format!(
+                "{} flowpath ids appear more than once with different values: {:?}",
+                conflicts.len(),
+                conflicts
+            ),
+        }
+        .into());
+    }
+    if repeated > 0 {
+        console_warn!(strict, "ignoring {} duplicate flowpath rows", repeated)?;
+    }
+
+    // Catch this here; the topological sort would report it as a missing headwater
+    if topology.nodes.is_empty() {
+        let total: i64 = conn
+            .query_row("SELECT COUNT(*) FROM 'flowpaths'", [], |row| row.get(0))
+            .context("Failed to count flowpaths")?;
+        let message = if total == 0 {
+            "The network is empty: the flowpaths table has no rows".to_string()
+        } else {
+            format!(
+                "The network is empty: all {} flowpaths have a null {} column",
+                total, config.downstream
+            )
+        };
+        return Err(RouteError::Topology {
+            reaches: Vec::new(),
+            message,
+        }
+        .into());
+    }
+
+    // Reaches whose downstream lies outside the domain become outlets
+    let boundary = topology.resolve_boundary_outlets();
+    if !boundary.is_empty() {
+        console_warn!(
+            strict,
+            "{} reaches drain outside the network and are treated as outlets: {:?}",
+            boundary.len(),
+            boundary
+        )?;
+    }
+
+    finish_topology(topology)
+}
+
+// Build from rows of (id, upstream ids, area km2), for hydrofabrics that list each reach's
+// upstream reaches instead of its downstream one. Routing needs each reach to drain to a
+// single reach, so one listed upstream of several (a braided channel) is an error.
+pub fn build_network_topology_from_upstream(
+    reaches: &[(u64, Vec<u64>, f32)],
+    csv_dir: &Path,
+    strict: bool,
+) -> Result<NetworkTopology> {
+    let mut areas: HashMap<u64, f32> = HashMap::with_capacity(reaches.len());
+    let mut repeated = Vec::new();
+    for (id, _, area_sqkm) in reaches {
+        if areas.insert(*id, *area_sqkm).is_some() {
+            repeated.push(*id);
+        }
+    }
+    if !repeated.is_empty() {
+        return Err(RouteError::Topology {
+            message: format!(
+                "{} reaches are listed more than once: {:?}",
+                repeated.len(),
+                repeated
+            ),
+            reaches: repeated,
+        }
+        .into());
+    }
+    if reaches.is_empty() {
+        return Err(RouteError::Topology {
+            reaches: Vec::new(),
+            message: "The network is empty: no reaches were given".to_string(),
+        }
+        .into());
+    }
+
+    // Invert the upstream lists into the single downstream id each reach drains to
+    let mut downstream: HashMap<u64, u64> = HashMap::new();
+    let mut braided: HashMap<u64, Vec<u64>> = HashMap::new();
+    let mut boundary = Vec::new();
+    for (id, upstream_ids, _) in reaches {
+        for upstream_id in upstream_ids {
+            if !areas.contains_key(upstream_id) {
+                boundary.push(*upstream_id);
+            } else if let Some(&existing) = downstream.get(upstream_id) {
+                braided
+                    .entry(*upstream_id)
+                    .or_insert_with(|| vec![existing])
+                    .push(*id);
+            } else {
+                downstream.insert(*upstream_id, *id);
+            }
+        }
+    }
+    if !braided.is_empty() {
+        let mut braided: Vec<(u64, Vec<u64>)> = braided.into_iter().collect();
+        braided.sort_unstable();
+        return Err(RouteError::Topology {
+            reaches: braided.iter().map(|(id, _)| *id).collect(),
+            message: format!(
+                "{} reaches drain to more than one reach, which can't be routed (reach, downstream reaches): {:?}",
+                braided.len(),
+                braided
+            ),
+        }
+        .into());
+    }
+    if !boundary.is_empty() {
+        boundary.sort_unstable();
+        boundary.dedup();
+        console_warn!(
+            strict,
+            "{} upstream reaches are outside the network and contribute no inflow: {:?}",
+            boundary.len(),
+            boundary
+        )?;
+    }
+
+    let mut topology = NetworkTopology::new();
+    for (id, _, area_sqkm) in reaches {
+        let qlat_file_path = csv_dir.join(format!("cat-{}.csv", id));
+        topology.add_node(
+            *id,
+            downstream.get(id).copied(),
+            Some(*area_sqkm),
+            qlat_file_path,
+        );
     }
 
+    finish_topology(topology)
+}
+
+// Link each reach to its upstream reaches and sort into routing order
+fn finish_topology(mut topology: NetworkTopology) -> Result<NetworkTopology> {
     // Build upstream connections
     topology.build_upstream_connections();
 
@@ -210,14 +976,61 @@ pub fn build_network_topology(
     Ok(topology)
 }
 
-// Fetch all channel parameters in a single query
+// Set-diff the routed flowpath ids against the flowpath-attributes ids, returning the
+// reaches with no parameters and the parameters with no reach, each sorted
+pub fn cross_check_ids(
+    conn: &Connection,
+    config: &ColumnConfig,
+) -> Result<(Vec<String>, Vec<String>)> {
+    let read_ids = |query: String| -> Result<HashSet<String>> {
+        let mut stmt = conn
+            .prepare(&query)
+            .with_context(|| format!("Failed to prepare id query: {}", query))?;
+        let ids = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<HashSet<_>, _>>()
+            .context("Failed to read ids")?;
+        Ok(ids)
+    };
+    // Only flowpaths with a downstream id are routed, as in build_network_topology
+    let flowpaths = read_ids(format!(
+        "SELECT {0} FROM 'flowpaths' WHERE {1} IS NOT NULL",
+        config.key, config.downstream
+    ))?;
+    let attributes = read_ids(format!(
+        "SELECT {0} FROM 'flowpath-attributes' WHERE {0} IS NOT NULL",
+        config.key
+    ))?;
+
+    let mut missing_attributes: Vec<String> = flowpaths.difference(&attributes).cloned().collect();
+    let mut missing_flowpaths: Vec<String> = attributes.difference(&flowpaths).cloned().collect();
+    missing_attributes.sort_unstable();
+    missing_flowpaths.sort_unstable();
+    Ok((missing_attributes, missing_flowpaths))
+}
+
+// Fetch all channel parameters in a single query. Reaches with NULL parameters are left
+// out, as if they had no row.
 pub fn load_channel_parameters(
     conn: &Connection,
     topology: &NetworkTopology,
     config: &ColumnConfig,
-) -> Result<HashMap<u32, ChannelParams>> {
+) -> Result<HashMap<u64, ChannelParams>> {
+    Ok(read_channel_parameters(conn, topology, config, None, false)?.0)
+}
+
+// Fetch all channel parameters in a single query, filling NULL columns from `default`.
+// Without a default, a reach with any NULL is left out. Also returns the reaches that had
+// NULLs, sorted.
+pub fn read_channel_parameters(
+    conn: &Connection,
+    topology: &NetworkTopology,
+    config: &ColumnConfig,
+    default: Option<&ChannelParams>,
+    strict: bool,
+) -> Result<(HashMap<u64, ChannelParams>, Vec<u64>)> {
     if topology.routing_order.is_empty() {
-        return Ok(HashMap::new());
+        return Ok((HashMap::new(), Vec::new()));
     }
 
     println!(
@@ -225,8 +1038,16 @@ pub fn load_channel_parameters(
         topology.routing_order.len()
     );
 
+    let id_parser = IdParser::new(&config.id_pattern)?;
+
     // Build single query for all IDs
-    let wb_ids = topology.routing_order.iter().map(|id| format!("wb-{}", id));
+    let wb_ids = topology.routing_order.iter().map(|id| {
+        topology
+            .raw_ids
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| format!("wb-{}", id))
+    });
 
     let placeholders = vec!["?"; wb_ids.len()].join(",");
 
@@ -253,32 +1074,200 @@ pub fn load_channel_parameters(
     let params_vec: Vec<_> = stmt
         .query_map(rusqlite::params_from_iter(wb_ids), |row| {
             let wb_id: String = row.get(0)?;
-            let id = wb_id
-                .strip_prefix("wb-")
-                .and_then(|s| s.parse::<u32>().ok())
+            let id = id_parser
+                .parse(&wb_id)
                 .ok_or(rusqlite::Error::InvalidQuery)?;
 
-            Ok((
-                id,
-                ChannelParams {
-                    dx: row.get(1)?,
-                    n: row.get(2)?,
-                    ncc: row.get(3)?,
-                    s0: row.get(4)?,
-                    bw: row.get(5)?,
-                    tw: row.get(6)?,
-                    twcc: row.get(7)?,
-                    cs: row.get(8)?,
-                },
-            ))
+            let mut values = [None; 8];
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = row.get::<_, Option<f32>>(column + 1)?;
+            }
+            Ok((id, values))
         })?
         .collect::<Result<Vec<_>, _>>()
         .context("Failed to read channel parameters")?;
 
     // Build output structures
-    let channel_params_map: HashMap<u32, ChannelParams> = params_vec.into_iter().collect();
+    let columns = [
+        &config.dx,
+        &config.n,
+        &config.ncc,
+        &config.s0,
+        &config.bw,
+        &config.tw,
+        &config.twcc,
+        &config.cs,
+    ];
+    let mut channel_params_map = HashMap::new();
+    let mut null_reaches = Vec::new();
+    for (id, values) in params_vec {
+        if values.iter().any(Option::is_none) {
+            let nulls: Vec<&str> = columns
+                .iter()
+                .zip(&values)
+                .filter(|(_, value)| value.is_none())
+                .map(|(column, _)| column.as_str())
+                .collect();
+            null_reaches.push((id, nulls));
+        }
+        if let Some(params) = fill_null_parameters(values, default) {
+            channel_params_map.insert(id, params);
+        }
+    }
+
+    null_reaches.sort_unstable();
+    if !null_reaches.is_empty() {
+        let listed: Vec<String> = null_reaches
+            .iter()
+            .take(10)
+            .map(|(id, nulls)| format!("{} ({})", id, nulls.join(", ")))
+            .collect();
+        // Filled reaches route, so only reaches left without parameters are a warning
+        // (and an error under --strict)
+        if default.is_some() {
+            console_println!(
+                "{} reaches have NULL channel parameters, filled from --default-channel: {}",
+                null_reaches.len(),
+                listed.join("; ")
+            );
+        } else {
+            console_warn!(
+                strict,
+                "{} reaches have NULL channel parameters, so they have no parameters: {}",
+                null_reaches.len(),
+                listed.join("; ")
+            )?;
+        }
+    }
+
+    report_loaded_parameters(topology, &channel_params_map);
+
+    Ok((
+        channel_params_map,
+        null_reaches.into_iter().map(|(id, _)| id).collect(),
+    ))
+}
+
+// A reach's parameters from its dx, n, ncc, s0, bw, tw, twcc and cs columns, taking NULLs
+// from `default`; None when a column is NULL and there is no default
+fn fill_null_parameters(
+    values: [Option<f32>; 8],
+    default: Option<&ChannelParams>,
+) -> Option<ChannelParams> {
+    let fallback = default.map(|d| [d.dx, d.n, d.ncc, d.s0, d.bw, d.tw, d.twcc, d.cs]);
+    let mut filled = [0.0; 8];
+    for (column, value) in values.into_iter().enumerate() {
+        filled[column] = match value {
+            Some(value) => value,
+            None => fallback?[column],
+        };
+    }
+    let [dx, n, ncc, s0, bw, tw, twcc, cs] = filled;
+    Some(ChannelParams {
+        dx,
+        n,
+        ncc,
+        s0,
+        bw,
+        tw,
+        twcc,
+        cs,
+    })
+}
+
+// Read channel parameters from a CSV with the same columns as `flowpath-attributes`
+pub fn load_channel_parameters_csv(
+    csv_file: &Path,
+    topology: &NetworkTopology,
+    config: &ColumnConfig,
+) -> Result<HashMap<u64, ChannelParams>> {
+    println!(
+        "Loading channel parameters for {} nodes from {}...",
+        topology.routing_order.len(),
+        csv_file.display()
+    );
+
+    let id_parser = IdParser::new(&config.id_pattern)?;
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_path(csv_file)
+        .with_context(|| format!("Failed to open attributes CSV: {}", csv_file.display()))?;
 
-    // Report results
+    let headers = rdr
+        .headers()
+        .context("Failed to read attributes CSV headers")?
+        .clone();
+    let column = |name: &str| -> Result<usize> {
+        headers.iter().position(|h| h == name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Column {} not found in attributes CSV {}",
+                name,
+                csv_file.display()
+            )
+        })
+    };
+    let key = column(&config.key)?;
+    let columns = [
+        column(&config.dx)?,
+        column(&config.n)?,
+        column(&config.ncc)?,
+        column(&config.s0)?,
+        column(&config.bw)?,
+        column(&config.tw)?,
+        column(&config.twcc)?,
+        column(&config.cs)?,
+    ];
+
+    let mut channel_params_map = HashMap::new();
+    for (i, result) in rdr.records().enumerate() {
+        let record = result.with_context(|| format!("Failed to read attributes record {}", i))?;
+
+        let raw_id = record.get(key).unwrap_or_default();
+        let Some(id) = id_parser.parse(raw_id) else {
+            return Err(anyhow::anyhow!("Invalid ID format: {}", raw_id));
+        };
+        if !topology.nodes.contains_key(&id) {
+            continue;
+        }
+
+        let mut values = [0.0f32; 8];
+        for (value, &idx) in values.iter_mut().zip(&columns) {
+            let field = record.get(idx).unwrap_or_default();
+            *value = field.parse::<f32>().with_context(|| {
+                format!(
+                    "Failed to parse '{}' in column {} for {}",
+                    field, &headers[idx], raw_id
+                )
+            })?;
+        }
+        let [dx, n, ncc, s0, bw, tw, twcc, cs] = values;
+
+        channel_params_map.insert(
+            id,
+            ChannelParams {
+                dx,
+                n,
+                ncc,
+                s0,
+                bw,
+                tw,
+                twcc,
+                cs,
+            },
+        );
+    }
+
+    report_loaded_parameters(topology, &channel_params_map);
+
+    Ok(channel_params_map)
+}
+
+// Print how many nodes have parameters
+fn report_loaded_parameters(
+    topology: &NetworkTopology,
+    channel_params_map: &HashMap<u64, ChannelParams>,
+) {
     let loaded = channel_params_map.len();
     let total = topology.routing_order.len();
 
@@ -286,19 +1275,638 @@ pub fn load_channel_parameters(
         "Successfully loaded parameters for {}/{} nodes",
         loaded, total
     );
+}
 
-    if loaded < total {
-        let missing: Vec<_> = topology
-            .routing_order
-            .iter()
-            .filter(|id| !channel_params_map.contains_key(id))
-            .collect();
-        println!(
-            "Warning: Missing parameters for {} nodes: {:?}",
+// Find reaches with bw >= tw and apply the --geometry-check policy, returning their ids
+pub fn check_channel_geometry(
+    channel_params_map: &mut HashMap<u64, ChannelParams>,
+    check: GeometryCheck,
+    clamp_factor: f32,
+) -> Result<Vec<u64>> {
+    let mut degenerate: Vec<u64> = channel_params_map
+        .iter()
+        .filter(|(_, params)| params.bw >= params.tw)
+        .map(|(id, _)| *id)
+        .collect();
+    if degenerate.is_empty() {
+        return Ok(degenerate);
+    }
+    degenerate.sort_unstable();
+    let first = &degenerate[..degenerate.len().min(10)];
+
+    match check {
+        GeometryCheck::Keep => println!(
+            "{} reaches have a bottom width at least their top width and are routed as given (first: {:?})",
+            degenerate.len(),
+            first
+        ),
+        GeometryCheck::Clamp => {
+            for id in &degenerate {
+                if let Some(params) = channel_params_map.get_mut(id) {
+                    params.tw = params.bw * clamp_factor;
+                }
+            }
+            println!(
+                "Widened the top width to {} x the bottom width on {} reaches (first: {:?})",
+                clamp_factor,
+                degenerate.len(),
+                first
+            );
+        }
+        GeometryCheck::Error => {
+            return Err(RouteError::Params {
+                message: format!(
+                    "{} reaches have a bottom width at least their top width (first: {:?}); pass --geometry-check keep or clamp to route them",
+                    degenerate.len(),
+                    first
+                ),
+                reaches: degenerate,
+            }
+            .into());
+        }
+    }
+    Ok(degenerate)
+}
+
+// Muskingum-Cunge needs a positive reach length; reaches passed through unrouted don't
+pub fn check_reach_lengths(
+    channel_params_map: &HashMap<u64, ChannelParams>,
+    passthrough: &HashSet<u64>,
+) -> Result<()> {
+    let mut invalid: Vec<(u64, f32)> = channel_params_map
+        .iter()
+        .filter(|(id, params)| {
+            (params.dx.is_nan() || params.dx <= 0.0) && !passthrough.contains(id)
+        })
+        .map(|(id, params)| (*id, params.dx))
+        .collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+    invalid.sort_unstable_by_key(|(id, _)| *id);
+    let listed: Vec<String> = invalid
+        .iter()
+        .map(|(id, dx)| format!("{} (dx {})", id, dx))
+        .collect();
+    Err(RouteError::Params {
+        reaches: invalid.iter().map(|(id, _)| *id).collect(),
+        message: format!(
+            "{} reaches have a zero, negative or missing length: {:?}; fix dx or pass them through with --passthrough-ids",
+            listed.len(),
+            listed
+        ),
+    }
+    .into())
+}
+
+// Kinematic celerity (m/s) in the main channel at half bankfull depth, as a typical
+// flow for judging how a reach's travel time compares to the timestep
+fn representative_celerity(params: &ChannelParams) -> f32 {
+    let z = if params.cs == 0.0 {
+        1.0
+    } else {
+        1.0 / params.cs
+    };
+    let bankfull = if params.bw >= params.tw {
+        params.bw / (2.0 * z)
+    } else {
+        (params.tw - params.bw) / (2.0 * z)
+    };
+    if params.n <= 0.0 || params.bw <= 0.0 {
+        return 0.0;
+    }
+    let s0 = if params.s0 > 0.0 { params.s0 } else { 0.00001 };
+    celerity(bankfull / 2.0, s0, params.n, params.bw, z)
+}
+
+// Compare each routed reach's travel time at a representative celerity with the internal
+// timestep `dt`. Reaches crossed in under one timestep have their Muskingum K held to
+// it; reaches taking more than 10 are resolved more finely in time than in space. Notes
+// when either is over a tenth of the routed reaches, as a hint for picking the timestep
+// rather than a problem with the run, and returns how many reaches are too short and too
+// long for the timestep.
+pub fn check_timestep_resolution(
+    channel_params_map: &HashMap<u64, ChannelParams>,
+    passthrough: &HashSet<u64>,
+    dt: f32,
+) -> (usize, usize) {
+    let mut routed = 0;
+    let (mut short, mut long) = (0, 0);
+    for (id, params) in channel_params_map {
+        if passthrough.contains(id) {
+            continue;
+        }
+        routed += 1;
+        let ck = representative_celerity(params);
+        // Courant number: the fraction of the reach that flow crosses in one timestep
+        let courant = ck * dt / params.dx;
+        if courant > 1.0 {
+            short += 1;
+        } else if courant < 0.1 {
+            long += 1;
+        }
+    }
+    if routed == 0 {
+        return (short, long);
+    }
+
+    let percent = |count: usize| 100.0 * count as f64 / routed as f64;
+    if short * 10 > routed {
+        console_println!(
+            "{} of {} routed reaches ({:.0}%) are crossed in under one {} s timestep at half-bankfull flow, so their Muskingum K is held to the timestep; a shorter --internal-timestep-seconds resolves them",
+            short,
+            routed,
+            percent(short),
+            dt
+        );
+    }
+    if long * 10 > routed {
+        console_println!(
+            "{} of {} routed reaches ({:.0}%) take over 10 timesteps of {} s to cross at half-bankfull flow; a longer --internal-timestep-seconds would route them at less cost",
+            long,
+            routed,
+            percent(long),
+            dt
+        );
+    }
+    (short, long)
+}
+
+// Warn about reaches with no channel parameters, which are written as fill values
+pub fn check_missing_parameters(
+    topology: &NetworkTopology,
+    channel_params_map: &HashMap<u64, ChannelParams>,
+    strict: bool,
+) -> Result<()> {
+    let missing: Vec<_> = topology
+        .routing_order
+        .iter()
+        .filter(|id| !channel_params_map.contains_key(id))
+        .collect();
+    if !missing.is_empty() {
+        console_warn!(
+            strict,
+            "Missing parameters for {} nodes: {:?}",
             missing.len(),
             missing
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures;
+
+    #[test]
+    fn loads_topology_and_params_from_database() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+
+        let topology = build_network_topology(&conn, &config, Path::new("forcing"), false).unwrap();
+        assert_eq!(topology.nodes.len(), 4);
+        assert_eq!(topology.routing_order.len(), 4);
+
+        // Both headwaters are routed before the confluence, which is routed before the outlet
+        let position = |id: u64| {
+            topology
+                .routing_order
+                .iter()
+                .position(|&n| n == id)
+                .unwrap()
+        };
+        assert!(position(1) < position(3));
+        assert!(position(2) < position(3));
+        assert!(position(3) < position(4));
+
+        let mut upstream = topology.nodes[&3].upstream_ids.clone();
+        upstream.sort_unstable();
+        assert_eq!(upstream, vec![1, 2]);
+        assert_eq!(
+            topology.nodes[&1].qlat_file,
+            Path::new("forcing").join("cat-1.csv")
         );
+
+        let params = load_channel_parameters(&conn, &topology, &config).unwrap();
+        assert_eq!(params.len(), 4);
+        assert_eq!(params[&3].dx, fixtures::default_params().dx);
     }
 
-    Ok(channel_params_map)
+    #[test]
+    fn null_channel_parameters_are_filled_from_the_default_or_left_out() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        conn.execute(
+            &format!(
+                "UPDATE 'flowpath-attributes' SET {} = NULL, {} = NULL WHERE {} = 'wb-2'",
+                config.n, config.tw, config.key
+            ),
+            [],
+        )
+        .unwrap();
+        let topology = build_network_topology(&conn, &config, Path::new("forcing"), false).unwrap();
+
+        let (params, nulls) =
+            read_channel_parameters(&conn, &topology, &config, None, false).unwrap();
+        assert_eq!(nulls, vec![2]);
+        assert_eq!(params.len(), 3);
+        assert!(!params.contains_key(&2));
+        assert!(read_channel_parameters(&conn, &topology, &config, None, true).is_err());
+
+        let default = ChannelParams {
+            dx: 1.0,
+            n: 0.5,
+            tw: 50.0,
+            ..fixtures::default_params()
+        };
+        // Filled reaches have parameters, so --strict doesn't reject them
+        let (params, nulls) =
+            read_channel_parameters(&conn, &topology, &config, Some(&default), true).unwrap();
+        assert_eq!(nulls, vec![2]);
+        assert_eq!((params[&2].n, params[&2].tw), (0.5, 50.0));
+        assert_eq!(params[&2].dx, fixtures::default_params().dx);
+    }
+
+    #[test]
+    fn source_order_follows_the_flowpaths_rows() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE flowpaths (id TEXT, toid TEXT, areasqkm REAL);
+             INSERT INTO flowpaths VALUES ('wb-30', 'wb-10', 1.0), ('wb-20', 'wb-10', 1.0),
+                                          ('wb-10', 'wb-99', 1.0);",
+        )
+        .unwrap();
+        let topology =
+            build_network_topology(&conn, &ColumnConfig::new(), Path::new("forcing"), false)
+                .unwrap();
+
+        assert_eq!(topology.source_order, vec![30, 20, 10]);
+        // Both headwaters come before their confluence, the lower id first
+        assert_eq!(topology.stable_routing_order(), vec![20, 30, 10]);
+    }
+
+    #[test]
+    fn downstream_outside_network_becomes_outlet() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+
+        // wb-4 drains to wb-5, which is not part of the flowpaths table
+        let topology = build_network_topology(&conn, &config, Path::new("forcing"), false).unwrap();
+        assert_eq!(topology.nodes[&4].downstream_id, None);
+        assert_eq!(topology.nodes[&3].downstream_id, Some(4));
+
+        let outlets: Vec<u64> = topology
+            .nodes
+            .values()
+            .filter(|node| node.downstream_id.is_none())
+            .map(|node| node.id)
+            .collect();
+        assert_eq!(outlets, vec![4]);
+    }
+
+    #[test]
+    fn ids_beyond_u32_are_kept_whole() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::empty_network(&config);
+        conn.execute_batch(
+            "INSERT INTO 'flowpaths' (id, toid, areasqkm) VALUES
+                 ('wb-4294967296', 'wb-4294967297', 1.0),
+                 ('wb-4294967297', 'wb-4294967298', 1.0)",
+        )
+        .unwrap();
+
+        // Truncated to u32 these would be reaches 0 and 1
+        let topology = build_network_topology(&conn, &config, Path::new("forcing"), false).unwrap();
+        assert_eq!(topology.routing_order, vec![4_294_967_296, 4_294_967_297]);
+        assert_eq!(
+            topology.nodes[&4_294_967_296].downstream_id,
+            Some(4_294_967_297)
+        );
+        assert_eq!(topology.raw_ids[&4_294_967_297], "wb-4294967297");
+
+        // Beyond the NetCDF's int64 feature_id an id doesn't parse
+        let parser = IdParser::new(&config.id_pattern).unwrap();
+        assert_eq!(
+            parser.parse("wb-9223372036854775807"),
+            Some(i64::MAX as u64)
+        );
+        assert_eq!(parser.parse("wb-9223372036854775808"), None);
+    }
+
+    #[test]
+    fn empty_network_is_reported_before_sorting() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::empty_network(&config);
+        let error = build_network_topology(&conn, &config, Path::new("forcing"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("flowpaths table has no rows"), "{}", error);
+
+        conn.execute_batch(
+            "INSERT INTO 'flowpaths' (id, toid, areasqkm) VALUES ('wb-1', NULL, 1.0)",
+        )
+        .unwrap();
+        let error = build_network_topology(&conn, &config, Path::new("forcing"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("all 1 flowpaths have a null toid"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn conflicting_duplicate_ids_are_rejected() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+
+        // An exact copy of a row is harmless
+        fixtures::insert_flowpath(&conn, &config, "wb-2", "wb-3", 1.5);
+        let topology = build_network_topology(&conn, &config, Path::new("forcing"), false).unwrap();
+        assert_eq!(topology.nodes.len(), 4);
+        assert_eq!(topology.nodes[&2].downstream_id, Some(3));
+
+        fixtures::insert_flowpath(&conn, &config, "wb-1", "wb-4", 2.5);
+        let error = build_network_topology(&conn, &config, Path::new("forcing"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("wb-1 (toid 3, area 2.5) vs wb-1 (toid 4, area 2.5)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn ids_parsing_to_the_same_number_are_rejected() {
+        let config = ColumnConfig::new();
+        let parser = IdParser::new(&config.id_pattern).unwrap();
+        assert_eq!(parser.parse("wb-12"), Some(12));
+        assert_eq!(parser.parse("cat-0012"), Some(12));
+        assert_eq!(parser.parse("wb-0"), Some(0));
+        assert_eq!(parser.parse("wb-12a"), None);
+        assert_eq!(parser.parse("wb-12-1"), None);
+
+        // A suffixed id no longer parses as its prefix
+        let conn = fixtures::synthetic_network(&config);
+        fixtures::insert_flowpath(&conn, &config, "wb-2a", "wb-3", 1.5);
+        let error = build_network_topology(&conn, &config, Path::new("forcing"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("wb-2a -> wb-3"), "{}", error);
+
+        // Zero padding still maps both ids to reach 2, so the repeat is a conflict
+        let conn = fixtures::synthetic_network(&config);
+        fixtures::insert_flowpath(&conn, &config, "wb-002", "wb-3", 1.5);
+        let error = build_network_topology(&conn, &config, Path::new("forcing"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("wb-2 (toid 3, area 1.5) vs wb-002 (toid 3, area 1.5)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn upstream_lists_build_the_same_network() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let expected = build_network_topology(&conn, &config, Path::new("forcing"), false).unwrap();
+
+        // The synthetic network listed by upstream reach; 5 lies outside the domain
+        let reaches = vec![
+            (4, vec![3], 3.0),
+            (3, vec![1, 2], 4.0),
+            (1, vec![], 2.5),
+            (2, vec![5], 1.5),
+        ];
+        let topology =
+            build_network_topology_from_upstream(&reaches, Path::new("forcing"), false).unwrap();
+        for (id, node) in &expected.nodes {
+            let mut upstream = topology.nodes[id].upstream_ids.clone();
+            let mut expected_upstream = node.upstream_ids.clone();
+            upstream.sort_unstable();
+            expected_upstream.sort_unstable();
+            assert_eq!(topology.nodes[id].downstream_id, node.downstream_id);
+            assert_eq!(upstream, expected_upstream);
+            assert_eq!(topology.nodes[id].area_sqkm, node.area_sqkm);
+            assert_eq!(topology.nodes[id].qlat_file, node.qlat_file);
+        }
+        assert_eq!(topology.nodes.len(), expected.nodes.len());
+        assert_eq!(topology.routing_order.last(), Some(&4));
+    }
+
+    #[test]
+    fn braided_upstream_lists_are_rejected() {
+        // 1 splits into 2 and 3, which rejoin at 4
+        let reaches = vec![
+            (1, vec![], 1.0),
+            (2, vec![1], 1.0),
+            (3, vec![1], 1.0),
+            (4, vec![2, 3], 1.0),
+        ];
+        let error = build_network_topology_from_upstream(&reaches, Path::new("forcing"), false)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("[(1, [2, 3])]"), "{}", error);
+
+        let repeated = vec![(1, vec![], 1.0), (1, vec![], 1.0)];
+        assert!(
+            build_network_topology_from_upstream(&repeated, Path::new("forcing"), false).is_err()
+        );
+    }
+
+    #[test]
+    fn cross_check_reports_ids_missing_from_either_table() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        assert_eq!(
+            cross_check_ids(&conn, &config).unwrap(),
+            (Vec::new(), Vec::new())
+        );
+
+        fixtures::insert_flowpath(&conn, &config, "wb-6", "wb-4", 1.0);
+        fixtures::insert_attributes(&conn, &config, "wb-7", &fixtures::default_params());
+        let (missing_attributes, missing_flowpaths) = cross_check_ids(&conn, &config).unwrap();
+        assert_eq!(missing_attributes, vec!["wb-6"]);
+        assert_eq!(missing_flowpaths, vec!["wb-7"]);
+    }
+
+    #[test]
+    fn degenerate_geometry_is_kept_clamped_or_rejected() {
+        let wide = ChannelParams {
+            bw: 12.0,
+            tw: 10.0,
+            ..fixtures::default_params()
+        };
+        let params = HashMap::from([(1, fixtures::default_params()), (2, wide)]);
+
+        let mut kept = params.clone();
+        assert_eq!(
+            check_channel_geometry(&mut kept, GeometryCheck::Keep, 1.5).unwrap(),
+            vec![2]
+        );
+        assert_eq!(kept[&2].tw, 10.0);
+
+        let mut clamped = params.clone();
+        check_channel_geometry(&mut clamped, GeometryCheck::Clamp, 1.5).unwrap();
+        assert_eq!(clamped[&2].tw, 18.0);
+        assert_eq!(clamped[&1].tw, fixtures::default_params().tw);
+
+        let mut rejected = params;
+        assert!(check_channel_geometry(&mut rejected, GeometryCheck::Error, 1.5).is_err());
+    }
+
+    #[test]
+    fn timestep_resolution_counts_short_and_long_reaches() {
+        // The default channel carries half-bankfull flow at about 0.5 m/s
+        let reach = |dx: f32| ChannelParams {
+            dx,
+            ..fixtures::default_params()
+        };
+        let params = HashMap::from([
+            (1, reach(100.0)),
+            (2, reach(1000.0)),
+            (3, reach(10_000.0)),
+            (4, reach(50.0)),
+        ]);
+        assert_eq!(
+            check_timestep_resolution(&params, &HashSet::new(), 300.0),
+            (2, 1)
+        );
+        assert_eq!(
+            check_timestep_resolution(&params, &HashSet::from([1, 4]), 300.0),
+            (0, 1)
+        );
+        assert_eq!(
+            check_timestep_resolution(&params, &HashSet::new(), 3600.0),
+            (3, 0)
+        );
+    }
+
+    #[test]
+    fn zero_length_reaches_are_rejected_unless_passed_through() {
+        let zero = ChannelParams {
+            dx: 0.0,
+            ..fixtures::default_params()
+        };
+        let params = HashMap::from([(1, fixtures::default_params()), (2, zero)]);
+
+        let error = check_reach_lengths(&params, &HashSet::new())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("[\"2 (dx 0)\"]"), "{}", error);
+        assert!(check_reach_lengths(&params, &HashSet::from([2])).is_ok());
+    }
+
+    #[test]
+    fn schema_check_reports_missing_columns() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::empty_network(&config);
+        assert!(check_schema(&conn, &config).unwrap().is_empty());
+
+        let mut renamed = ColumnConfig::new();
+        renamed.dx = "length".to_string();
+        let problems = check_schema(&conn, &renamed).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("'flowpath-attributes' has no column 'length'"));
+
+        conn.execute_batch("DROP TABLE 'flowpaths'").unwrap();
+        let problems = check_schema(&conn, &config).unwrap();
+        assert_eq!(problems, vec!["table 'flowpaths' not found"]);
+    }
+
+    #[test]
+    fn schema_check_parses_ids_with_the_configured_pattern() {
+        let mut config = ColumnConfig::new();
+        let conn = fixtures::empty_network(&config);
+        fixtures::insert_flowpath(&conn, &config, "wb-1", "wb-2", 1.0);
+        fixtures::insert_flowpath(&conn, &config, "wb-2x", "wb-3", 1.0);
+        let problems = check_schema(&conn, &config).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(
+            problems[0].starts_with("1 ids in flowpaths.id"),
+            "{}",
+            problems[0]
+        );
+        assert!(problems[0].contains("'wb-2x'"));
+
+        config.id_pattern = r"^wb-(\d+)".to_string();
+        assert!(check_schema(&conn, &config).unwrap().is_empty());
+
+        config.id_pattern = "wb-".to_string();
+        assert!(check_schema(&conn, &config).is_err());
+    }
+
+    #[test]
+    fn headwaters_without_forcing_are_dry() {
+        let forcing = fixtures::write_forcing("dry_reaches", 1, &[1.0, 2.0]);
+        let header_only = forcing.with_file_name("cat-2.csv");
+        std::fs::write(&header_only, "Time,ID,Q_OUT\n").unwrap();
+
+        // 1 (forced), 2 (header only), 3 (no file) -> 4 (no file, but fed from upstream)
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, Some(4), Some(1.0), forcing.clone());
+        topology.add_node(2, Some(4), Some(1.0), header_only);
+        topology.add_node(3, Some(4), Some(1.0), forcing.with_file_name("cat-3.csv"));
+        topology.add_node(4, None, Some(1.0), forcing.with_file_name("cat-4.csv"));
+        topology.build_upstream_connections();
+
+        assert_eq!(find_dry_reaches(&topology), vec![2, 3]);
+    }
+
+    #[test]
+    fn preloaded_forcing_is_held_as_lateral_inflow() {
+        let forcing = fixtures::write_forcing("preload_forcing", 1, &[3.6, 7.2]);
+        fixtures::write_forcing("preload_forcing", 2, &[1.8]);
+
+        // 1 and 2 are read from their files, 3 has none and 4 already has its forcing
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, Some(3), Some(1.0), forcing.clone());
+        topology.add_node(2, Some(3), Some(2.0), forcing.with_file_name("cat-2.csv"));
+        topology.add_node(3, Some(4), Some(1.0), forcing.with_file_name("cat-3.csv"));
+        topology.add_node(4, None, Some(1.0), forcing.with_file_name("cat-4.csv"));
+        topology.nodes.get_mut(&4).unwrap().lateral_inflow = Some(vec![9.0]);
+
+        preload_forcing(&mut topology, 2).unwrap();
+        let inflow = |id: u64| topology.nodes[&id].lateral_inflow.clone();
+        assert_eq!(inflow(1), Some(vec![1000.0, 2000.0]));
+        assert_eq!(inflow(2), Some(vec![1000.0]));
+        assert_eq!(inflow(3), Some(Vec::new()));
+        assert_eq!(inflow(4), Some(vec![9.0]));
+    }
+
+    #[test]
+    fn forcing_coverage_matches_file_names_to_reaches() {
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let dir = std::env::temp_dir().join("route_rs_forcing_coverage");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // cat-0003.csv is reach 3 by id pattern, but routing reads cat-3.csv
+        for name in [
+            "cat-1.csv",
+            "cat-2.csv",
+            "cat-0003.csv",
+            "cat-9.csv",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.join(name), "Time,ID,Q_OUT\n").unwrap();
+        }
+
+        let topology = build_network_topology(&conn, &config, &dir, false).unwrap();
+        let coverage = forcing_coverage(&topology, &dir).unwrap();
+        assert_eq!(
+            coverage,
+            ForcingCoverage {
+                covered: 2,
+                missing: vec![3, 4],
+                unmatched: vec!["cat-0003.csv".to_string(), "cat-9.csv".to_string()],
+            }
+        );
+        assert_eq!(coverage.percent(), 50.0);
+    }
 }
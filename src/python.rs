@@ -0,0 +1,125 @@
+use crate::config::{ChannelParams, SolverConfig};
+use crate::mc_kernel;
+use crate::routing::{self, RoutingOptions};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+// Python bindings, built as the `route_rs` extension module. Series are passed in as
+// any float sequence, numpy arrays included, and returned as numpy arrays.
+
+/// Route one timestep of one reach with Muskingum-Cunge. Same arguments as the
+/// t-route kernel; returns (qdc, velc, depthc).
+#[pyfunction]
+#[allow(clippy::too_many_arguments)]
+fn submuskingcunge(
+    qup: f32,
+    quc: f32,
+    qdp: f32,
+    ql: f32,
+    dt: f32,
+    so: f32,
+    dx: f32,
+    n: f32,
+    cs: f32,
+    bw: f32,
+    tw: f32,
+    tw_cc: f32,
+    n_cc: f32,
+    depth_p: f32,
+) -> PyResult<(f32, f32, f32)> {
+    if n <= 0.0 || so <= 0.0 || bw <= 0.0 || dx.is_nan() || dx <= 0.0 || cs < 0.0 {
+        return Err(PyValueError::new_err(
+            "n, so, bw and dx must be positive and cs non-negative",
+        ));
+    }
+    let output = mc_kernel::submuskingcunge(
+        qup,
+        quc,
+        qdp,
+        ql,
+        dt,
+        so,
+        dx,
+        n,
+        cs,
+        bw,
+        tw,
+        tw_cc,
+        n_cc,
+        depth_p,
+        &SolverConfig::default(),
+    )
+    .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok((output.qdc, output.velc, output.depthc))
+}
+
+/// Outflow, velocity and depth of a routed reach at each timestep as float32 numpy
+/// arrays, and the timesteps at which the kernel didn't converge as an int64 array
+#[pyclass(get_all, frozen)]
+struct ReachResult {
+    flow: Py<PyAny>,
+    velocity: Py<PyAny>,
+    depth: Py<PyAny>,
+    nonconverged: Py<PyAny>,
+}
+
+/// Route a reach over a series of upstream inflow (m3/s per timestep of `dt`
+/// seconds), with optional lateral inflow (m3/s per hour of forcing, held over the
+/// hour's timesteps). The reach is routed as it would be in a network. `params` holds
+/// the channel parameters dx, n, ncc, s0, bw, tw, twcc and cs.
+#[pyfunction]
+#[pyo3(signature = (params, inflow, dt, lateral = None))]
+fn route_reach(
+    py: Python<'_>,
+    params: HashMap<String, f32>,
+    inflow: Vec<f32>,
+    dt: f32,
+    lateral: Option<Vec<f32>>,
+) -> PyResult<ReachResult> {
+    let values = params
+        .iter()
+        .map(|(key, value)| (key.as_str(), *value))
+        .collect();
+    let channel_params = ChannelParams::from_values(&values).map_err(PyValueError::new_err)?;
+    let ChannelParams { n, s0, bw, cs, .. } = channel_params;
+    if n <= 0.0 || bw <= 0.0 || s0 < 0.0 || cs < 0.0 {
+        return Err(PyValueError::new_err(
+            "n and bw must be positive and s0 and cs non-negative",
+        ));
+    }
+    if dt <= 0.0 {
+        return Err(PyValueError::new_err("dt must be positive"));
+    }
+
+    let results = routing::route_reach(
+        &channel_params,
+        &inflow,
+        lateral.as_deref().unwrap_or_default(),
+        dt,
+        &RoutingOptions::default(),
+    )
+    .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+
+    // Built with numpy.asarray, so the numpy crate isn't needed to return arrays
+    let numpy = py.import("numpy")?;
+    let array = |values: Vec<f32>| -> PyResult<Py<PyAny>> {
+        Ok(numpy.call_method1("asarray", (values, "float32"))?.unbind())
+    };
+    Ok(ReachResult {
+        flow: array(results.flow_data)?,
+        velocity: array(results.velocity_data)?,
+        depth: array(results.depth_data)?,
+        nonconverged: numpy
+            .call_method1("asarray", (results.nonconverged, "int64"))?
+            .unbind(),
+    })
+}
+
+#[pymodule]
+fn route_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(submuskingcunge, m)?)?;
+    m.add_function(wrap_pyfunction!(route_reach, m)?)?;
+    m.add_class::<ReachResult>()?;
+    Ok(())
+}
@@ -1,42 +1,137 @@
-use crate::config::ChannelParams;
-use crate::io::csv::load_external_flows;
-use crate::io::netcdf::write_output;
+use crate::config::{
+    ChannelParams, Confluence, EXTERNAL_TIMESTEP_SECONDS, OutputVariable, SolverConfig,
+};
+use crate::console;
+use crate::error::RouteError;
+use crate::interrupt;
+use crate::io::aggregate::GroupAggregator;
+use crate::io::cache::ResultsCache;
+use crate::io::csv::{load_external_flows, runoff_to_flow};
+use crate::io::forcing_window::ForcingWindow;
+use crate::io::netcdf::{NetCdfOutput, write_fill, write_output};
+use crate::io::nudge::Nudging;
 use crate::io::results::SimulationResults;
-use crate::mc_kernel;
-use crate::network::NetworkTopology;
+use crate::io::roughness::Roughness;
+use crate::io::stats::{OutputStatistics, PeakFlows};
+use crate::io::timings::TimingReport;
+use crate::io::velocity::{FlaggedStep, VelocityCheck};
+use crate::kw_kernel::{KinematicWave, Passthrough};
+use crate::mc_kernel::{KernelOutput, MuskingumCunge, RoutingKernel};
+use crate::network::{NetworkTopology, no_area};
 use crate::state::NodeStatus;
+use crate::{console_eprintln, console_println, console_warn};
 use anyhow::{Context, Result};
+use csv::Writer;
 use indicatif::ProgressBar;
-use netcdf::FileMut;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+// Options controlling how each reach is routed
+#[derive(Debug, Clone, Default)]
+pub struct RoutingOptions {
+    pub solver: SolverConfig,
+    // Per-node results are also written here so output can be regenerated with --replay
+    pub results_cache: Option<ResultsCache>,
+    // Blend flow toward observations at gauged reaches
+    pub nudging: Option<Nudging>,
+    // Manning's n that varies over the run, overriding `ChannelParams` where given
+    pub roughness: Option<Roughness>,
+    // Variables that aren't output, so needn't be kept in `SimulationResults`.
+    // Flow is always kept since it feeds the downstream reach.
+    pub discarded_variables: Vec<OutputVariable>,
+    // Resuming into an existing output: reaches to skip entirely, and reaches that
+    // are routed (to feed downstream) but whose results are already written
+    pub skip_routing: HashSet<u64>,
+    pub already_written: HashSet<u64>,
+    // Reaches whose outflow is their inflow, with no attenuation
+    pub passthrough: HashSet<u64>,
+    // Count and report timesteps where a reach's outflow is negative
+    pub check_nonnegative: bool,
+    // Outflow is raised to at least this after the kernel (and nudging), and carried on
+    pub min_flow: Option<f32>,
+    // Routing stops at the first reach whose outflow exceeds this or isn't finite
+    pub abort_above_flow: Option<f32>,
+    // How upstream outflow is combined into each reach's inflow
+    pub confluence: Confluence,
+    // Collect timesteps where a reach's velocity exceeds a plausibility threshold
+    pub velocity_check: Option<VelocityCheck>,
+    // Collect each reach's peak written flow
+    pub peak_flows: Option<PeakFlows>,
+    // The first forcing value is a t=0 state rather than the first interval's flux, so drop it
+    pub skip_first_forcing: bool,
+    // Times the forcing record is routed before the output cycle, carrying state across
+    pub spinup_cycles: usize,
+    // Forcing steps routed after the forcing ends, with zero lateral inflow
+    pub recession_steps: usize,
+    // Single-file forcing read as reaches are dispatched rather than held in `lateral_inflow`
+    pub forcing_window: Option<Arc<ForcingWindow>>,
+    // Route each independent basin whole on one worker instead of scheduling reach by reach
+    pub by_component: bool,
+    // Keep the kernel's X and celerity at each timestep in `SimulationResults`
+    pub record_muskingum: bool,
+    // Integrate outflow into `SimulationResults::cumulative_volume_data`
+    pub record_cumulative_volume: bool,
+    // Extra attempts, with doubling backoff, before a reach's output write is given up on
+    pub write_retries: u32,
+    // Raise warnings as errors, and stop the run at the first reach that fails (--strict)
+    pub strict: bool,
+    // Secant iterations a reach may spend over the whole run, spin-up included; once spent,
+    // its remaining timesteps are routed at normal flow and count as nonconverged
+    pub max_reach_iterations: Option<u64>,
+    // Applied to each reach's results before they are written (and cached). Downstream
+    // reaches are still fed the untransformed flow.
+    pub result_transform: Option<ResultTransform>,
+}
+
+// Post-processing for library users, e.g. bias correction or unit conversion of the
+// written series, run on a worker thread for every routed reach
+#[derive(Clone)]
+pub struct ResultTransform(pub Arc<dyn Fn(&mut SimulationResults) + Send + Sync>);
+
+impl ResultTransform {
+    pub fn new(transform: impl Fn(&mut SimulationResults) + Send + Sync + 'static) -> Self {
+        ResultTransform(Arc::new(transform))
+    }
+}
+
+impl std::fmt::Debug for ResultTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ResultTransform")
+    }
+}
 
 // Message types
 enum WriterMessage {
     WriteResults(Arc<SimulationResults>),
+    // Node was skipped or failed; keep its output slot filled
+    WriteFill(u64),
     Shutdown,
 }
 
 enum WorkerMessage {
-    ProcessNode(u32),
+    ProcessNode(u64),
     Shutdown,
 }
 
 enum SchedulerMessage {
-    NodeCompleted(u32),
+    NodeCompleted(u64),
     Shutdown,
 }
 
 // Process all timesteps for a single node (unchanged)
-fn process_node_all_timesteps(
-    node_id: &u32,
+pub fn process_node_all_timesteps(
+    node_id: &u64,
     topology: &NetworkTopology,
     channel_params: &ChannelParams,
     max_timesteps: usize,
     dt: f32,
+    options: &RoutingOptions,
 ) -> Result<SimulationResults> {
     let node = topology
         .nodes
@@ -45,125 +140,550 @@ fn process_node_all_timesteps(
 
     let mut results = SimulationResults::new(node.id as i64);
 
-    let area = node
-        .area_sqkm
-        .ok_or_else(|| anyhow::anyhow!("Node {} has no area defined", node_id))?;
+    let mut external_flows = match &node.lateral_inflow {
+        Some(flows) => flows.iter().copied().collect(),
+        None => {
+            let area = node.area_sqkm.ok_or_else(|| no_area(node.id))?;
+            match &options.forcing_window {
+                Some(window) if node.windowed_forcing => window
+                    .take(node.id)?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|ql| runoff_to_flow(ql * node.lateral_weight, area))
+                    .collect(),
+                _ => load_external_flows(
+                    node.qlat_file.clone(),
+                    &node.id,
+                    area,
+                    node.lateral_weight,
+                )?,
+            }
+        }
+    };
 
-    let mut external_flows =
-        load_external_flows(node.qlat_file.clone(), &node.id, Some(&"Q_OUT"), area)?;
+    if options.skip_first_forcing {
+        external_flows.pop_front();
+    }
+
+    // Each forcing value is held for `upsampling` internal steps
+    let upsampling = ((EXTERNAL_TIMESTEP_SECONDS as f32 / dt) as usize).max(1);
+    let forcing_steps = max_timesteps.div_ceil(upsampling);
+    // The recession after the forcing ends has no lateral inflow by design
+    let forced_steps = forcing_steps.saturating_sub(options.recession_steps);
+
+    // A forcing record of the wrong length is padded with zeros or truncated to the run,
+    // and reported once for all reaches when routing finishes
+    if !external_flows.is_empty() && external_flows.len() != forced_steps {
+        results.forcing_mismatch = Some((external_flows.len(), forced_steps));
+        external_flows.resize(forced_steps, 0.0);
+    }
+    // No forcing file: route upstream inflow with zero lateral inflow at every timestep
+    external_flows.resize(forcing_steps, 0.0);
 
     let s0 = if channel_params.s0 == 0.0 {
+        // Too many in most hydrofabrics to warn about, but strict runs want to know
+        if options.strict {
+            return Err(anyhow::anyhow!(
+                "Node {} has zero slope, which is otherwise floored to 0.00001 (--strict)",
+                node_id
+            ));
+        }
         0.00001
     } else {
         channel_params.s0
     };
 
-    let mut inflow = node
-        .inflow_storage
-        .lock()
-        .map_err(|e| anyhow::anyhow!("Failed to lock inflow storage: {}", e))?;
+    // Headwaters never receive upstream inflow, so skip locking their buffer entirely
+    let mut inflow = if node.upstream_ids.is_empty() {
+        None
+    } else {
+        Some(
+            node.inflow_storage
+                .lock()
+                .map_err(|e| anyhow::anyhow!("Failed to lock inflow storage: {}", e))?,
+        )
+    };
+
+    // Waterbodies, junctions and passthrough reaches all hand their inflow straight on
+    let kernel: &dyn RoutingKernel = match options.solver.kinematic_above_slope {
+        _ if !node.reach_type.is_routed() || options.passthrough.contains(node_id) => &Passthrough,
+        Some(threshold) if s0 > threshold => &KinematicWave,
+        _ => &MuskingumCunge,
+    };
+
+    let roughness = options
+        .roughness
+        .as_ref()
+        .and_then(|roughness| roughness.for_reach(node.id));
+
+    let mut nudger = options
+        .nudging
+        .as_ref()
+        .and_then(|nudging| nudging.nudger(node.id));
+
+    let discard_velocity = options
+        .discarded_variables
+        .contains(&OutputVariable::Velocity);
+    let discard_depth = options.discarded_variables.contains(&OutputVariable::Depth);
 
     let mut qup = 0.0;
     let mut qdp = 0.0;
     let mut depth_p = 0.0;
 
-    let upsampling = max_timesteps / external_flows.len();
-
     let mut external_flow = 0.0;
-    let mut upstream_flow = 0.0;
+    let mut spent_iterations: u64 = 0;
+    // Outflow volume so far, integrated with the trapezoidal rule from the outflow the
+    // output cycle starts with
+    let mut volume = 0.0_f64;
+    // Timesteps with negative outflow, and the most negative value, for --check-nonnegative
+    let mut negative_steps = 0;
+    let mut min_flow = 0.0_f32;
+    // Timesteps faster than --flag-velocity-above
+    let mut fast_steps = Vec::new();
 
-    for _timestep in 0..max_timesteps {
-        if _timestep % upsampling == 0 {
-            external_flow = external_flows.pop_front().unwrap();
-        }
-        upstream_flow = inflow.pop_front().unwrap_or(0.0);
-
-        let (qdc, velc, depthc, _, _, _) = mc_kernel::submuskingcunge(
-            qup,
-            upstream_flow,
-            qdp,
-            external_flow,
+    // Flow raised to --min-flow takes the in-channel normal depth and velocity of the
+    // raised flow, so the three stay consistent
+    let at_floor = |floor: f32, n: f32, ncc: f32| {
+        Passthrough.route(
+            0.0,
+            floor,
+            0.0,
+            0.0,
             dt,
             s0,
             channel_params.dx,
-            channel_params.n,
+            n,
             channel_params.cs,
             channel_params.bw,
             channel_params.tw,
             channel_params.twcc,
-            channel_params.ncc,
-            depth_p,
-        );
+            ncc,
+            0.0,
+            &options.solver,
+        )
+    };
+
+    // Spin-up cycles route the whole forcing record first, carrying state into the next
+    // cycle. Their outflow feeds the downstream reach but isn't output.
+    let spinup_steps = options.spinup_cycles * max_timesteps;
+    for step in 0..spinup_steps + max_timesteps {
+        let timestep = step % max_timesteps;
+        let spinup = step < spinup_steps;
+        if timestep.is_multiple_of(upsampling) {
+            external_flow = external_flows[timestep / upsampling];
+        }
+        let upstream_flow = inflow
+            .as_mut()
+            .and_then(|buffer| buffer.pop_front())
+            .map_or(0.0, |flow| flow as f32);
+        let (n, ncc) = match &roughness {
+            Some(roughness) => {
+                roughness.at(timestep / upsampling, channel_params.n, channel_params.ncc)
+            }
+            None => (channel_params.n, channel_params.ncc),
+        };
+
+        // Past the iteration budget, skip the secant solve and take normal flow
+        let capped = options
+            .max_reach_iterations
+            .is_some_and(|budget| spent_iterations >= budget);
+        if capped && !results.iteration_capped {
+            results.iteration_capped = true;
+            console_warn!(
+                options.strict,
+                "node {} spent its {} iteration budget by step {} of {}; routing the rest at normal flow",
+                node_id,
+                spent_iterations,
+                step,
+                spinup_steps + max_timesteps
+            )?;
+        }
+        let step_kernel = if capped { &Passthrough } else { kernel };
+
+        let KernelOutput {
+            qdc,
+            velc,
+            depthc,
+            converged,
+            iterations,
+            travel_time,
+            x,
+            ck,
+            ..
+        } = step_kernel
+            .route(
+                qup,
+                upstream_flow,
+                qdp,
+                external_flow,
+                dt,
+                s0,
+                channel_params.dx,
+                n,
+                channel_params.cs,
+                channel_params.bw,
+                channel_params.tw,
+                channel_params.twcc,
+                ncc,
+                depth_p,
+                &options.solver,
+            )
+            .map_err(|error| RouteError::Kernel {
+                reaches: vec![node.id],
+                message: format!("node {}: {}", node_id, error),
+            })?;
+        spent_iterations += iterations as u64;
+        if spinup {
+            let (qdc, depthc) = match options.min_flow {
+                Some(floor) if qdc < floor => (floor, at_floor(floor, n, ncc)?.depthc),
+                _ => (qdc, depthc),
+            };
+            results.spinup_flow.push(qdc);
+            qup = upstream_flow;
+            qdp = qdc;
+            depth_p = depthc;
+            continue;
+        }
+
+        if !converged || capped {
+            results.nonconverged.push(timestep);
+        }
+        results.iterations += iterations as u64;
+        results.max_iterations = results.max_iterations.max(iterations);
+        if travel_time > 0.0 {
+            results.travel_time += travel_time as f64;
+            results.flowing_steps += 1;
+        }
+
+        // The nudged flow is carried forward, so the correction propagates downstream
+        let qdc = match nudger.as_mut() {
+            Some(nudger) => {
+                let (nudged, nudge) = nudger.apply(timestep / upsampling, qdc, dt);
+                results.nudge_data.push(nudge);
+                nudged
+            }
+            None => qdc,
+        };
+
+        if options.check_nonnegative && qdc < 0.0 {
+            negative_steps += 1;
+            min_flow = min_flow.min(qdc);
+        }
+        let (qdc, velc, depthc) = match options.min_flow {
+            Some(floor) if qdc < floor => {
+                results.floored_steps += 1;
+                let normal = at_floor(floor, n, ncc)?;
+                (floor, normal.velc, normal.depthc)
+            }
+            _ => (qdc, velc, depthc),
+        };
+        if let Some(check) = &options.velocity_check
+            && velc > check.threshold
+        {
+            fast_steps.push(FlaggedStep {
+                feature_id: node.id,
+                timestep,
+                velocity: velc,
+                s0: channel_params.s0,
+                dx: channel_params.dx,
+            });
+        }
 
         results.flow_data.push(qdc);
-        results.velocity_data.push(velc);
-        results.depth_data.push(depthc);
+        if !discard_velocity {
+            results.velocity_data.push(velc);
+        }
+        if !discard_depth {
+            results.depth_data.push(depthc);
+        }
+        if options.record_muskingum {
+            results.musk_x_data.push(x);
+            results.celerity_data.push(ck);
+        }
+        if options.record_cumulative_volume {
+            volume += (qdp as f64 + qdc as f64) / 2.0 * dt as f64;
+            results.cumulative_volume_data.push(volume as f32);
+        }
 
         qup = upstream_flow;
         qdp = qdc;
         depth_p = depthc;
     }
 
+    if !results.nonconverged.is_empty() {
+        console_warn!(
+            options.strict,
+            "node {} did not converge at {} of {} timesteps (fallback: {:?})",
+            node_id,
+            results.nonconverged.len(),
+            max_timesteps,
+            options.solver.nonconvergence_fallback
+        )?;
+    }
+
+    if negative_steps > 0 {
+        console_warn!(
+            options.strict,
+            "node {} has negative outflow at {} of {} timesteps (minimum {})",
+            node_id,
+            negative_steps,
+            max_timesteps,
+            min_flow
+        )?;
+    }
+    if let Some(check) = &options.velocity_check {
+        check.record(fast_steps)?;
+    }
+
     Ok(results)
 }
 
-// Writer thread function (unchanged)
+// Route one reach outside of a network, exactly as it would be routed inside one:
+// `inflow` is the upstream flow at each timestep and `lateral` the lateral inflow for
+// each forcing step (m3/s), the shorter padded with zeros. Used by the Python binding.
+pub fn route_reach(
+    channel_params: &ChannelParams,
+    inflow: &[f32],
+    lateral: &[f32],
+    dt: f32,
+    options: &RoutingOptions,
+) -> Result<SimulationResults, RouteError> {
+    let mut topology = NetworkTopology::new();
+    topology.add_node(0, None, None, PathBuf::new());
+    let node = topology
+        .nodes
+        .get_mut(&0)
+        .ok_or_else(|| anyhow::anyhow!("Node 0 not found"))?;
+    node.lateral_inflow = Some(lateral.to_vec());
+    // An upstream id, although there's no such reach, so the inflow buffer is read
+    if !inflow.is_empty() {
+        node.upstream_ids.push(u64::MAX);
+        node.inflow_storage
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock inflow storage: {}", e))?
+            .extend(inflow.iter().map(|&flow| flow as f64));
+    }
+
+    let upsampling = ((EXTERNAL_TIMESTEP_SECONDS as f32 / dt) as usize).max(1);
+    let max_timesteps = inflow.len().max(lateral.len() * upsampling);
+    Ok(process_node_all_timesteps(
+        &0,
+        &topology,
+        channel_params,
+        max_timesteps,
+        dt,
+        options,
+    )?)
+}
+
+// Writer thread function: the only place results are written, to the NetCDF and to the
+// CSV when there is one. Returns the features whose output couldn't be written even
+// after retrying
 fn writer_thread(
     receiver: Receiver<WriterMessage>,
-    output_file: Arc<Mutex<FileMut>>,
-) -> Result<()> {
+    output_file: Arc<Mutex<NetCdfOutput>>,
+    csv_writer: Option<Writer<File>>,
+    mut aggregator: Option<GroupAggregator>,
+    mut statistics: Option<OutputStatistics>,
+    mut timings: Option<TimingReport>,
+    write_retries: u32,
+) -> Result<Vec<u64>> {
+    let mut failed_writes = Vec::new();
     loop {
         match receiver.recv() {
             Ok(WriterMessage::WriteResults(results)) => {
-                if let Err(e) = write_output(&output_file, &results) {
-                    eprintln!(
-                        "Error writing results for node {}: {}",
-                        results.feature_id, e
-                    );
+                let feature_id = results.feature_id as u64;
+                if let Err(e) = with_retries(write_retries, feature_id, || {
+                    write_output(&output_file, &results)
+                }) {
+                    console_eprintln!("Error writing results for node {}: {}", feature_id, e);
+                    failed_writes.push(feature_id);
+                    let _ = write_fill(&output_file, feature_id);
+                }
+                if let Some(aggregator) = aggregator.as_mut() {
+                    aggregator.add(&results);
+                }
+                if let Some(statistics) = statistics.as_mut() {
+                    statistics.add(&results);
+                }
+                if let Some(timings) = timings.as_mut() {
+                    timings.add(&results);
+                }
+            }
+            Ok(WriterMessage::WriteFill(feature_id)) => {
+                if let Err(e) = with_retries(write_retries, feature_id, || {
+                    write_fill(&output_file, feature_id)
+                }) {
+                    console_eprintln!("Error writing fill values for node {}: {}", feature_id, e);
+                    failed_writes.push(feature_id);
                 }
             }
             Ok(WriterMessage::Shutdown) => break,
             Err(e) => {
-                eprintln!("Writer thread channel error: {}", e);
+                console_eprintln!("Writer thread channel error: {}", e);
                 break;
             }
         }
     }
+
+    // Flush what was written while this thread still holds the outputs open. The loop
+    // above only ends once every sender is gone, so this runs on a clean finish and when
+    // routing stopped early alike.
+    if let Some(mut csv_writer) = csv_writer {
+        csv_writer.flush().context("Failed to flush CSV writer")?;
+    }
+    output_file
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Failed to acquire NetCDF file lock: {}", e))?
+        .sync()?;
+
+    if let Some(aggregator) = aggregator {
+        aggregator.finish()?;
+    }
+    if let Some(statistics) = statistics {
+        statistics.finish()?;
+    }
+    if let Some(timings) = timings {
+        timings.finish()?;
+    }
+    Ok(failed_writes)
+}
+
+// First wait before retrying a failed write; it doubles with each further attempt
+const WRITE_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// Retry a write that may fail transiently, as on networked filesystems. Writes fill
+// fixed slots in the output, so repeating a partially applied one is harmless.
+fn with_retries(
+    retries: u32,
+    feature_id: u64,
+    mut write: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retries => {
+                let backoff = WRITE_RETRY_BACKOFF * 2u32.pow(attempt.min(6));
+                console_eprintln!(
+                    "Writing node {} failed ({}); retrying in {:?}",
+                    feature_id,
+                    e,
+                    backoff
+                );
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Surface features the writer gave up on; their slots hold fill values at best
+fn report_failed_writes(failed_writes: &[u64], total_nodes: usize, strict: bool) -> Result<()> {
+    if !failed_writes.is_empty() {
+        console_warn!(
+            strict,
+            "{} of {} nodes could not be written (first: {:?})",
+            failed_writes.len(),
+            total_nodes,
+            &failed_writes[..failed_writes.len().min(10)]
+        )?;
+    }
     Ok(())
 }
 
+// Wave-front dependency tracking: a reach is ready once every upstream reach is done
+struct Scheduler {
+    ready: VecDeque<u64>,
+    // Upstream reaches still to complete, for reaches not yet ready
+    pending_upstream: HashMap<u64, usize>,
+    remaining: usize,
+}
+
+impl Scheduler {
+    // Start from every headwater
+    #[cfg(test)]
+    fn new(topology: &NetworkTopology) -> Self {
+        Self::from_checkpoint(topology, &HashSet::new())
+    }
+
+    // Resume with the `completed` reaches already done: they're never dispatched, and a
+    // reach is ready as soon as its upstream reaches outside `completed` are done
+    fn from_checkpoint(topology: &NetworkTopology, completed: &HashSet<u64>) -> Self {
+        let mut ready = VecDeque::new();
+        let mut pending_upstream = HashMap::new();
+        for (&node_id, node) in &topology.nodes {
+            if completed.contains(&node_id) {
+                continue;
+            }
+            let pending = node
+                .upstream_ids
+                .iter()
+                .filter(|upstream| !completed.contains(upstream))
+                .count();
+            if pending == 0 {
+                ready.push_back(node_id);
+            } else {
+                pending_upstream.insert(node_id, pending);
+            }
+        }
+        let remaining = ready.len() + pending_upstream.len();
+        Scheduler {
+            ready,
+            pending_upstream,
+            remaining,
+        }
+    }
+
+    // Record a reach as done, readying its downstream reach if that was its last upstream
+    #[allow(clippy::collapsible_if)]
+    fn complete(&mut self, topology: &NetworkTopology, node_id: u64) {
+        self.remaining = self.remaining.saturating_sub(1);
+        if let Some(node) = topology.nodes.get(&node_id) {
+            if let Some(downstream_id) = node.downstream_id {
+                if let Some(count) = self.pending_upstream.get_mut(&downstream_id) {
+                    *count = count.saturating_sub(1);
+                    if *count == 0 {
+                        // All upstream nodes are complete, this node is ready
+                        self.ready.push_back(downstream_id);
+                        self.pending_upstream.remove(&downstream_id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
 // Scheduler thread that tracks dependencies and sends ready work
 fn scheduler_thread(
     topology: Arc<NetworkTopology>,
     scheduler_rx: Receiver<SchedulerMessage>,
     worker_tx: Vec<Sender<WorkerMessage>>,
-    total_nodes: usize,
+    mut scheduler: Scheduler,
+    forcing_window: Option<Arc<ForcingWindow>>,
     completed_count: Arc<AtomicUsize>,
 ) -> Result<()> {
-    // Track which nodes are ready to process
-    let mut ready_nodes = VecDeque::new();
-    let mut processed_nodes = HashSet::new();
-    let mut pending_downstream_count: HashMap<u32, usize> = HashMap::new();
-
-    // Initialize with leaf nodes (no upstream dependencies)
-    for (&node_id, node) in &topology.nodes {
-        if node.upstream_ids.is_empty() {
-            ready_nodes.push_back(node_id);
-        } else {
-            // Count how many upstream nodes need to complete
-            pending_downstream_count.insert(node_id, node.upstream_ids.len());
-        }
-    }
-
     let num_workers = worker_tx.len();
     let mut next_worker = 0;
 
-    loop {
+    while !scheduler.is_done() {
+        // Queue every ready reach's forcing first so they're read together
+        if let Some(window) = &forcing_window {
+            for &node_id in &scheduler.ready {
+                window.request(node_id)?;
+            }
+        }
+
         // Send ready work to workers
-        while let Some(node_id) = ready_nodes.pop_front() {
+        while let Some(node_id) = scheduler.ready.pop_front() {
             // Round-robin distribution to workers
             if let Err(e) = worker_tx[next_worker].send(WorkerMessage::ProcessNode(node_id)) {
-                eprintln!("Failed to send work to worker {}: {}", next_worker, e);
+                console_eprintln!("Failed to send work to worker {}: {}", next_worker, e);
             }
             next_worker = (next_worker + 1) % num_workers;
         }
@@ -171,30 +691,12 @@ fn scheduler_thread(
         // Wait for completion messages
         match scheduler_rx.recv() {
             Ok(SchedulerMessage::NodeCompleted(node_id)) => {
-                processed_nodes.insert(node_id);
-
-                // Check if this enables any downstream nodes
-                if let Some(node) = topology.nodes.get(&node_id) {
-                    if let Some(downstream_id) = node.downstream_id {
-                        if let Some(count) = pending_downstream_count.get_mut(&downstream_id) {
-                            *count = count.saturating_sub(1);
-                            if *count == 0 {
-                                // All upstream nodes are complete, this node is ready
-                                ready_nodes.push_back(downstream_id);
-                                pending_downstream_count.remove(&downstream_id);
-                            }
-                        }
-                    }
-                }
-
-                // Check if we're done
-                if processed_nodes.len() >= total_nodes {
-                    break;
-                }
+                completed_count.fetch_add(1, Ordering::Relaxed);
+                scheduler.complete(&topology, node_id);
             }
             Ok(SchedulerMessage::Shutdown) => break,
             Err(e) => {
-                eprintln!("Scheduler channel error: {}", e);
+                console_eprintln!("Scheduler channel error: {}", e);
                 break;
             }
         }
@@ -208,87 +710,243 @@ fn scheduler_thread(
     Ok(())
 }
 
-// Worker thread - now just receives work and processes it
-fn worker_thread(
-    work_rx: Receiver<WorkerMessage>,
-    scheduler_tx: Sender<SchedulerMessage>,
+// What every worker needs to route a node and hand its results on
+#[derive(Clone)]
+struct Worker {
     topology: Arc<NetworkTopology>,
-    channel_params_map: Arc<HashMap<u32, ChannelParams>>,
+    channel_params_map: Arc<HashMap<u64, ChannelParams>>,
     max_timesteps: usize,
     dt: f32,
+    options: Arc<RoutingOptions>,
     writer_tx: Sender<WriterMessage>,
     progress_bar: Arc<ProgressBar>,
-) -> Result<()> {
-    loop {
-        match work_rx.recv() {
-            Ok(WorkerMessage::ProcessNode(node_id)) => {
-                // Process the node
-                if let Some(params) = channel_params_map.get(&node_id) {
-                    match process_node_all_timesteps(&node_id, &topology, params, max_timesteps, dt)
+    failed_nodes: Arc<AtomicUsize>,
+    capped_nodes: Arc<AtomicUsize>,
+    // Reaches whose forcing record was the wrong length: (node, steps found, steps needed)
+    forcing_mismatches: Arc<Mutex<Vec<(u64, usize, usize)>>>,
+    // Reaches already routed, failed or skipped, whose flow is in their downstream buffer
+    completed: Arc<Mutex<HashSet<u64>>>,
+}
+
+impl Worker {
+    // A reach must only be routed once every upstream reach has handed its flow on,
+    // otherwise it reads a partly filled inflow buffer and silently routes too little flow
+    fn check_upstream_complete(&self, node_id: u64) -> Result<()> {
+        let Some(node) = self.topology.nodes.get(&node_id) else {
+            return Ok(());
+        };
+        let completed = self
+            .completed
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock completed reaches: {}", e))?;
+        let mut pending: Vec<u64> = node
+            .upstream_ids
+            .iter()
+            .copied()
+            .filter(|upstream| !completed.contains(upstream))
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        pending.sort_unstable();
+        Err(RouteError::Routing {
+            reaches: std::iter::once(node_id)
+                .chain(pending.iter().copied())
+                .collect(),
+            message: format!(
+                "Node {} was routed before its upstream nodes {:?} completed",
+                node_id, pending
+            ),
+        }
+        .into())
+    }
+
+    // Route one node, send its results to the writer and its flow downstream
+    fn route_node(&self, node_id: u64) -> Result<()> {
+        let Worker {
+            ref topology,
+            ref channel_params_map,
+            max_timesteps,
+            dt,
+            ref options,
+            ref writer_tx,
+            ref progress_bar,
+            ref failed_nodes,
+            ref capped_nodes,
+            ref forcing_mismatches,
+            ref completed,
+        } = *self;
+
+        interrupt::check()?;
+        if options.skip_routing.contains(&node_id) {
+            // Already written, and so is everything downstream of it
+        } else if let Some(params) = channel_params_map.get(&node_id) {
+            self.check_upstream_complete(node_id)?;
+            let started = Instant::now();
+            match process_node_all_timesteps(&node_id, topology, params, max_timesteps, dt, options)
+            {
+                Ok(mut results) => {
+                    if let Some(ceiling) = options.abort_above_flow {
+                        check_flow_ceiling(&results, ceiling, dt)?;
+                    }
+                    results.wall_micros = started.elapsed().as_micros() as u64;
+                    if results.iteration_capped {
+                        capped_nodes.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if let Some((found, needed)) = results.forcing_mismatch {
+                        forcing_mismatches
+                            .lock()
+                            .map_err(|e| anyhow::anyhow!("Failed to lock forcing report: {}", e))?
+                            .push((node_id, found, needed));
+                    }
+                    let mut routed_flow = std::mem::take(&mut results.spinup_flow);
+
+                    // Downstream reaches are fed the routed flow, not the transformed output
+                    let untransformed_flow = options.result_transform.as_ref().map(|transform| {
+                        let flow = results.flow_data.clone();
+                        (transform.0)(&mut results);
+                        flow
+                    });
+
+                    if let Some(peaks) = &options.peak_flows {
+                        peaks.record(node_id, &results.flow_data)?;
+                    }
+
+                    if let Some(cache) = &options.results_cache
+                        && let Err(e) = cache.store(&results)
                     {
-                        Ok(results) => {
-                            let results_arc = Arc::new(results);
-
-                            // Send results to writer
-                            if let Err(e) = writer_tx
-                                .send(WriterMessage::WriteResults(Arc::clone(&results_arc)))
-                            {
-                                eprintln!("Failed to send results to writer: {}", e);
-                            }
+                        console_eprintln!("Failed to cache results for node {}: {}", node_id, e);
+                    }
 
-                            // Pass flow to downstream node
-                            if let Some(node) = topology.nodes.get(&node_id) {
-                                if let Some(downstream_id) = node.downstream_id {
-                                    if let Some(downstream_node) =
-                                        topology.nodes.get(&downstream_id)
-                                    {
-                                        let mut buffer =
-                                            downstream_node.inflow_storage.lock().map_err(|e| {
-                                                anyhow::anyhow!(
-                                                    "Failed to lock downstream buffer: {}",
-                                                    e
-                                                )
-                                            })?;
-                                        if buffer.is_empty() {
-                                            buffer.resize(results_arc.flow_data.len(), 0.0);
-                                        }
-                                        for (i, &flow) in results_arc.flow_data.iter().enumerate() {
-                                            if i < buffer.len() {
-                                                buffer[i] += flow;
-                                            }
-                                        }
-                                    }
-                                }
-
-                                // Update status
-                                let mut status = node.status.write().map_err(|e| {
-                                    anyhow::anyhow!("Failed to acquire status write lock: {}", e)
-                                })?;
-                                *status = NodeStatus::Ready;
+                    let results_arc = Arc::new(results);
+
+                    // Send results to writer, unless a resumed output already has them
+                    if !options.already_written.contains(&node_id)
+                        && let Err(e) =
+                            writer_tx.send(WriterMessage::WriteResults(Arc::clone(&results_arc)))
+                    {
+                        console_eprintln!("Failed to send results to writer: {}", e);
+                    }
 
-                                // Clear inflow storage
-                                let mut old_inflow = node.inflow_storage.lock().map_err(|e| {
-                                    anyhow::anyhow!("Failed to lock inflow storage: {}", e)
+                    // Pass flow to downstream node
+                    if let Some(node) = topology.nodes.get(&node_id) {
+                        if let Some(downstream_id) = node.downstream_id
+                            && let Some(downstream_node) = topology.nodes.get(&downstream_id)
+                        {
+                            let mut buffer =
+                                downstream_node.inflow_storage.lock().map_err(|e| {
+                                    anyhow::anyhow!("Failed to lock downstream buffer: {}", e)
                                 })?;
-                                old_inflow.clear();
+                            let flow = untransformed_flow
+                                .as_deref()
+                                .unwrap_or(&results_arc.flow_data);
+                            let lag = match options.confluence {
+                                Confluence::Sum => 0,
+                                Confluence::Lagged => travel_lag(&results_arc, dt),
+                            };
+                            if routed_flow.is_empty() {
+                                accumulate_inflow(&mut buffer, flow, lag);
+                            } else {
+                                routed_flow.extend(flow);
+                                accumulate_inflow(&mut buffer, &routed_flow, lag);
                             }
                         }
-                        Err(e) => {
-                            eprintln!("Error processing node {}: {}", node_id, e);
-                        }
+
+                        // Update status
+                        let mut status = node.status.write().map_err(|e| {
+                            anyhow::anyhow!("Failed to acquire status write lock: {}", e)
+                        })?;
+                        *status = NodeStatus::Ready;
+
+                        // Clear inflow storage
+                        let mut old_inflow = node
+                            .inflow_storage
+                            .lock()
+                            .map_err(|e| anyhow::anyhow!("Failed to lock inflow storage: {}", e))?;
+                        old_inflow.clear();
                     }
+                }
+                // Strict runs stop at the first failure instead of writing fill values
+                Err(e) if options.strict => return Err(e),
+                Err(e) => {
+                    console_eprintln!("Error processing node {}: {}", node_id, e);
+                    failed_nodes.fetch_add(1, Ordering::Relaxed);
+                    let _ = writer_tx.send(WriterMessage::WriteFill(node_id));
+                }
+            }
+        } else if options.strict {
+            return Err(anyhow::anyhow!(
+                "Node {} has no channel parameters (--strict)",
+                node_id
+            ));
+        } else {
+            console_eprintln!("Skipping node {}: no channel parameters", node_id);
+            failed_nodes.fetch_add(1, Ordering::Relaxed);
+            let _ = writer_tx.send(WriterMessage::WriteFill(node_id));
+        }
+        if let Some(window) = &options.forcing_window {
+            window.release(node_id)?;
+        }
+        completed
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock completed reaches: {}", e))?
+            .insert(node_id);
+        progress_bar.inc(1);
+        Ok(())
+    }
+}
+
+// Fail on the first timestep whose outflow is above `ceiling` or isn't finite, for
+// --abort-above-flow. The worker's error stops the whole run.
+// flow_data holds internal steps, so the step is reported as time since the start, which
+// matches the output file's time axis whatever the output stride
+fn check_flow_ceiling(results: &SimulationResults, ceiling: f32, dt: f32) -> Result<()> {
+    let Some((timestep, flow)) = results
+        .flow_data
+        .iter()
+        .enumerate()
+        .find(|&(_, &flow)| !flow.is_finite() || flow > ceiling)
+    else {
+        return Ok(());
+    };
+    Err(RouteError::Routing {
+        reaches: vec![results.feature_id as u64],
+        message: format!(
+            "Node {} outflow of {} m3/s at {} s after the start (internal step {}) exceeds \
+             --abort-above-flow {}",
+            results.feature_id,
+            flow,
+            timestep as f64 * dt as f64,
+            timestep,
+            ceiling
+        ),
+    }
+    .into())
+}
 
-                    progress_bar.inc(1);
+// Worker thread - now just receives work and processes it
+fn worker_thread(
+    work_rx: Receiver<WorkerMessage>,
+    scheduler_tx: Sender<SchedulerMessage>,
+    worker: Worker,
+) -> Result<()> {
+    loop {
+        match work_rx.recv() {
+            Ok(WorkerMessage::ProcessNode(node_id)) => {
+                if let Err(e) = worker.route_node(node_id) {
+                    // The scheduler would otherwise wait forever for this reach
+                    let _ = scheduler_tx.send(SchedulerMessage::Shutdown);
+                    return Err(e);
                 }
 
                 // Notify scheduler that node is complete
                 if let Err(e) = scheduler_tx.send(SchedulerMessage::NodeCompleted(node_id)) {
-                    eprintln!("Failed to notify scheduler of completion: {}", e);
+                    console_eprintln!("Failed to notify scheduler of completion: {}", e);
                 }
             }
             Ok(WorkerMessage::Shutdown) => break,
             Err(e) => {
-                eprintln!("Worker channel error: {}", e);
+                console_eprintln!("Worker channel error: {}", e);
                 break;
             }
         }
@@ -296,100 +954,1805 @@ fn worker_thread(
     Ok(())
 }
 
+// Worker thread for --by-component: takes whole basins and routes each in order.
+// Basins share no reaches, so no scheduler is needed.
+fn component_worker_thread(
+    components: Arc<Mutex<VecDeque<Vec<u64>>>>,
+    worker: Worker,
+    completed_count: Arc<AtomicUsize>,
+) -> Result<()> {
+    loop {
+        let component = components
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock component queue: {}", e))?
+            .pop_front();
+        let Some(component) = component else {
+            break;
+        };
+
+        if let Some(window) = &worker.options.forcing_window {
+            for &node_id in &component {
+                window.request(node_id)?;
+            }
+        }
+        for node_id in component {
+            worker.route_node(node_id)?;
+            completed_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+// Add an upstream reach's flow series into a downstream inflow buffer, delayed by `lag`
+// timesteps. The first value is held over the delay, and the last `lag` values fall
+// past the end of the run.
+fn accumulate_inflow(buffer: &mut VecDeque<f64>, flows: &[f32], lag: usize) {
+    if buffer.is_empty() {
+        buffer.resize(flows.len(), 0.0);
+    }
+    for (i, total) in buffer.iter_mut().enumerate().take(flows.len()) {
+        *total += flows[i.saturating_sub(lag)] as f64;
+    }
+}
+
+// A reach's mean Muskingum travel time over the timesteps it carried flow, in whole
+// timesteps, for --confluence lagged
+fn travel_lag(results: &SimulationResults, dt: f32) -> usize {
+    if results.flowing_steps == 0 {
+        return 0;
+    }
+    (results.travel_time / results.flowing_steps as f64 / dt as f64).round() as usize
+}
+
+// Summarize reaches whose forcing record didn't match the run's length in one warning
+fn report_forcing_mismatches(
+    mismatches: &[(u64, usize, usize)],
+    total_nodes: usize,
+    strict: bool,
+) -> Result<()> {
+    let Some(&(_, _, needed)) = mismatches.first() else {
+        return Ok(());
+    };
+    let mut mismatches = mismatches.to_vec();
+    mismatches.sort_unstable();
+    let short = mismatches
+        .iter()
+        .filter(|&&(_, found, _)| found < needed)
+        .count();
+    let examples: Vec<String> = mismatches
+        .iter()
+        .take(5)
+        .map(|(node_id, found, _)| format!("{} ({})", node_id, found))
+        .collect();
+    console_warn!(
+        strict,
+        "{} of {} nodes have forcing records that don't match the {} steps the run needs; \
+         padded {} short records with zero lateral inflow and truncated {} long ones. \
+         First nodes (steps found): {}",
+        mismatches.len(),
+        total_nodes,
+        needed,
+        short,
+        mismatches.len() - short,
+        examples.join(", ")
+    )
+}
+
 // Main parallel routing function
+#[allow(clippy::too_many_arguments)]
 pub fn process_routing_parallel(
     topology: &NetworkTopology,
-    channel_params_map: &HashMap<u32, ChannelParams>,
+    channel_params_map: &HashMap<u64, ChannelParams>,
     max_timesteps: usize,
     dt: f32,
-    output_file: Arc<Mutex<FileMut>>,
+    options: RoutingOptions,
+    output_file: Arc<Mutex<NetCdfOutput>>,
+    csv_writer: Option<Writer<File>>,
+    aggregator: Option<GroupAggregator>,
+    statistics: Option<OutputStatistics>,
+    timings: Option<TimingReport>,
     progress_bar: Arc<ProgressBar>,
-) -> Result<()> {
+) -> Result<(), RouteError> {
+    let _console = console::attach(&progress_bar);
     let total_nodes = topology.nodes.len();
     let completed_count = Arc::new(AtomicUsize::new(0));
     let topology_arc = Arc::new(topology.clone());
     let channel_params_arc = Arc::new(channel_params_map.clone());
+    let options_arc = Arc::new(options);
 
     // Create channels
     let (writer_tx, writer_rx) = mpsc::channel();
-    let (scheduler_tx, scheduler_rx) = mpsc::channel();
 
     // Create worker channels
     let num_threads = num_cpus::get();
-    println!(
+    console_println!(
         "Using {} worker threads for parallel processing",
         num_threads
     );
 
-    let mut worker_txs = Vec::new();
     let mut worker_handles = Vec::new();
+    let failed_nodes = Arc::new(AtomicUsize::new(0));
+    let capped_nodes = Arc::new(AtomicUsize::new(0));
+    let forcing_mismatches = Arc::new(Mutex::new(Vec::new()));
+    let worker = Worker {
+        topology: Arc::clone(&topology_arc),
+        channel_params_map: Arc::clone(&channel_params_arc),
+        max_timesteps,
+        dt,
+        options: Arc::clone(&options_arc),
+        writer_tx: writer_tx.clone(),
+        progress_bar: Arc::clone(&progress_bar),
+        failed_nodes: Arc::clone(&failed_nodes),
+        capped_nodes: Arc::clone(&capped_nodes),
+        forcing_mismatches: Arc::clone(&forcing_mismatches),
+        completed: Arc::new(Mutex::new(options_arc.skip_routing.clone())),
+    };
 
-    // Spawn worker threads
-    for i in 0..num_threads {
-        let (work_tx, work_rx) = mpsc::channel();
-        worker_txs.push(work_tx);
+    // Whole basins go to whichever worker is free, largest first; otherwise reaches are
+    // dispatched one at a time as their upstream reaches complete
+    let mut scheduler = None;
+    if options_arc.by_component {
+        let components = topology.components();
+        console_println!(
+            "Routing {} independent basins, the largest with {} reaches",
+            components.len(),
+            components.first().map_or(0, Vec::len)
+        );
+        let components = Arc::new(Mutex::new(VecDeque::from(components)));
+        for i in 0..num_threads {
+            let components = Arc::clone(&components);
+            let worker = worker.clone();
+            let completed = Arc::clone(&completed_count);
+            let handle = thread::spawn(move || {
+                component_worker_thread(components, worker, completed)
+                    .inspect_err(|e| console_eprintln!("Worker {} error: {}", i, e))
+            });
+            worker_handles.push(handle);
+        }
+    } else {
+        let (scheduler_tx, scheduler_rx) = mpsc::channel();
+        let mut worker_txs = Vec::new();
+
+        // Spawn worker threads
+        for i in 0..num_threads {
+            let (work_tx, work_rx) = mpsc::channel();
+            worker_txs.push(work_tx);
+
+            let scheduler_tx = scheduler_tx.clone();
+            let worker = worker.clone();
+            let handle = thread::spawn(move || {
+                worker_thread(work_rx, scheduler_tx, worker)
+                    .inspect_err(|e| console_eprintln!("Worker {} error: {}", i, e))
+            });
+            worker_handles.push(handle);
+        }
 
+        // Spawn scheduler thread. Reaches a resumed run needn't route are never dispatched.
         let topo = Arc::clone(&topology_arc);
-        let params = Arc::clone(&channel_params_arc);
-        let writer = writer_tx.clone();
-        let scheduler = scheduler_tx.clone();
-        let pb = Arc::clone(&progress_bar);
-
-        let handle = thread::spawn(move || {
-            if let Err(e) = worker_thread(
-                work_rx,
-                scheduler,
+        let forcing_window = options_arc.forcing_window.clone();
+        let state = Scheduler::from_checkpoint(topology, &options_arc.skip_routing);
+        progress_bar.inc((total_nodes - state.remaining) as u64);
+        let completed = Arc::clone(&completed_count);
+        scheduler = Some(thread::spawn(move || {
+            scheduler_thread(
                 topo,
-                params,
-                max_timesteps,
-                dt,
-                writer,
-                pb,
-            ) {
-                eprintln!("Worker {} error: {}", i, e);
-            }
-        });
-        worker_handles.push(handle);
+                scheduler_rx,
+                worker_txs,
+                state,
+                forcing_window,
+                completed,
+            )
+            .inspect_err(|e| console_eprintln!("Scheduler thread error: {}", e))
+        }));
     }
 
     // Spawn writer thread
     let output_file_clone = Arc::clone(&output_file);
+    let write_retries = options_arc.write_retries;
     let writer_handle = thread::spawn(move || {
-        if let Err(e) = writer_thread(writer_rx, output_file_clone) {
-            eprintln!("Writer thread error: {}", e);
-        }
+        writer_thread(
+            writer_rx,
+            output_file_clone,
+            csv_writer,
+            aggregator,
+            statistics,
+            timings,
+            write_retries,
+        )
+        .inspect_err(|e| console_eprintln!("Writer thread error: {}", e))
     });
 
-    // Spawn scheduler thread
-    let topo = Arc::clone(&topology_arc);
-    let completed = Arc::clone(&completed_count);
-    let scheduler_handle = thread::spawn(move || {
-        if let Err(e) = scheduler_thread(topo, scheduler_rx, worker_txs, total_nodes, completed) {
-            eprintln!("Scheduler thread error: {}", e);
-        }
-    });
+    // Drop original senders; the writer is told to shut down once every worker is done
+    drop(worker);
 
-    // Drop original senders
+    // Join every thread before returning, even once one has failed: the writer only
+    // finishes after draining every result already sent and syncing the output, so the
+    // file is complete before its last handle is dropped and closes it
+    let mut errors = Vec::new();
+    // Reaches named by a worker's RouteError, e.g. the reach that tripped --abort-above-flow
+    let mut failed_reaches = Vec::new();
+    if let Some(scheduler_handle) = scheduler {
+        match scheduler_handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => errors.push(format!("scheduler failed: {}", e)),
+            Err(_) => errors.push("scheduler thread panicked".to_string()),
+        }
+    }
+    for (i, handle) in worker_handles.into_iter().enumerate() {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                if let Some(error) = e.downcast_ref::<RouteError>() {
+                    failed_reaches.extend_from_slice(error.reaches());
+                }
+                errors.push(format!("worker {} failed: {}", i, e));
+            }
+            Err(_) => errors.push(format!("worker {} panicked", i)),
+        }
+    }
+    let _ = writer_tx.send(WriterMessage::Shutdown);
     drop(writer_tx);
-    drop(scheduler_tx);
+    let failed_writes = match writer_handle.join() {
+        Ok(Ok(failed_writes)) => failed_writes,
+        Ok(Err(e)) => {
+            errors.push(format!("writer failed: {}", e));
+            Vec::new()
+        }
+        Err(_) => {
+            errors.push("writer thread panicked".to_string());
+            Vec::new()
+        }
+    };
+    drop(output_file);
+    if !errors.is_empty() {
+        progress_bar.abandon();
+        failed_reaches.sort_unstable();
+        failed_reaches.dedup();
+        return Err(RouteError::Routing {
+            reaches: failed_reaches,
+            message: format!(
+                "Routing stopped early after {} of {} reaches ({}); the output holds only the \
+                 reaches routed before then",
+                completed_count.load(Ordering::Relaxed),
+                total_nodes,
+                errors.join("; ")
+            ),
+        });
+    }
 
-    // Wait for all threads to complete
-    scheduler_handle
-        .join()
-        .map_err(|e| anyhow::anyhow!("Scheduler thread panicked: {:?}", e))?;
+    progress_bar.finish_with_message("Complete");
+    report_failed_writes(&failed_writes, total_nodes, options_arc.strict)?;
+    report_forcing_mismatches(
+        &forcing_mismatches
+            .lock()
+            .map_err(|e| anyhow::anyhow!("Failed to lock forcing report: {}", e))?,
+        total_nodes,
+        options_arc.strict,
+    )?;
+    let capped = capped_nodes.load(Ordering::Relaxed);
+    if capped > 0 {
+        console_warn!(
+            options_arc.strict,
+            "{} of {} nodes hit --max-reach-iterations and finished at normal flow",
+            capped,
+            total_nodes
+        )?;
+    }
+    let failed = failed_nodes.load(Ordering::Relaxed);
+    if failed > 0 {
+        console_warn!(
+            options_arc.strict,
+            "{} of {} nodes failed and were written as fill values",
+            failed,
+            total_nodes
+        )?;
+    }
+    console_println!("Successfully processed all {} nodes", total_nodes);
 
-    for (i, handle) in worker_handles.into_iter().enumerate() {
-        handle
-            .join()
-            .map_err(|e| anyhow::anyhow!("Worker thread {} panicked: {:?}", i, e))?;
+    Ok(())
+}
+
+// Reaches that needn't be routed when resuming: already written, with every
+// reach downstream of them also written, so nothing depends on their flow
+pub fn resume_skip_set(topology: &NetworkTopology, written: &HashSet<u64>) -> HashSet<u64> {
+    let mut needed: HashSet<u64> = HashSet::new();
+
+    // Downstream reaches come later in routing order, so walk it backwards
+    for &id in topology.routing_order.iter().rev() {
+        let downstream_needed = topology
+            .nodes
+            .get(&id)
+            .and_then(|node| node.downstream_id)
+            .is_some_and(|downstream| needed.contains(&downstream));
+        if !written.contains(&id) || downstream_needed {
+            needed.insert(id);
+        }
     }
 
-    writer_handle
+    topology
+        .routing_order
+        .iter()
+        .copied()
+        .filter(|id| !needed.contains(id))
+        .collect()
+}
+
+// Print a JSON progress line every `interval` until the bar is finished, then a final line
+pub fn spawn_json_progress(
+    progress_bar: Arc<ProgressBar>,
+    interval: Duration,
+) -> thread::JoinHandle<()> {
+    let report = |pb: &ProgressBar| {
+        console_println!(
+            "{}",
+            serde_json::json!({
+                "done": pb.position(),
+                "total": pb.length().unwrap_or(0),
+                "eta_s": pb.eta().as_secs(),
+            })
+        );
+    };
+
+    thread::spawn(move || {
+        let mut last_report = Instant::now();
+        while !progress_bar.is_finished() {
+            thread::sleep(Duration::from_millis(100));
+            if last_report.elapsed() >= interval {
+                report(&progress_bar);
+                last_report = Instant::now();
+            }
+        }
+        report(&progress_bar);
+    })
+}
+
+// Regenerate output from cached results without routing
+#[allow(clippy::too_many_arguments)]
+pub fn replay_results(
+    topology: &NetworkTopology,
+    cache: &ResultsCache,
+    output_file: Arc<Mutex<NetCdfOutput>>,
+    csv_writer: Option<Writer<File>>,
+    aggregator: Option<GroupAggregator>,
+    statistics: Option<OutputStatistics>,
+    write_retries: u32,
+    strict: bool,
+    progress_bar: Arc<ProgressBar>,
+) -> Result<()> {
+    let _console = console::attach(&progress_bar);
+    let (writer_tx, writer_rx) = mpsc::channel();
+    let writer_handle = thread::spawn(move || {
+        writer_thread(
+            writer_rx,
+            output_file,
+            csv_writer,
+            aggregator,
+            statistics,
+            None,
+            write_retries,
+        )
+    });
+
+    let mut replayed = 0;
+    for node_id in &topology.routing_order {
+        if interrupt::interrupted() {
+            break;
+        }
+        replayed += 1;
+        match cache.load(*node_id as i64) {
+            Ok(results) => {
+                if let Err(e) = writer_tx.send(WriterMessage::WriteResults(Arc::new(results))) {
+                    console_eprintln!("Failed to send results to writer: {}", e);
+                }
+            }
+            Err(e) => {
+                console_eprintln!("Skipping node {}: {}", node_id, e);
+                let _ = writer_tx.send(WriterMessage::WriteFill(*node_id));
+            }
+        }
+        progress_bar.inc(1);
+    }
+
+    drop(writer_tx);
+    let failed_writes = writer_handle
         .join()
-        .map_err(|e| anyhow::anyhow!("Writer thread panicked: {:?}", e))?;
+        .map_err(|e| anyhow::anyhow!("Writer thread panicked: {:?}", e))?
+        .context("Writer thread failed")?;
 
+    let total_nodes = topology.routing_order.len();
+    if replayed < total_nodes {
+        progress_bar.abandon();
+        return Err(anyhow::anyhow!(
+            "Replay interrupted after {} of {} nodes; the output holds only those nodes",
+            replayed,
+            total_nodes
+        ));
+    }
     progress_bar.finish_with_message("Complete");
-    println!("Successfully processed all {} nodes", total_nodes);
+    report_failed_writes(&failed_writes, total_nodes, strict)?;
+    console_println!("Replayed {} nodes from cache", total_nodes);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReachType;
+    use crate::fixtures;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn interior_reach_without_forcing_routes_upstream_inflow() {
+        let mut topology = NetworkTopology::new();
+        let missing_forcing = PathBuf::from("does-not-exist").join("cat-2.csv");
+        topology.add_node(1, Some(2), Some(1.0), PathBuf::from("unused.csv"));
+        topology.add_node(2, None, Some(1.0), missing_forcing);
+        topology.build_upstream_connections();
+
+        let max_timesteps = 6;
+        topology.nodes[&2]
+            .inflow_storage
+            .lock()
+            .unwrap()
+            .extend(vec![10.0; max_timesteps]);
+
+        let results = process_node_all_timesteps(
+            &2,
+            &topology,
+            &fixtures::default_params(),
+            max_timesteps,
+            3600.0,
+            &RoutingOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.flow_data.len(), max_timesteps);
+        let single = route_reach(
+            &fixtures::default_params(),
+            &[10.0; 6],
+            &[],
+            3600.0,
+            &RoutingOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(single.flow_data, results.flow_data);
+        assert_eq!(single.depth_data, results.depth_data);
+        // Outflow rises toward the steady upstream inflow even with no lateral inflow
+        assert!(results.flow_data.windows(2).all(|w| w[1] >= w[0]));
+        assert!(results.flow_data[max_timesteps - 1] > 9.0);
+
+        // Each step's depth solve finishes after a single secant iteration
+        assert_eq!(results.max_iterations, 1);
+        assert_eq!(results.iterations, max_timesteps as u64);
+
+        // Every step carries flow, so every step adds its travel time
+        assert_eq!(results.flowing_steps as usize, max_timesteps);
+        assert!(results.travel_time > 0.0);
+    }
+
+    #[test]
+    fn single_reach_routing_matches_the_network_headwater() {
+        let q_out = [0.36, 0.72, 0.18, 0.0];
+        let forcing = fixtures::write_forcing("single_reach", 3, &q_out);
+        let mut topology = NetworkTopology::new();
+        topology.add_node(3, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+
+        let options = RoutingOptions::default();
+        let network = process_node_all_timesteps(
+            &3,
+            &topology,
+            &fixtures::default_params(),
+            4,
+            3600.0,
+            &options,
+        )
+        .unwrap();
+
+        let lateral = q_out.map(|q| runoff_to_flow(q, 1.0));
+        let single =
+            route_reach(&fixtures::default_params(), &[], &lateral, 3600.0, &options).unwrap();
+        assert_eq!(single.flow_data, network.flow_data);
+        assert_eq!(single.depth_data, network.depth_data);
+    }
+
+    // The gpkg stores channel parameters as f64 REALs, narrowed to f32 when read. Widening
+    // them back to f64 is exact, so it can't change anything; what f64 could add is the
+    // narrowing error, at most half an f32 ulp per parameter. Routing a storm hydrograph with
+    // one parameter a whole ulp off moves outflow by up to about 0.8% (s0): the secant
+    // solve stops anywhere within its RELATIVE_TOLERANCE, and the nudge can stop it at a
+    // different iterate. f64 parameters would move results only within that tolerance.
+    #[test]
+    fn f64_channel_params_would_not_change_routed_flow_materially() {
+        let source: [f64; 8] = [
+            1234.5678, 0.0612345, 0.1212345, 0.00123456, 5.4321, 10.789, 30.1, 0.5123,
+        ];
+        let params = |values: [f32; 8]| {
+            let [dx, n, ncc, s0, bw, tw, twcc, cs] = values;
+            ChannelParams {
+                dx,
+                n,
+                ncc,
+                s0,
+                bw,
+                tw,
+                twcc,
+                cs,
+            }
+        };
+        let narrowed = source.map(|value| value as f32);
+        let widened = narrowed.map(|value| value as f64).map(|value| value as f32);
+        let inflow: Vec<f32> = (0..96)
+            .map(|t| 5.0 + 400.0 * (-((t as f32 - 24.0) / 8.0).powi(2)).exp())
+            .collect();
+        let route = |values| {
+            route_reach(
+                &params(values),
+                &inflow,
+                &[],
+                300.0,
+                &RoutingOptions::default(),
+            )
+            .unwrap()
+            .flow_data
+        };
+
+        let reference = route(narrowed);
+        assert_eq!(route(widened), reference);
+        for index in 0..8 {
+            let mut nudged = narrowed;
+            nudged[index] = nudged[index].next_up();
+            let max_rel = route(nudged)
+                .iter()
+                .zip(&reference)
+                .map(|(x, y)| (x - y).abs() / y.abs().max(1.0))
+                .fold(0.0_f32, f32::max);
+            assert!(
+                max_rel < crate::mc_kernel::RELATIVE_TOLERANCE,
+                "parameter {} moved outflow by {}",
+                index,
+                max_rel
+            );
+        }
+    }
+
+    #[test]
+    fn routing_by_component_matches_the_scheduler() {
+        // Basins 1 -> 2 -> 4 <- 3 and 5 -> 6, and a lone reach 7
+        let build = || {
+            let mut topology = NetworkTopology::new();
+            for (id, downstream) in [
+                (1, Some(2)),
+                (2, Some(4)),
+                (3, Some(4)),
+                (4, None),
+                (5, Some(6)),
+                (6, None),
+                (7, None),
+            ] {
+                topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+                topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![id as f32; 4]);
+            }
+            topology.build_upstream_connections();
+            topology.topological_sort().unwrap();
+            topology
+        };
+        let components = build().components();
+        assert_eq!(components.len(), 3);
+        assert_eq!(components[0].len(), 4);
+        assert_eq!(components[0].last(), Some(&4));
+        assert_eq!(components[1], vec![5, 6]);
+        assert_eq!(components[2], vec![7]);
+
+        let params: HashMap<u64, ChannelParams> =
+            (1..=7).map(|id| (id, fixtures::default_params())).collect();
+        let route = |by_component: bool| -> Vec<Vec<f32>> {
+            let options = RoutingOptions {
+                by_component,
+                ..RoutingOptions::default()
+            };
+            let cache = fixtures::route_in_memory(&build(), &params, 4, options).unwrap();
+            (1..=7)
+                .map(|id| cache.load(id).unwrap().flow_data)
+                .collect()
+        };
+        assert_eq!(route(true), route(false));
+    }
+
+    #[test]
+    fn scheduler_resumed_from_a_checkpoint_finishes_the_run() {
+        // 1 -> 2 -> 4 <- 3, and 5 -> 6
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [
+            (1, Some(2)),
+            (2, Some(4)),
+            (3, Some(4)),
+            (4, None),
+            (5, Some(6)),
+            (6, None),
+        ] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+        }
+        topology.build_upstream_connections();
+
+        // Dispatch and complete reaches one at a time, stopping after `limit`
+        let run = |scheduler: &mut Scheduler, done: &mut Vec<u64>, limit: usize| {
+            while done.len() < limit
+                && let Some(node_id) = scheduler.ready.pop_front()
+            {
+                let upstream = &topology.nodes[&node_id].upstream_ids;
+                assert!(upstream.iter().all(|id| done.contains(id)), "{}", node_id);
+                done.push(node_id);
+                scheduler.complete(&topology, node_id);
+            }
+        };
+
+        let mut done = Vec::new();
+        let mut scheduler = Scheduler::new(&topology);
+        run(&mut scheduler, &mut done, 3);
+        assert!(!scheduler.is_done());
+
+        let checkpoint: HashSet<u64> = done.iter().copied().collect();
+        let mut resumed = Scheduler::from_checkpoint(&topology, &checkpoint);
+        assert_eq!(resumed.remaining, 3);
+        run(&mut resumed, &mut done, usize::MAX);
+        assert!(resumed.is_done());
+        done.sort_unstable();
+        assert_eq!(done, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn routing_resumed_from_a_checkpoint_matches_an_uninterrupted_run() {
+        // 1 -> 2 -> 4 <- 3, with 1 and 3 done at the checkpoint
+        let build = || {
+            let mut topology = NetworkTopology::new();
+            for (id, downstream) in [(1, Some(2)), (2, Some(4)), (3, Some(4)), (4, None)] {
+                topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+                topology.nodes.get_mut(&id).unwrap().lateral_inflow =
+                    Some(vec![id as f32, 2.0 * id as f32, 0.5]);
+            }
+            topology.build_upstream_connections();
+            topology
+        };
+        let params: HashMap<u64, ChannelParams> =
+            (1..=4).map(|id| (id, fixtures::default_params())).collect();
+        let route = |topology: NetworkTopology, skip_routing: HashSet<u64>| {
+            let options = RoutingOptions {
+                skip_routing,
+                ..RoutingOptions::default()
+            };
+            fixtures::route_in_memory(&topology, &params, 3, options).unwrap()
+        };
+
+        let uninterrupted = route(build(), HashSet::new());
+
+        // Restore the checkpointed reaches' outflow into the reaches they feed
+        let resumed_topology = build();
+        for (done, downstream) in [(1, 2), (3, 4)] {
+            let flow = uninterrupted.load(done).unwrap().flow_data;
+            accumulate_inflow(
+                &mut resumed_topology.nodes[&downstream]
+                    .inflow_storage
+                    .lock()
+                    .unwrap(),
+                &flow,
+                0,
+            );
+        }
+        let resumed = route(resumed_topology, HashSet::from([1, 3]));
+
+        for id in [2, 4] {
+            assert_eq!(
+                resumed.load(id).unwrap().flow_data,
+                uninterrupted.load(id).unwrap().flow_data
+            );
+        }
+        assert!(resumed.load(1).is_err());
+    }
+
+    #[test]
+    fn nodes_without_parameters_still_advance_the_progress_bar() {
+        use indicatif::ProgressDrawTarget;
+
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(2)), (2, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![1.0, 2.0, 1.0]);
+        }
+        topology.build_upstream_connections();
+        // Reach 1 has no channel parameters and is skipped
+        let params = HashMap::from([(2, fixtures::default_params())]);
+        let (writer_tx, writer_rx) = mpsc::channel();
+        let progress_bar = Arc::new(ProgressBar::with_draw_target(
+            Some(2),
+            ProgressDrawTarget::hidden(),
+        ));
+        let worker = Worker {
+            topology: Arc::new(topology),
+            channel_params_map: Arc::new(params),
+            max_timesteps: 3,
+            dt: 3600.0,
+            options: Arc::new(RoutingOptions::default()),
+            writer_tx,
+            progress_bar: Arc::clone(&progress_bar),
+            failed_nodes: Arc::new(AtomicUsize::new(0)),
+            capped_nodes: Arc::new(AtomicUsize::new(0)),
+            forcing_mismatches: Arc::new(Mutex::new(Vec::new())),
+            completed: Arc::new(Mutex::new(HashSet::new())),
+        };
+
+        worker.route_node(1).unwrap();
+        assert_eq!(progress_bar.position(), 1);
+        assert!(matches!(
+            writer_rx.try_recv(),
+            Ok(WriterMessage::WriteFill(1))
+        ));
+        worker.route_node(2).unwrap();
+        assert_eq!(progress_bar.position(), progress_bar.length().unwrap());
+        assert_eq!(worker.failed_nodes.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn strict_runs_stop_at_the_first_reach_that_fails() {
+        // 1 -> 2 -> 3, where 1 has zero slope and 3 has no channel parameters
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(2)), (2, Some(3)), (3, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![1.0, 2.0, 1.0]);
+        }
+        topology.build_upstream_connections();
+        let params = HashMap::from([
+            (
+                1,
+                ChannelParams {
+                    s0: 0.0,
+                    ..fixtures::default_params()
+                },
+            ),
+            (2, fixtures::default_params()),
+        ]);
+        let options = RoutingOptions {
+            strict: true,
+            ..RoutingOptions::default()
+        };
+        let (writer_tx, writer_rx) = mpsc::channel();
+        let worker = Worker {
+            topology: Arc::new(topology.clone()),
+            channel_params_map: Arc::new(params.clone()),
+            max_timesteps: 3,
+            dt: 3600.0,
+            options: Arc::new(options.clone()),
+            writer_tx,
+            progress_bar: Arc::new(ProgressBar::hidden()),
+            failed_nodes: Arc::new(AtomicUsize::new(0)),
+            capped_nodes: Arc::new(AtomicUsize::new(0)),
+            forcing_mismatches: Arc::new(Mutex::new(Vec::new())),
+            completed: Arc::new(Mutex::new(HashSet::from([1, 2]))),
+        };
+
+        // The worker fails rather than writing fill values and carrying on
+        let error = worker.route_node(1).unwrap_err();
+        assert!(error.to_string().contains("zero slope"), "{}", error);
+        let error = worker.route_node(3).unwrap_err();
+        assert!(
+            error.to_string().contains("no channel parameters"),
+            "{}",
+            error
+        );
+        assert!(writer_rx.try_recv().is_err());
+        assert_eq!(worker.failed_nodes.load(Ordering::Relaxed), 0);
+
+        let error = fixtures::route_in_memory(&topology, &params, 3, options).unwrap_err();
+        assert!(
+            error.to_string().contains("Routing stopped early"),
+            "{}",
+            error
+        );
+
+        // Without --strict the zero slope is floored and the missing reach written as fill
+        assert!(
+            fixtures::route_in_memory(&topology, &params, 3, RoutingOptions::default()).is_ok()
+        );
+    }
+
+    #[test]
+    fn reused_topology_routes_each_forcing_member_like_a_fresh_one() {
+        use crate::config::ColumnConfig;
+        use crate::network::{build_network_topology, load_channel_parameters, use_forcing_dir};
+
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let member_dir = |name: &str, scale: f32| {
+            let mut dir = PathBuf::new();
+            for id in 1..=4 {
+                let q_out: Vec<f32> = [0.5, 2.0, 1.0].iter().map(|q| q * scale).collect();
+                dir = fixtures::write_forcing(name, id, &q_out)
+                    .parent()
+                    .unwrap()
+                    .to_path_buf();
+            }
+            dir
+        };
+        let (first, second) = (member_dir("member_a", 1.0), member_dir("member_b", 3.0));
+
+        let mut topology = build_network_topology(&conn, &config, &first, false).unwrap();
+        let params = load_channel_parameters(&conn, &topology, &config).unwrap();
+        let route = |topology: &NetworkTopology| {
+            fixtures::route_in_memory(topology, &params, 3, RoutingOptions::default()).unwrap()
+        };
+
+        let first_results = route(&topology);
+        use_forcing_dir(&mut topology, &second).unwrap();
+        let reused = route(&topology);
+        let fresh = route(&build_network_topology(&conn, &config, &second, false).unwrap());
+        for id in 1..=4 {
+            let flow = reused.load(id).unwrap().flow_data;
+            assert_eq!(flow, fresh.load(id).unwrap().flow_data);
+            assert_ne!(flow, first_results.load(id).unwrap().flow_data);
+        }
+    }
+
+    #[test]
+    fn a_failed_worker_stops_routing_after_the_writer_drains() {
+        // 1 -> 2, and 3 on its own
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(2)), (2, None), (3, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![1.0, 2.0, 1.0]);
+        }
+        topology.build_upstream_connections();
+        // Poison reach 2's inflow buffer so the worker fails handing reach 1's flow on
+        let inflow = Arc::clone(&topology.nodes[&2].inflow_storage);
+        let _ = thread::spawn(move || {
+            let _guard = inflow.lock().unwrap();
+            panic!("poisoning the inflow buffer");
+        })
+        .join();
+        let params: HashMap<u64, ChannelParams> =
+            (1..=3).map(|id| (id, fixtures::default_params())).collect();
+
+        let timings_path = std::env::temp_dir().join("route_rs_failed_worker_timings.csv");
+        let _ = std::fs::remove_file(&timings_path);
+        let error = process_routing_parallel(
+            &topology,
+            &params,
+            3,
+            3600.0,
+            RoutingOptions::default(),
+            fixtures::empty_output(1..=3),
+            None,
+            None,
+            None,
+            Some(TimingReport::new(timings_path.clone())),
+            Arc::new(ProgressBar::hidden()),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("downstream buffer"), "{}", error);
+
+        // Reach 1's results were sent before the failure and still reached the writer,
+        // which finished its reports; reach 2 was never routed
+        let timings = std::fs::read_to_string(&timings_path).unwrap();
+        let routed: HashSet<&str> = timings
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert!(routed.contains("1"));
+        assert!(!routed.contains("2"));
+    }
+
+    #[test]
+    fn full_pipeline_output_is_readable_after_a_failed_worker() {
+        // 1 -> 2, with reach 2's inflow buffer poisoned as above
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(2)), (2, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![1.0, 2.0, 1.0]);
+        }
+        topology.build_upstream_connections();
+        let inflow = Arc::clone(&topology.nodes[&2].inflow_storage);
+        let _ = thread::spawn(move || {
+            let _guard = inflow.lock().unwrap();
+            panic!("poisoning the inflow buffer");
+        })
+        .join();
+        let params: HashMap<u64, ChannelParams> =
+            (1..=2).map(|id| (id, fixtures::default_params())).collect();
+
+        let path = std::env::temp_dir().join("route_rs_failed_worker.nc");
+        let _ = std::fs::remove_file(&path);
+        let error =
+            fixtures::route_to_netcdf(&path, &topology, &params, 3, RoutingOptions::default())
+                .unwrap_err();
+        assert!(error.to_string().contains("downstream buffer"), "{}", error);
+
+        // The file was synced before the error returned: reach 1 is complete and reach 2's
+        // slot still holds fill values
+        let file = netcdf::open(&path).unwrap();
+        let feature_ids: Vec<i64> = file.variable("feature_id").unwrap().get_values(..).unwrap();
+        assert_eq!(feature_ids[0], 1);
+        let flow = file.variable("flow").unwrap();
+        let routed: Vec<f32> = flow.get_values((0, ..)).unwrap();
+        assert!(routed.iter().all(|value| value.is_finite() && *value > 0.0));
+        let unrouted: Vec<f32> = flow.get_values((1, ..)).unwrap();
+        let fill = crate::config::OutputOptions::default().fill_value;
+        assert!(unrouted.iter().all(|&value| value == fill));
+    }
+
+    #[test]
+    fn flow_above_the_ceiling_stops_the_run_naming_the_reach() {
+        // 1 -> 2, and 3 on its own with far more lateral inflow
+        let mut topology = NetworkTopology::new();
+        for (id, downstream, inflow) in [(1, Some(2), 1.0), (2, None, 1.0), (3, None, 500.0)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![inflow; 3]);
+        }
+        topology.build_upstream_connections();
+        let params: HashMap<u64, ChannelParams> =
+            (1..=3).map(|id| (id, fixtures::default_params())).collect();
+        let options = RoutingOptions {
+            abort_above_flow: Some(100.0),
+            ..RoutingOptions::default()
+        };
+
+        let error = fixtures::route_in_memory(&topology, &params, 3, options).unwrap_err();
+        assert_eq!(error.downcast_ref::<RouteError>().unwrap().reaches(), &[3]);
+        assert!(error.to_string().contains("Node 3 outflow"), "{}", error);
+
+        let mut results = SimulationResults::new(4);
+        results.flow_data = vec![1.0, f32::NAN, 200.0];
+        let error = check_flow_ceiling(&results, 100.0, 300.0).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("at 300 s after the start (internal step 1)"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn routing_a_reach_before_its_upstream_reaches_is_an_error() {
+        // 1 -> 3 <- 2
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(3)), (2, Some(3)), (3, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![1.0, 2.0, 1.0]);
+        }
+        topology.build_upstream_connections();
+        let (writer_tx, _writer_rx) = mpsc::channel();
+        let worker = Worker {
+            topology: Arc::new(topology),
+            channel_params_map: Arc::new(
+                (1..=3).map(|id| (id, fixtures::default_params())).collect(),
+            ),
+            max_timesteps: 3,
+            dt: 3600.0,
+            options: Arc::new(RoutingOptions::default()),
+            writer_tx,
+            progress_bar: Arc::new(ProgressBar::hidden()),
+            failed_nodes: Arc::new(AtomicUsize::new(0)),
+            capped_nodes: Arc::new(AtomicUsize::new(0)),
+            forcing_mismatches: Arc::new(Mutex::new(Vec::new())),
+            completed: Arc::new(Mutex::new(HashSet::new())),
+        };
+
+        worker.route_node(1).unwrap();
+        let error = worker.route_node(3).unwrap_err();
+        match error.downcast_ref::<RouteError>() {
+            Some(RouteError::Routing { reaches, .. }) => assert_eq!(reaches, &[3, 2]),
+            other => panic!("expected a routing error, got {:?}", other),
+        }
+        assert!(error.to_string().contains("[2]"), "{}", error);
+
+        // Reach 3 wasn't routed, so once reach 2 is done it can be
+        worker.route_node(2).unwrap();
+        worker.route_node(3).unwrap();
+    }
+
+    #[test]
+    fn result_transform_changes_the_output_but_not_the_flow_passed_downstream() {
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(2)), (2, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow = Some(vec![4.0, 8.0, 2.0]);
+        }
+        topology.build_upstream_connections();
+        let params: HashMap<u64, ChannelParams> =
+            (1..=2).map(|id| (id, fixtures::default_params())).collect();
+        let route = |result_transform: Option<ResultTransform>| {
+            let options = RoutingOptions {
+                result_transform,
+                ..RoutingOptions::default()
+            };
+            fixtures::route_in_memory(&topology, &params, 3, options).unwrap()
+        };
+
+        let plain = route(None);
+        // m3/s to ft3/s
+        let converted = route(Some(ResultTransform::new(|results| {
+            results
+                .flow_data
+                .iter_mut()
+                .for_each(|flow| *flow *= 35.3147);
+        })));
+        for id in [1, 2] {
+            let expected: Vec<f32> = plain
+                .load(id)
+                .unwrap()
+                .flow_data
+                .iter()
+                .map(|flow| flow * 35.3147)
+                .collect();
+            assert_eq!(converted.load(id).unwrap().flow_data, expected);
+        }
+    }
+
+    #[test]
+    fn passthrough_reach_adds_lateral_to_upstream_inflow() {
+        let forcing = fixtures::write_forcing("passthrough", 2, &[0.36, 0.36, 0.36]);
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, Some(2), Some(1.0), PathBuf::from("unused.csv"));
+        topology.add_node(2, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+
+        let upstream = [10.0, 20.0, 5.0];
+        topology.nodes[&2]
+            .inflow_storage
+            .lock()
+            .unwrap()
+            .extend(upstream);
+
+        let mut options = RoutingOptions::default();
+        options.passthrough.insert(2);
+        let results = process_node_all_timesteps(
+            &2,
+            &topology,
+            &fixtures::default_params(),
+            upstream.len(),
+            3600.0,
+            &options,
+        )
+        .unwrap();
+
+        // 0.36 m/h over 1 km2 is 100 m3/s of lateral inflow
+        assert_eq!(results.flow_data, vec![110.0, 120.0, 105.0]);
+        assert!(results.depth_data.iter().all(|&depth| depth > 0.0));
+
+        // A reach weighted to half its catchment gets half the lateral inflow
+        topology.nodes.get_mut(&2).unwrap().lateral_weight = 0.5;
+        topology.nodes[&2]
+            .inflow_storage
+            .lock()
+            .unwrap()
+            .extend(upstream);
+        let results = process_node_all_timesteps(
+            &2,
+            &topology,
+            &fixtures::default_params(),
+            upstream.len(),
+            3600.0,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(results.flow_data, vec![60.0, 70.0, 55.0]);
+    }
+
+    #[test]
+    fn reach_type_selects_the_kernel() {
+        let forcing = fixtures::write_forcing("reach_type", 2, &[0.36, 0.36, 0.36]);
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, Some(2), Some(1.0), PathBuf::from("unused.csv"));
+        topology.add_node(2, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+
+        let upstream = [10.0, 20.0, 5.0];
+        let mut route = |reach_type| {
+            topology.nodes.get_mut(&2).unwrap().reach_type = reach_type;
+            topology.nodes[&2]
+                .inflow_storage
+                .lock()
+                .unwrap()
+                .extend(upstream);
+            process_node_all_timesteps(
+                &2,
+                &topology,
+                &fixtures::default_params(),
+                upstream.len(),
+                3600.0,
+                &RoutingOptions::default(),
+            )
+            .unwrap()
+            .flow_data
+        };
+
+        for reach_type in [
+            ReachType::Waterbody,
+            ReachType::Junction,
+            ReachType::Passthrough,
+        ] {
+            assert_eq!(route(reach_type), vec![110.0, 120.0, 105.0]);
+        }
+        // A channel attenuates the same inflow
+        assert_ne!(route(ReachType::Channel), vec![110.0, 120.0, 105.0]);
+    }
+
+    #[test]
+    fn velocities_above_the_threshold_are_flagged_with_the_reach_geometry() {
+        use crate::io::velocity::VelocityCheck;
+
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, None, Some(1.0), PathBuf::from("unused.csv"));
+        topology.nodes.get_mut(&1).unwrap().lateral_inflow = Some(vec![1.0, 400.0, 1.0]);
+        topology.build_upstream_connections();
+        let params = fixtures::default_params();
+        let route = |threshold: f32| {
+            let check = VelocityCheck::new(threshold);
+            let options = RoutingOptions {
+                velocity_check: Some(check.clone()),
+                ..RoutingOptions::default()
+            };
+            let results =
+                process_node_all_timesteps(&1, &topology, &params, 3, 3600.0, &options).unwrap();
+            (results.velocity_data, check.flagged().unwrap())
+        };
+
+        let (velocity, _) = route(f32::MAX);
+        let threshold = (velocity[0] + velocity[1]) / 2.0;
+        let (_, flagged) = route(threshold);
+        let fast: Vec<usize> = (0..3).filter(|&step| velocity[step] > threshold).collect();
+        assert!(!fast.is_empty() && fast.len() < 3);
+        assert_eq!(
+            flagged.iter().map(|step| step.timestep).collect::<Vec<_>>(),
+            fast
+        );
+        assert!(flagged.iter().all(|step| step.feature_id == 1
+            && step.s0 == params.s0
+            && step.dx == params.dx
+            && step.velocity > threshold));
+    }
+
+    #[test]
+    fn min_flow_floors_outflow_and_counts_where_it_applied() {
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, None, Some(1.0), PathBuf::from("unused.csv"));
+        topology.nodes.get_mut(&1).unwrap().lateral_inflow = Some(vec![0.0, 5.0, 0.0, 0.0]);
+        topology.build_upstream_connections();
+        let route = |min_flow: Option<f32>| {
+            let options = RoutingOptions {
+                min_flow,
+                ..RoutingOptions::default()
+            };
+            process_node_all_timesteps(
+                &1,
+                &topology,
+                &fixtures::default_params(),
+                4,
+                3600.0,
+                &options,
+            )
+            .unwrap()
+        };
+
+        let unfloored = route(None);
+        assert_eq!(unfloored.floored_steps, 0);
+        let floor = 0.5;
+        let floored = route(Some(floor));
+        let below = unfloored
+            .flow_data
+            .iter()
+            .filter(|&&flow| flow < floor)
+            .count();
+        assert!(below > 0);
+        assert_eq!(floored.floored_steps as usize, below);
+        assert!(floored.flow_data.iter().all(|&flow| flow >= floor));
+        // Depth and velocity follow the raised flow rather than the kernel's lower one
+        let (floored_depth, floored_velocity) = (floored.depth_data[0], floored.velocity_data[0]);
+        assert!(floored_depth > unfloored.depth_data[0]);
+        assert!(floored_velocity > unfloored.velocity_data[0]);
+        assert!(
+            floored
+                .flow_data
+                .iter()
+                .zip(floored.depth_data.iter().zip(&floored.velocity_data))
+                .filter(|(flow, _)| **flow == floor)
+                .all(|(_, (&depth, &velocity))| depth == floored_depth
+                    && velocity == floored_velocity)
+        );
+        // The raised flow is the reach's state for the next step, so later steps differ too
+        assert!(
+            floored.flow_data[0] == floor
+                && floored
+                    .flow_data
+                    .iter()
+                    .zip(&unfloored.flow_data)
+                    .any(|(a, b)| a != b && *b >= floor)
+        );
+    }
+
+    #[test]
+    fn recession_continues_from_the_forced_state_with_no_lateral_inflow() {
+        let mut topology = NetworkTopology::new();
+        let forcing = fixtures::write_forcing("recession", 1, &[0.36, 0.72, 0.36]);
+        topology.add_node(1, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+        let route = |recession_steps: usize| {
+            let options = RoutingOptions {
+                recession_steps,
+                ..RoutingOptions::default()
+            };
+            process_node_all_timesteps(
+                &1,
+                &topology,
+                &fixtures::default_params(),
+                3 + recession_steps,
+                3600.0,
+                &options,
+            )
+            .unwrap()
+            .flow_data
+        };
+
+        let forced = route(0);
+        let receding = route(4);
+        assert_eq!(receding.len(), 7);
+        assert_eq!(receding[..3], forced[..]);
+        // Stored water keeps draining after the lateral inflow stops
+        assert!(receding[3] > 0.0);
+        assert!(receding[3..].windows(2).all(|pair| pair[1] <= pair[0]));
+    }
+
+    #[test]
+    fn muskingum_series_are_recorded_only_on_request() {
+        let mut topology = NetworkTopology::new();
+        let forcing = fixtures::write_forcing("muskingum_series", 1, &[0.0, 0.36, 0.72]);
+        topology.add_node(1, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+        let route = |record_muskingum: bool| {
+            let options = RoutingOptions {
+                record_muskingum,
+                ..RoutingOptions::default()
+            };
+            process_node_all_timesteps(
+                &1,
+                &topology,
+                &fixtures::default_params(),
+                3,
+                3600.0,
+                &options,
+            )
+            .unwrap()
+        };
+
+        let plain = route(false);
+        assert!(plain.musk_x_data.is_empty() && plain.celerity_data.is_empty());
+
+        // No flow at the first step, then X within its clamped range and a positive celerity
+        let recorded = route(true);
+        assert_eq!(recorded.flow_data, plain.flow_data);
+        assert_eq!(recorded.musk_x_data[0], 0.0);
+        assert!(
+            recorded.musk_x_data[1..]
+                .iter()
+                .all(|x| (0.25..=0.5).contains(x))
+        );
+        assert!(recorded.celerity_data[1..].iter().all(|&ck| ck > 0.0));
+    }
+
+    #[test]
+    fn cumulative_volume_integrates_outflow_with_the_trapezoidal_rule() {
+        let mut topology = NetworkTopology::new();
+        let forcing = fixtures::write_forcing("cumulative_volume", 1, &[0.36, 0.72, 0.36]);
+        topology.add_node(1, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+        let options = RoutingOptions {
+            record_cumulative_volume: true,
+            ..RoutingOptions::default()
+        };
+        let results = process_node_all_timesteps(
+            &1,
+            &topology,
+            &fixtures::default_params(),
+            6,
+            1800.0,
+            &options,
+        )
+        .unwrap();
+
+        // The reach starts dry, so the first step adds half its outflow
+        let mut expected = Vec::new();
+        let mut volume = 0.0;
+        let mut previous = 0.0;
+        for &flow in &results.flow_data {
+            volume += (previous + flow) / 2.0 * 1800.0;
+            expected.push(volume);
+            previous = flow;
+        }
+        assert_eq!(results.cumulative_volume_data.len(), 6);
+        for (recorded, expected) in results.cumulative_volume_data.iter().zip(&expected) {
+            assert!(
+                (recorded - expected).abs() < 1e-3 * expected,
+                "{recorded} {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn exhausted_iteration_budget_falls_back_to_normal_flow() {
+        let q_out = [0.36, 0.72, 0.36, 0.36];
+        let route = |name: &str, max_reach_iterations: Option<u64>| {
+            let mut topology = NetworkTopology::new();
+            topology.add_node(1, None, Some(1.0), fixtures::write_forcing(name, 1, &q_out));
+            topology.build_upstream_connections();
+            let options = RoutingOptions {
+                max_reach_iterations,
+                ..RoutingOptions::default()
+            };
+            process_node_all_timesteps(
+                &1,
+                &topology,
+                &fixtures::default_params(),
+                q_out.len(),
+                3600.0,
+                &options,
+            )
+            .unwrap()
+        };
+
+        let unbounded = route("iteration_budget_unbounded", None);
+        assert!(!unbounded.iteration_capped);
+        assert!(unbounded.nonconverged.is_empty());
+
+        // The first timestep's solve spends the whole budget; the rest pass the inflow through
+        let capped = route("iteration_budget_capped", Some(1));
+        assert!(capped.iteration_capped);
+        assert_eq!(capped.nonconverged, vec![1, 2, 3]);
+        assert_eq!(capped.flow_data[0], unbounded.flow_data[0]);
+        assert_eq!(capped.flow_data[1..], [200.0, 100.0, 100.0]);
+    }
+
+    #[test]
+    fn skipping_first_forcing_row_shifts_lateral_inflow_one_step_earlier() {
+        let route = |name: &str, q_out: &[f32], skip_first_forcing: bool| {
+            let mut topology = NetworkTopology::new();
+            topology.add_node(1, None, Some(1.0), fixtures::write_forcing(name, 1, q_out));
+            topology.build_upstream_connections();
+            let options = RoutingOptions {
+                skip_first_forcing,
+                ..RoutingOptions::default()
+            };
+            process_node_all_timesteps(
+                &1,
+                &topology,
+                &fixtures::default_params(),
+                3,
+                3600.0,
+                &options,
+            )
+            .unwrap()
+            .flow_data
+        };
+
+        let with_state_row = route("skip_first_forcing", &[9.0, 1.0, 2.0, 3.0], true);
+        let fluxes_only = route("forcing_without_state_row", &[1.0, 2.0, 3.0], false);
+        assert_eq!(with_state_row, fluxes_only);
+    }
+
+    #[test]
+    fn spinup_cycles_carry_state_and_output_one_cycle() {
+        let route = |name: &str, q_out: &[f32], max_timesteps: usize, spinup_cycles: usize| {
+            let mut topology = NetworkTopology::new();
+            topology.add_node(1, None, Some(1.0), fixtures::write_forcing(name, 1, q_out));
+            topology.build_upstream_connections();
+            let options = RoutingOptions {
+                spinup_cycles,
+                ..RoutingOptions::default()
+            };
+            process_node_all_timesteps(
+                &1,
+                &topology,
+                &fixtures::default_params(),
+                max_timesteps,
+                3600.0,
+                &options,
+            )
+            .unwrap()
+        };
+
+        // One spin-up cycle is the same as routing the record twice and keeping the second half
+        let q_out = [0.36, 1.8, 0.72];
+        let twice: Vec<f32> = q_out.iter().chain(&q_out).copied().collect();
+        let spun_up = route("spinup_once", &q_out, 3, 1);
+        let repeated = route("spinup_twice", &twice, 6, 0);
+
+        assert_eq!(spun_up.flow_data.len(), q_out.len());
+        assert_eq!(spun_up.spinup_flow, repeated.flow_data[..3]);
+        assert_eq!(spun_up.flow_data, repeated.flow_data[3..]);
+        assert_eq!(spun_up.depth_data, repeated.depth_data[3..]);
+        // State carried across the boundary: the output cycle doesn't start from a dry channel
+        assert_ne!(
+            spun_up.flow_data,
+            route("spinup_cold", &q_out, 3, 0).flow_data
+        );
+    }
+
+    #[test]
+    fn headwater_fast_path_matches_buffered_path() {
+        let forcing = fixtures::write_forcing("headwater_fast_path", 1, &[0.5, 2.0, 1.0, 0.2]);
+        let max_timesteps = 8;
+
+        // Same reach routed once as a headwater and once with an upstream reach that contributed nothing
+        let mut headwater = NetworkTopology::new();
+        headwater.add_node(1, None, Some(1.0), forcing.clone());
+        headwater.build_upstream_connections();
+
+        let mut buffered = NetworkTopology::new();
+        buffered.add_node(0, Some(1), Some(1.0), PathBuf::from("unused.csv"));
+        buffered.add_node(1, None, Some(1.0), forcing);
+        buffered.build_upstream_connections();
+
+        let route = |topology: &NetworkTopology| {
+            process_node_all_timesteps(
+                &1,
+                topology,
+                &fixtures::default_params(),
+                max_timesteps,
+                1800.0,
+                &RoutingOptions::default(),
+            )
+            .unwrap()
+        };
+        let fast = route(&headwater);
+        let slow = route(&buffered);
+
+        assert!(fast.flow_data.iter().any(|&q| q > 0.0));
+        assert_eq!(fast.flow_data, slow.flow_data);
+        assert_eq!(fast.velocity_data, slow.velocity_data);
+        assert_eq!(fast.depth_data, slow.depth_data);
+    }
+
+    #[test]
+    fn full_pipeline_writes_routed_flow_to_netcdf() {
+        use crate::config::{ColumnConfig, OutputOptions};
+        use crate::io::netcdf::{
+            OutputChunk, init_netcdf_output, sorted_feature_index, verify_output,
+        };
+        use crate::network::{build_network_topology, load_channel_parameters};
+        use chrono::NaiveDate;
+
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let q_out = [0.5, 2.0, 3.0, 1.0, 0.5, 0.2];
+        let mut forcing_dir = PathBuf::new();
+        for id in 1..=4 {
+            let path = fixtures::write_forcing("full_pipeline", id, &q_out);
+            forcing_dir = path.parent().unwrap().to_path_buf();
+        }
+
+        let topology = build_network_topology(&conn, &config, &forcing_dir, false).unwrap();
+        let params = load_channel_parameters(&conn, &topology, &config).unwrap();
+
+        let dt = 1800.0;
+        let max_timesteps = q_out.len() * 2;
+        let timesteps: Vec<i64> = (0..q_out.len()).map(|step| (step * 3600) as i64).collect();
+        let reference_time = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+
+        let filename = forcing_dir.join("troute_output.nc");
+        // Half-hour routing written hourly
+        let options = OutputOptions {
+            output_stride: 2,
+            ..OutputOptions::default()
+        };
+        let file = init_netcdf_output(
+            filename.to_str().unwrap(),
+            timesteps.clone(),
+            &reference_time,
+            &options,
+        )
+        .unwrap();
+        let output = Arc::new(Mutex::new(NetCdfOutput {
+            chunks: vec![OutputChunk {
+                filename: filename.to_string_lossy().into_owned(),
+                first_step: 0,
+                num_steps: timesteps.len(),
+                start_time: reference_time,
+                end_time: reference_time,
+                file,
+            }],
+            feature_index: sorted_feature_index(topology.routing_order.iter().copied()),
+            options,
+            drainage_area: HashMap::new(),
+            member: 0,
+        }));
+
+        process_routing_parallel(
+            &topology,
+            &params,
+            max_timesteps,
+            dt,
+            RoutingOptions::default(),
+            Arc::clone(&output),
+            None,
+            None,
+            None,
+            None,
+            Arc::new(ProgressBar::hidden()),
+        )
+        .unwrap();
+        // Close the file before reading it back
+        drop(output);
+
+        // A headwater reach is unaffected by the rest of the network, so route it directly for reference
+        let expected = process_node_all_timesteps(
+            &1,
+            &topology,
+            &params[&1],
+            max_timesteps,
+            dt,
+            &RoutingOptions::default(),
+        )
+        .unwrap();
+        let expected = crate::io::netcdf::downsample(&expected.flow_data, 2);
+
+        let file = netcdf::open(&filename).unwrap();
+        let feature_ids: Vec<i64> = file.variable("feature_id").unwrap().get_values(..).unwrap();
+        assert_eq!(feature_ids, vec![1, 2, 3, 4]);
+
+        let flow = file.variable("flow").unwrap();
+        let headwater: Vec<f32> = flow.get_values((0, ..)).unwrap();
+        assert_eq!(headwater, expected);
+
+        // The outlet carries the flow of all three upstream reaches plus its own lateral inflow
+        let outlet: Vec<f32> = flow.get_values((3, ..)).unwrap();
+        assert!(outlet.iter().sum::<f32>() > headwater.iter().sum::<f32>());
+        drop(file);
+
+        let options = OutputOptions::default();
+        assert!(verify_output(&filename, 4, timesteps.len(), &options).is_empty());
+        let problems = verify_output(&filename, 5, timesteps.len(), &options);
+        assert_eq!(
+            problems,
+            vec!["feature_id dimension has length 4, expected 5"]
+        );
+    }
+
+    #[test]
+    fn full_pipeline_runs_write_byte_identical_output() {
+        use crate::config::ColumnConfig;
+        use crate::network::{build_network_topology, load_channel_parameters};
+
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let mut forcing_dir = PathBuf::new();
+        for id in 1..=4 {
+            let q_out = [0.5, 2.0 * id as f32, 3.0, 1.0];
+            let path = fixtures::write_forcing("identical_runs", id, &q_out);
+            forcing_dir = path.parent().unwrap().to_path_buf();
+        }
+        let topology = build_network_topology(&conn, &config, &forcing_dir, false).unwrap();
+        let params = load_channel_parameters(&conn, &topology, &config).unwrap();
+
+        // Reaches finish in a different order each run, but land in the same slots
+        let run = |name: &str| {
+            let path = forcing_dir.join(name);
+            fixtures::route_to_netcdf(&path, &topology, &params, 4, RoutingOptions::default())
+                .unwrap();
+            let file = netcdf::open(&path).unwrap();
+            let variable = |name: &str| -> Vec<u8> {
+                let values: Vec<f32> = file.variable(name).unwrap().get_values(..).unwrap();
+                values
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect()
+            };
+            let feature_ids: Vec<i64> =
+                file.variable("feature_id").unwrap().get_values(..).unwrap();
+            (
+                feature_ids,
+                variable("flow"),
+                variable("velocity"),
+                variable("depth"),
+            )
+        };
+        let first = run("first.nc");
+        assert_eq!(first.0, vec![1, 2, 3, 4]);
+        assert_eq!(first, run("second.nc"));
+    }
+
+    #[test]
+    fn full_pipeline_resumed_output_matches_an_uninterrupted_run() {
+        use crate::config::ColumnConfig;
+        use crate::network::{build_network_topology, load_channel_parameters};
+
+        let config = ColumnConfig::new();
+        let conn = fixtures::synthetic_network(&config);
+        let mut forcing_dir = PathBuf::new();
+        for id in 1..=4 {
+            let q_out = [0.5, 2.0 * id as f32, 3.0, 1.0];
+            let path = fixtures::write_forcing("resumed_run", id, &q_out);
+            forcing_dir = path.parent().unwrap().to_path_buf();
+        }
+        let topology = build_network_topology(&conn, &config, &forcing_dir, false).unwrap();
+        let params = load_channel_parameters(&conn, &topology, &config).unwrap();
+        let read = |path: &Path| -> (Vec<i64>, Vec<f32>, Vec<f32>) {
+            let file = netcdf::open(path).unwrap();
+            (
+                file.variable("feature_id").unwrap().get_values(..).unwrap(),
+                file.variable("flow").unwrap().get_values(..).unwrap(),
+                file.variable("depth").unwrap().get_values(..).unwrap(),
+            )
+        };
+
+        let complete = forcing_dir.join("complete.nc");
+        fixtures::route_to_netcdf(&complete, &topology, &params, 4, RoutingOptions::default())
+            .unwrap();
+
+        // A run stopped before the outlet was written
+        let interrupted = forcing_dir.join("interrupted.nc");
+        let options = RoutingOptions {
+            already_written: HashSet::from([4]),
+            ..RoutingOptions::default()
+        };
+        fixtures::route_to_netcdf(&interrupted, &topology, &params, 4, options).unwrap();
+        assert_ne!(read(&interrupted).0, vec![1, 2, 3, 4]);
+
+        // Resuming with different inputs or options is refused and leaves the file alone
+        let error =
+            fixtures::resume_netcdf(&interrupted, &topology, &params, 4, "fedcba9876543210")
+                .unwrap_err();
+        assert!(error.to_string().contains("different inputs or options"));
+
+        fixtures::resume_netcdf(
+            &interrupted,
+            &topology,
+            &params,
+            4,
+            fixtures::FIXTURE_INPUT_HASH,
+        )
+        .unwrap();
+        assert_eq!(read(&interrupted), read(&complete));
+    }
+
+    #[test]
+    fn short_forcing_is_padded_instead_of_dropping_steps() {
+        // 3 hourly forcing values can't cover 8 half-hour steps; the 4th hour gets zero lateral inflow
+        let forcing = fixtures::write_forcing("short_forcing", 1, &[1.0, 1.0, 1.0]);
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, None, Some(1.0), forcing);
+        topology.build_upstream_connections();
+
+        let results = process_node_all_timesteps(
+            &1,
+            &topology,
+            &fixtures::default_params(),
+            8,
+            1800.0,
+            &RoutingOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(results.flow_data.len(), 8);
+        assert_eq!(results.forcing_mismatch, Some((3, 4)));
+        assert!(results.flow_data[5] > 0.0);
+        // Once lateral inflow stops the reach drains
+        assert!(results.flow_data[7] < results.flow_data[5]);
+    }
+
+    #[test]
+    fn resume_routes_written_reaches_that_feed_missing_ones() {
+        // 1, 2 -> 3 -> 4, with 4 missing from the existing output
+        let mut topology = NetworkTopology::new();
+        topology.add_node(1, Some(3), Some(1.0), PathBuf::new());
+        topology.add_node(2, Some(3), Some(1.0), PathBuf::new());
+        topology.add_node(3, Some(4), Some(1.0), PathBuf::new());
+        topology.add_node(4, None, Some(1.0), PathBuf::new());
+        topology.add_node(5, None, Some(1.0), PathBuf::new());
+        topology.build_upstream_connections();
+        topology.topological_sort().unwrap();
+
+        let written = HashSet::from([1, 2, 3, 5]);
+        assert_eq!(resume_skip_set(&topology, &written), HashSet::from([5]));
+
+        let written = HashSet::from([1, 2, 3, 4, 5]);
+        assert_eq!(resume_skip_set(&topology, &written).len(), 5);
+
+        // An unwritten headwater only needs itself
+        let written = HashSet::from([2, 3, 4, 5]);
+        assert_eq!(
+            resume_skip_set(&topology, &written),
+            HashSet::from([2, 3, 4, 5])
+        );
+    }
+
+    #[test]
+    fn many_small_upstream_flows_are_not_lost_at_a_large_confluence() {
+        // Below half an f32 ulp at 1e6, so each addition would round away in f32
+        let mut buffer = VecDeque::new();
+        accumulate_inflow(&mut buffer, &[1.0e6, 1.0e6], 0);
+        for _ in 0..1000 {
+            accumulate_inflow(&mut buffer, &[0.01, 0.01], 0);
+        }
+
+        let mut f32_total = 1.0e6_f32;
+        for _ in 0..1000 {
+            f32_total += 0.01;
+        }
+        assert_eq!(f32_total, 1.0e6);
+        assert_eq!(buffer.len(), 2);
+        assert!(buffer.iter().all(|&total| total as f32 == 1_000_010.0));
+    }
+
+    #[test]
+    fn lagged_confluence_delays_each_tributary_by_its_travel_time() {
+        let mut buffer = VecDeque::new();
+        accumulate_inflow(&mut buffer, &[1.0, 5.0, 2.0, 1.0], 0);
+        accumulate_inflow(&mut buffer, &[1.0, 5.0, 2.0, 1.0], 2);
+        assert_eq!(buffer, [2.0, 6.0, 3.0, 6.0]);
+
+        let mut results = SimulationResults::new(1);
+        assert_eq!(travel_lag(&results, 300.0), 0);
+        // Mean K of 800 s is closest to 3 timesteps of 300 s
+        results.travel_time = 2400.0;
+        results.flowing_steps = 3;
+        assert_eq!(travel_lag(&results, 300.0), 3);
+    }
+
+    #[test]
+    fn short_reaches_add_no_confluence_lag() {
+        // 1 -> 3 <- 2, where 1 and 2 are 10 m long: far less than a timestep
+        let mut topology = NetworkTopology::new();
+        for (id, downstream) in [(1, Some(3)), (2, Some(3)), (3, None)] {
+            topology.add_node(id, downstream, Some(1.0), PathBuf::from("unused.csv"));
+            topology.nodes.get_mut(&id).unwrap().lateral_inflow =
+                Some(vec![1.0, 8.0, 20.0, 6.0, 2.0, 1.0]);
+        }
+        topology.build_upstream_connections();
+        let mut short = fixtures::default_params();
+        short.dx = 10.0;
+        let params: HashMap<u64, ChannelParams> = HashMap::from([
+            (1, short.clone()),
+            (2, short.clone()),
+            (3, fixtures::default_params()),
+        ]);
+
+        let headwater =
+            process_node_all_timesteps(&1, &topology, &short, 6, 300.0, &RoutingOptions::default())
+                .unwrap();
+        assert!(headwater.flowing_steps > 0);
+        assert_eq!(travel_lag(&headwater, 300.0), 0);
+
+        let route = |confluence: Confluence| {
+            let options = RoutingOptions {
+                confluence,
+                ..RoutingOptions::default()
+            };
+            let cache = fixtures::route_in_memory(&topology, &params, 6, options).unwrap();
+            cache.load(3).unwrap().flow_data
+        };
+        assert_eq!(route(Confluence::Lagged), route(Confluence::Sum));
+    }
+
+    #[test]
+    fn writes_are_retried_until_the_attempts_run_out() {
+        let flaky = |failures: u32| {
+            let mut calls = 0;
+            move || {
+                calls += 1;
+                if calls <= failures {
+                    Err(anyhow::anyhow!("transient failure {}", calls))
+                } else {
+                    Ok(())
+                }
+            }
+        };
+
+        assert!(with_retries(0, 1, flaky(0)).is_ok());
+        assert!(with_retries(2, 1, flaky(2)).is_ok());
+        let error = with_retries(1, 1, flaky(2)).unwrap_err();
+        assert_eq!(error.to_string(), "transient failure 2");
+    }
+}